@@ -174,6 +174,8 @@ pub fn criterion_benchmark(c: &mut Criterion<Instructions>) {
     // MemoryManager benchmarks
     bench_function(c, *BENCHMARK_CANISTER, "memory_manager_baseline");
     bench_function(c, *BENCHMARK_CANISTER, "memory_manager_overhead");
+    bench_function(c, *BENCHMARK_CANISTER, "memory_manager_contiguous_reads");
+    bench_function(c, *BENCHMARK_CANISTER, "memory_manager_small_reads");
 
     // BTree benchmarks
     bench_function(c, *BENCHMARK_CANISTER, "btreemap_insert_blob_4_1024");
@@ -212,6 +214,12 @@ pub fn criterion_benchmark(c: &mut Criterion<Instructions>) {
     bench_function(c, *BENCHMARK_CANISTER, "btreemap_remove_u64_blob_8");
     bench_function(c, *BENCHMARK_CANISTER, "btreemap_remove_blob_8_u64");
 
+    bench_function(c, *BENCHMARK_CANISTER, "btreemap_update_u64_u64");
+    bench_function(c, *BENCHMARK_CANISTER, "btreemap_update_u64_blob_8");
+
+    bench_function(c, *BENCHMARK_CANISTER, "btreemap_remove_range_u64_u64");
+    bench_function(c, *BENCHMARK_CANISTER, "btreemap_remove_range_u64_blob_8");
+
     // Vec benchmarks
     bench_function(c, *BENCHMARK_CANISTER, "vec_insert_blob_4");
     bench_function(c, *BENCHMARK_CANISTER, "vec_insert_blob_8");
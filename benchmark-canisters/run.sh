@@ -15,6 +15,8 @@ dfx deploy --no-wallet benchmarks
 # MemoryManager benchmarks
 dfx canister call benchmarks memory_manager_baseline --query
 dfx canister call benchmarks memory_manager_overhead --query
+dfx canister call benchmarks memory_manager_contiguous_reads --query
+dfx canister call benchmarks memory_manager_small_reads --query
 
 # BTreeMap benchmarks
 dfx canister call benchmarks btreemap_insert_blob_4_1024 --query
@@ -50,6 +52,38 @@ dfx canister call benchmarks btreemap_remove_blob_256_1024
 # This test goes over the instructions limit, so we can't run it currently.
 #dfx canister call benchmarks btreemap_remove_blob_512_1024
 
+# BTreeMap benchmarks with different branching factors
+dfx canister call benchmarks btreemap_insert_u64_u64_branching_factor_4 --query
+dfx canister call benchmarks btreemap_insert_u64_u64_branching_factor_16 --query
+dfx canister call benchmarks btreemap_insert_u64_u64_branching_factor_64 --query
+dfx canister call benchmarks btreemap_insert_u64_u64_branching_factor_256 --query
+dfx canister call benchmarks btreemap_insert_blob_64_1024_branching_factor_4 --query
+dfx canister call benchmarks btreemap_insert_blob_64_1024_branching_factor_16 --query
+
+dfx canister call benchmarks btreemap_get_u64_u64_branching_factor_4 --query
+dfx canister call benchmarks btreemap_get_u64_u64_branching_factor_16 --query
+dfx canister call benchmarks btreemap_get_u64_u64_branching_factor_64 --query
+dfx canister call benchmarks btreemap_get_u64_u64_branching_factor_256 --query
+dfx canister call benchmarks btreemap_get_blob_64_1024_branching_factor_4 --query
+dfx canister call benchmarks btreemap_get_blob_64_1024_branching_factor_16 --query
+
+dfx canister call benchmarks btreemap_remove_u64_u64_branching_factor_4
+dfx canister call benchmarks btreemap_remove_u64_u64_branching_factor_16
+dfx canister call benchmarks btreemap_remove_u64_u64_branching_factor_64
+dfx canister call benchmarks btreemap_remove_u64_u64_branching_factor_256
+
+# BTreeMap benchmarks with a node cache
+dfx canister call benchmarks btreemap_get_u64_u64 --query
+dfx canister call benchmarks btreemap_get_u64_u64_node_cache_16 --query
+dfx canister call benchmarks btreemap_get_u64_u64_node_cache_256 --query
+dfx canister call benchmarks btreemap_get_blob_32_1024 --query
+dfx canister call benchmarks btreemap_get_blob_32_1024_node_cache_16 --query
+dfx canister call benchmarks btreemap_get_blob_32_1024_node_cache_256 --query
+dfx canister call benchmarks btreemap_range_u64_u64 --query
+dfx canister call benchmarks btreemap_range_u64_u64_node_cache_16 --query
+dfx canister call benchmarks btreemap_range_u64_blob_1024 --query
+dfx canister call benchmarks btreemap_range_u64_blob_1024_node_cache_16 --query
+
 dfx canister call benchmarks vec_insert_blob_4
 dfx canister call benchmarks vec_insert_blob_8
 dfx canister call benchmarks vec_insert_blob_16
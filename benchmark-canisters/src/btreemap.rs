@@ -1,6 +1,8 @@
 use crate::{count_instructions, Random};
 use ic_cdk_macros::query;
-use ic_stable_structures::{storable::Blob, BTreeMap, DefaultMemoryImpl, Storable};
+use ic_stable_structures::{
+    btreemap::Config, storable::Blob, BTreeMap, DefaultMemoryImpl, Storable,
+};
 use tiny_rng::{Rand, Rng};
 
 #[query]
@@ -98,6 +100,37 @@ pub fn btreemap_insert_blob_8_u64() -> u64 {
     insert_helper::<Blob<8>, u64>()
 }
 
+/// Benchmarks inserting keys into BTreeMaps with different branching factors.
+#[query]
+pub fn btreemap_insert_u64_u64_branching_factor_4() -> u64 {
+    insert_helper_with_config::<u64, u64>(Config::default().branching_factor(4))
+}
+
+#[query]
+pub fn btreemap_insert_u64_u64_branching_factor_16() -> u64 {
+    insert_helper_with_config::<u64, u64>(Config::default().branching_factor(16))
+}
+
+#[query]
+pub fn btreemap_insert_u64_u64_branching_factor_64() -> u64 {
+    insert_helper_with_config::<u64, u64>(Config::default().branching_factor(64))
+}
+
+#[query]
+pub fn btreemap_insert_u64_u64_branching_factor_256() -> u64 {
+    insert_helper_with_config::<u64, u64>(Config::default().branching_factor(256))
+}
+
+#[query]
+pub fn btreemap_insert_blob_64_1024_branching_factor_4() -> u64 {
+    insert_helper_with_config::<Blob<64>, Blob<1024>>(Config::default().branching_factor(4))
+}
+
+#[query]
+pub fn btreemap_insert_blob_64_1024_branching_factor_16() -> u64 {
+    insert_helper_with_config::<Blob<64>, Blob<1024>>(Config::default().branching_factor(16))
+}
+
 /// Benchmarks removing keys from a BTreeMap.
 #[query]
 pub fn btreemap_remove_blob_4_1024() -> u64 {
@@ -154,6 +187,27 @@ pub fn btreemap_remove_blob_8_u64() -> u64 {
     remove_helper::<Blob<8>, u64>()
 }
 
+/// Benchmarks removing keys from BTreeMaps with different branching factors.
+#[query]
+pub fn btreemap_remove_u64_u64_branching_factor_4() -> u64 {
+    remove_helper_with_config::<u64, u64>(Config::default().branching_factor(4))
+}
+
+#[query]
+pub fn btreemap_remove_u64_u64_branching_factor_16() -> u64 {
+    remove_helper_with_config::<u64, u64>(Config::default().branching_factor(16))
+}
+
+#[query]
+pub fn btreemap_remove_u64_u64_branching_factor_64() -> u64 {
+    remove_helper_with_config::<u64, u64>(Config::default().branching_factor(64))
+}
+
+#[query]
+pub fn btreemap_remove_u64_u64_branching_factor_256() -> u64 {
+    remove_helper_with_config::<u64, u64>(Config::default().branching_factor(256))
+}
+
 /// Benchmarks getting keys from a BTreeMap.
 #[query]
 pub fn btreemap_get_blob_4_1024() -> u64 {
@@ -210,6 +264,101 @@ pub fn btreemap_get_blob_8_u64() -> u64 {
     get_helper::<Blob<8>, u64>()
 }
 
+/// Benchmarks getting keys from BTreeMaps with different branching factors.
+#[query]
+pub fn btreemap_get_u64_u64_branching_factor_4() -> u64 {
+    get_helper_with_config::<u64, u64>(Config::default().branching_factor(4))
+}
+
+#[query]
+pub fn btreemap_get_u64_u64_branching_factor_16() -> u64 {
+    get_helper_with_config::<u64, u64>(Config::default().branching_factor(16))
+}
+
+#[query]
+pub fn btreemap_get_u64_u64_branching_factor_64() -> u64 {
+    get_helper_with_config::<u64, u64>(Config::default().branching_factor(64))
+}
+
+#[query]
+pub fn btreemap_get_u64_u64_branching_factor_256() -> u64 {
+    get_helper_with_config::<u64, u64>(Config::default().branching_factor(256))
+}
+
+#[query]
+pub fn btreemap_get_blob_64_1024_branching_factor_4() -> u64 {
+    get_helper_with_config::<Blob<64>, Blob<1024>>(Config::default().branching_factor(4))
+}
+
+#[query]
+pub fn btreemap_get_blob_64_1024_branching_factor_16() -> u64 {
+    get_helper_with_config::<Blob<64>, Blob<1024>>(Config::default().branching_factor(16))
+}
+
+/// Benchmarks getting keys from a BTreeMap with a node cache of different sizes.
+#[query]
+pub fn btreemap_get_u64_u64_node_cache_16() -> u64 {
+    get_with_node_cache_helper::<u64, u64>(16)
+}
+
+#[query]
+pub fn btreemap_get_u64_u64_node_cache_256() -> u64 {
+    get_with_node_cache_helper::<u64, u64>(256)
+}
+
+#[query]
+pub fn btreemap_get_blob_32_1024_node_cache_16() -> u64 {
+    get_with_node_cache_helper::<Blob<32>, Blob<1024>>(16)
+}
+
+#[query]
+pub fn btreemap_get_blob_32_1024_node_cache_256() -> u64 {
+    get_with_node_cache_helper::<Blob<32>, Blob<1024>>(256)
+}
+
+/// Benchmarks iterating over ranges of a BTreeMap, with and without a node cache.
+#[query]
+pub fn btreemap_range_u64_u64() -> u64 {
+    range_helper::<u64>(0)
+}
+
+#[query]
+pub fn btreemap_range_u64_u64_node_cache_16() -> u64 {
+    range_helper::<u64>(16)
+}
+
+#[query]
+pub fn btreemap_range_u64_blob_1024() -> u64 {
+    range_helper::<Blob<1024>>(0)
+}
+
+#[query]
+pub fn btreemap_range_u64_blob_1024_node_cache_16() -> u64 {
+    range_helper::<Blob<1024>>(16)
+}
+
+/// Benchmarks updating the values of existing keys in a BTreeMap.
+#[query]
+pub fn btreemap_update_u64_u64() -> u64 {
+    update_helper::<u64, u64>()
+}
+
+#[query]
+pub fn btreemap_update_u64_blob_8() -> u64 {
+    update_helper::<u64, Blob<8>>()
+}
+
+/// Benchmarks removing the oldest half of a BTreeMap's entries, as in a TTL-style cleanup.
+#[query]
+pub fn btreemap_remove_range_u64_u64() -> u64 {
+    remove_range_helper::<u64>()
+}
+
+#[query]
+pub fn btreemap_remove_range_u64_blob_8() -> u64 {
+    remove_range_helper::<Blob<8>>()
+}
+
 // Profiles inserting a large number of random blobs into a btreemap.
 fn insert_blob_helper<const K: usize, const V: usize>() -> u64 {
     insert_helper::<Blob<K>, Blob<V>>()
@@ -217,7 +366,14 @@ fn insert_blob_helper<const K: usize, const V: usize>() -> u64 {
 
 // Profiles inserting a large number of random blobs into a btreemap.
 fn insert_helper<K: Clone + Ord + Storable + Random, V: Storable + Random>() -> u64 {
-    let mut btree: BTreeMap<K, V, _> = BTreeMap::new(DefaultMemoryImpl::default());
+    insert_helper_with_config::<K, V>(Config::default())
+}
+
+fn insert_helper_with_config<K: Clone + Ord + Storable + Random, V: Storable + Random>(
+    config: Config,
+) -> u64 {
+    let mut btree: BTreeMap<K, V, _> =
+        BTreeMap::new_with_config(DefaultMemoryImpl::default(), config);
     let num_keys = 10_000;
     let mut rng = Rng::from_seed(0);
     let mut random_keys = Vec::with_capacity(num_keys);
@@ -242,7 +398,14 @@ fn get_blob_helper<const K: usize, const V: usize>() -> u64 {
 }
 
 fn get_helper<K: Clone + Ord + Storable + Random, V: Storable + Random>() -> u64 {
-    let mut btree: BTreeMap<K, V, _> = BTreeMap::new(DefaultMemoryImpl::default());
+    get_helper_with_config::<K, V>(Config::default())
+}
+
+fn get_helper_with_config<K: Clone + Ord + Storable + Random, V: Storable + Random>(
+    config: Config,
+) -> u64 {
+    let mut btree: BTreeMap<K, V, _> =
+        BTreeMap::new_with_config(DefaultMemoryImpl::default(), config);
     let num_keys = 10_000;
     let mut rng = Rng::from_seed(0);
     let mut random_keys = Vec::with_capacity(num_keys);
@@ -266,13 +429,64 @@ fn get_helper<K: Clone + Ord + Storable + Random, V: Storable + Random>() -> u64
     })
 }
 
+// Profiles getting a large number of random keys from a btreemap with a node cache.
+fn get_with_node_cache_helper<K: Clone + Ord + Storable + Random, V: Storable + Random>(
+    node_cache_size: usize,
+) -> u64 {
+    let mut btree: BTreeMap<K, V, _> = BTreeMap::new(DefaultMemoryImpl::default());
+    btree.set_node_cache_size(node_cache_size);
+    let num_keys = 10_000;
+    let mut rng = Rng::from_seed(0);
+    let mut random_keys = Vec::with_capacity(num_keys);
+
+    for _ in 0..num_keys {
+        let key = K::random(&mut rng);
+        btree.insert(key.clone(), V::random(&mut rng));
+        random_keys.push(key);
+    }
+
+    count_instructions(|| {
+        for k in random_keys.into_iter() {
+            btree.get(&k).unwrap();
+        }
+    })
+}
+
+// Inserts a large number of entries with sequential keys into a btreemap, then profiles
+// iterating over many short ranges of them, as when paginating.
+fn range_helper<V: Storable + Random>(node_cache_size: usize) -> u64 {
+    let mut btree: BTreeMap<u64, V, _> = BTreeMap::new(DefaultMemoryImpl::default());
+    btree.set_node_cache_size(node_cache_size);
+    let num_keys = 10_000;
+    let mut rng = Rng::from_seed(0);
+
+    for k in 0..num_keys {
+        btree.insert(k, V::random(&mut rng));
+    }
+
+    count_instructions(|| {
+        for start in (0..num_keys).step_by(10) {
+            for entry in btree.range(start..start + 10) {
+                std::hint::black_box(entry);
+            }
+        }
+    })
+}
+
 // Inserts a large number of random blobs into a btreemap, then profiles removing them.
 fn remove_blob_helper<const K: usize, const V: usize>() -> u64 {
     remove_helper::<Blob<K>, Blob<V>>()
 }
 
 fn remove_helper<K: Clone + Ord + Storable + Random, V: Storable + Random>() -> u64 {
-    let mut btree: BTreeMap<K, V, _> = BTreeMap::new(DefaultMemoryImpl::default());
+    remove_helper_with_config::<K, V>(Config::default())
+}
+
+fn remove_helper_with_config<K: Clone + Ord + Storable + Random, V: Storable + Random>(
+    config: Config,
+) -> u64 {
+    let mut btree: BTreeMap<K, V, _> =
+        BTreeMap::new_with_config(DefaultMemoryImpl::default(), config);
     let num_keys = 10_000;
     let mut rng = Rng::from_seed(0);
     let mut random_keys = Vec::with_capacity(num_keys);
@@ -295,3 +509,48 @@ fn remove_helper<K: Clone + Ord + Storable + Random, V: Storable + Random>() ->
         }
     })
 }
+
+// Inserts a large number of random entries into a btreemap, then profiles updating
+// their values in place.
+fn update_helper<K: Clone + Ord + Storable + Random, V: Storable + Random>() -> u64 {
+    let mut btree: BTreeMap<K, V, _> = BTreeMap::new(DefaultMemoryImpl::default());
+    let num_keys = 10_000;
+    let mut rng = Rng::from_seed(0);
+    let mut random_keys = Vec::with_capacity(num_keys);
+    let mut random_values = Vec::with_capacity(num_keys);
+
+    for _ in 0..num_keys {
+        random_keys.push(K::random(&mut rng));
+        random_values.push(V::random(&mut rng));
+    }
+
+    // Insert the keys into the btree.
+    for (k, v) in random_keys.iter().zip(random_values.into_iter()) {
+        btree.insert(k.clone(), v);
+    }
+
+    let new_values: Vec<_> = (0..num_keys).map(|_| V::random(&mut rng)).collect();
+
+    count_instructions(|| {
+        // Update the values of all the keys.
+        for (k, v) in random_keys.iter().zip(new_values.into_iter()) {
+            btree.update(k, |value| *value = v);
+        }
+    })
+}
+
+// Inserts a large number of entries with sequential keys (e.g. timestamps) into a
+// btreemap, then profiles removing the first half of them as a range.
+fn remove_range_helper<V: Storable + Random>() -> u64 {
+    let mut btree: BTreeMap<u64, V, _> = BTreeMap::new(DefaultMemoryImpl::default());
+    let num_keys = 10_000;
+    let mut rng = Rng::from_seed(0);
+
+    for k in 0..num_keys {
+        btree.insert(k, V::random(&mut rng));
+    }
+
+    count_instructions(|| {
+        btree.remove_range(..num_keys / 2);
+    })
+}
@@ -55,3 +55,48 @@ pub fn memory_manager_overhead() -> u64 {
         }
     })
 }
+
+/// Benchmarks reading a 500MiB memory of the `MemoryManager` whose buckets are adjacent in the
+/// underlying stable memory, so that they are read with a single call.
+#[ic_cdk_macros::query]
+pub fn memory_manager_contiguous_reads() -> u64 {
+    // A buffer of 500MiB.
+    let buf_size = 500 * MB;
+    let mut buf = vec![0; buf_size];
+
+    let mem_mgr = MemoryManager::init(DefaultMemoryImpl::default());
+    let memory = mem_mgr.get(MemoryId::new(0));
+    memory.grow((500 * MB_IN_PAGES) as u64);
+    memory.write(0, &buf);
+
+    crate::count_instructions(|| {
+        for _ in 0..5 {
+            memory.read(0, &mut buf);
+        }
+    })
+}
+
+/// Benchmarks the overhead of the `MemoryManager` on small reads of interleaved memories.
+#[ic_cdk_macros::query]
+pub fn memory_manager_small_reads() -> u64 {
+    let num_memories = 5;
+    let num_chunks = 10;
+    let mem_mgr = MemoryManager::init(DefaultMemoryImpl::default());
+    let memories: Vec<_> = (0..num_memories)
+        .map(|i| mem_mgr.get(MemoryId::new(i)))
+        .collect();
+    for _ in 0..num_chunks {
+        for memory in memories.iter() {
+            memory.grow(MB_IN_PAGES as u64);
+        }
+    }
+
+    let mut buf = [0; 8];
+    let memory_size = (num_chunks * MB) as u64;
+    crate::count_instructions(|| {
+        for i in 0..100_000 {
+            let offset = (i * 4099) % (memory_size - buf.len() as u64);
+            memories[i as usize % memories.len()].read(offset, &mut buf);
+        }
+    })
+}
@@ -24,19 +24,93 @@
 //! ... free memory for nodes
 //! ----------------------------------------
 //! ```
+//!
+//! # V2 layout
+//!
+//! ```text
+//! ---------------------------------------- <- Address 0
+//! Magic "BTR"                 ↕ 3 bytes
+//! ----------------------------------------
+//! Layout version              ↕ 1 byte
+//! ----------------------------------------
+//! Max key size or page size   ↕ 4 bytes
+//! ----------------------------------------
+//! Max value size or marker    ↕ 4 bytes
+//! ----------------------------------------
+//! Root node address           ↕ 8 bytes
+//! ----------------------------------------
+//! Length (number of elements) ↕ 8 bytes
+//! ----------------------------------------
+//! Migration cursor            ↕ 8 bytes
+//! ----------------------------------------
+//! Cleared root node address   ↕ 8 bytes
+//! ----------------------------------------
+//! Flags                       ↕ 1 byte
+//! ----------------------------------------
+//! Branching factor            ↕ 2 bytes
+//! ---------------------------------------- <- Address 47 (PACKED_HEADER_SIZE_V2)
+//! Reserved space              ↕ 5 bytes
+//! ---------------------------------------- <- Address 52 (ALLOCATOR_OFFSET)
+//! Allocator
+//! ----------------------------------------
+//! ... free memory for nodes
+//! ----------------------------------------
+//! ```
+//!
+//! A derived page size is stored as the max key size and max value size, as in V1.
+//! A fixed page size is stored as the page size followed by `PAGE_SIZE_VALUE_MARKER`.
+//!
+//! A branching factor of zero indicates the default branching factor, which is also
+//! the branching factor of all V1 maps and of the V2 maps migrated from them.
+//!
+//! # Migrating from V1 to V2
+//!
+//! Loading a V1 map converts its header to V2, with a page size derived from the
+//! max key and value sizes of the V1 map. The header is saved before the first node
+//! is written, so loading a map that is only read leaves its memory untouched. The
+//! nodes themselves are migrated lazily: V1 nodes are rewritten as V2 nodes whenever
+//! they are saved. The migration can be completed in bounded steps using
+//! [`BTreeMap::migrate_step`], which scans the nodes starting from the migration
+//! cursor. A NULL migration cursor indicates that there are no V1 nodes left in the
+//! map, in which case nodes are loaded without checking their layout.
+//!
+//! # Clearing in steps
+//!
+//! [`BTreeMap::clear_step`] empties the map by detaching its tree, whose root is stored
+//! as the cleared root node address. The nodes of the detached tree are then deallocated
+//! in bounded steps. A NULL cleared root indicates that there are no nodes left to
+//! deallocate.
 mod allocator;
+mod bulk;
+mod clear;
+mod config;
+mod counts;
+mod entry;
 mod iter;
 mod node;
+mod node_cache;
+mod pagination;
+pub(crate) mod prefix;
+mod remove_range;
+mod split;
 use crate::{
-    storable::max_size,
-    types::{Address, NULL},
+    storable::{max_size, Bound as StorableBound},
+    types::{Address, Bytes, NULL},
     Memory, Storable,
 };
-use allocator::Allocator;
+use allocator::{Allocator, ChunkStatus};
+pub use bulk::BulkLoadError;
+pub use config::Config;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
 pub use iter::Iter;
 use iter::{Cursor, Index};
-use node::{DerivedPageSize, Entry, Node, NodeType, Version};
+use node::{DerivedPageSize, Entry as NodeEntry, Node, NodeType, PageSize, Version, B};
+use node_cache::NodeCache;
+pub use pagination::PageCursor;
+pub use prefix::KeyPrefix;
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::marker::PhantomData;
 use std::ops::{Bound, RangeBounds};
 
@@ -45,11 +119,25 @@ mod proptests;
 
 const MAGIC: &[u8; 3] = b"BTR";
 const LAYOUT_VERSION: u8 = 1;
+const LAYOUT_VERSION_2: u8 = 2;
 /// The sum of all the header fields, i.e. size of a packed header.
 const PACKED_HEADER_SIZE: usize = 28;
+/// The size of a packed V2 header.
+const PACKED_HEADER_SIZE_V2: usize = 47;
 /// The offset where the allocator begins.
 const ALLOCATOR_OFFSET: usize = 52;
 
+/// The page size used by V2 maps storing unbounded keys or values.
+const DEFAULT_PAGE_SIZE: u32 = 1024;
+
+/// A marker stored in place of the max value size to indicate that the
+/// header holds a `PageSize::Value` rather than a `PageSize::Derived`.
+const PAGE_SIZE_VALUE_MARKER: u32 = u32::MAX;
+
+/// A flag set in the V2 header if the internal nodes store the counts of their
+/// children's subtrees.
+const COUNTS_FLAG: u8 = 1;
+
 /// A "stable" map based on a B-tree.
 ///
 /// The implementation is based on the algorithm outlined in "Introduction to Algorithms"
@@ -64,11 +152,8 @@ where
     // is set to NULL.
     root_addr: Address,
 
-    // The maximum size a key can have.
-    max_key_size: u32,
-
-    // The maximum size a value can have.
-    max_value_size: u32,
+    // The version of the map, which determines the layout of its nodes.
+    version: Version,
 
     // An allocator used for managing memory and allocating nodes.
     allocator: Allocator<M>,
@@ -76,18 +161,45 @@ where
     // The number of elements in the map.
     length: u64,
 
+    // The address of the next node to be visited by `migrate_step`.
+    // NULL if there are no V1 nodes left to migrate.
+    migration_cursor: Address,
+
+    // Whether the migration was started by `load` without saving the header yet. The
+    // header is saved before the first node is written, so that loading a map never
+    // writes to memory.
+    migration_unsaved: bool,
+
+    // The root of a tree detached by `clear_step` whose nodes are yet to be deallocated.
+    // NULL if there are no nodes left to deallocate.
+    clear_root: Address,
+
+    // Whether the internal nodes store the number of entries in each child's subtree.
+    has_counts: bool,
+
+    // The minimum degree of the tree. See `Config::branching_factor`.
+    branching_factor: usize,
+
+    // The first keys of the nodes saved since the counts were last updated. Only used
+    // if the map has counts.
+    modified_keys: Vec<K>,
+
+    // A cache of the nodes recently loaded from memory. See `set_node_cache_size`.
+    node_cache: RefCell<NodeCache<K>>,
+
     // A marker to communicate to the Rust compiler that we own these types.
     _phantom: PhantomData<(K, V)>,
 }
 
 /// The packed header size must be <= ALLOCATOR_OFFSET.
-struct BTreeHeaderV1 {
-    magic: [u8; 3],
-    version: u8,
-    max_key_size: u32,
-    max_value_size: u32,
+struct BTreeHeader {
+    version: Version,
     root_addr: Address,
     length: u64,
+    migration_cursor: Address,
+    clear_root: Address,
+    has_counts: bool,
+    branching_factor: usize,
     // Reserved bytes for future extensions
 }
 
@@ -119,6 +231,33 @@ where
         }
     }
 
+    /// Initializes a v1 `BTreeMap`.
+    ///
+    /// This is the same as [`BTreeMap::init`], except that a newly created map
+    /// uses the V1 layout and that a loaded V1 map isn't migrated to V2.
+    /// V1 maps support bounded types only.
+    ///
+    /// The map is only migrated to V2 if [`BTreeMap::migrate_step`] or
+    /// [`BTreeMap::clear_step`] is called, after which older versions of this
+    /// library can no longer load it.
+    pub fn init_v1(memory: M) -> Self {
+        if memory.size() == 0 {
+            // Memory is empty. Create a new map.
+            return BTreeMap::new_v1(memory);
+        }
+
+        // Check if the magic in the memory corresponds to a BTreeMap.
+        let mut dst = vec![0; 3];
+        memory.read(0, &mut dst);
+        if dst != MAGIC {
+            // No BTreeMap found. Create a new instance.
+            BTreeMap::new_v1(memory)
+        } else {
+            // The memory already contains a BTreeMap. Load it.
+            BTreeMap::load_helper(memory, false)
+        }
+    }
+
     /// Creates a new instance a `BTreeMap`.
     ///
     /// The given `memory` is assumed to be exclusively reserved for this data
@@ -130,20 +269,121 @@ where
     ///    |  BTreeHeader  |  Allocator | ... free memory for nodes |
     ///
     /// See `Allocator` for more details on its own memory layout.
+    ///
+    /// New maps use the V2 layout, which supports both bounded and unbounded
+    /// keys and values. If both the key and the value are bounded, the page
+    /// size is derived from their max sizes. Otherwise, a default page size is
+    /// used and nodes that don't fit in a page overflow into additional pages.
     pub fn new(memory: M) -> Self {
-        let max_key_size = max_size::<K>();
-        let max_value_size = max_size::<V>();
+        Self::new_with_config(memory, Config::default())
+    }
 
+    /// Creates a new instance of a `BTreeMap` with the given configuration.
+    ///
+    /// The configuration is persisted, so it's kept when the map is loaded with
+    /// [`BTreeMap::init`] or [`BTreeMap::load`]. See [`Config`] for the available options.
+    pub fn new_with_config(memory: M, config: Config) -> Self {
+        let page_size = match Self::default_page_size() {
+            // A derived page size fits nodes with the default branching factor and no
+            // counts, so the pages of other nodes are sized explicitly.
+            PageSize::Derived(page_size) if config.counts || config.branching_factor != B => {
+                PageSize::Value(page_size.get_for(config.branching_factor, config.counts))
+            }
+            page_size => page_size,
+        };
+
+        Self::new_with_version(
+            memory,
+            Version::V2(page_size),
+            config.counts,
+            config.branching_factor,
+        )
+    }
+
+    /// Creates a new instance of a `BTreeMap` whose internal nodes store the number of
+    /// entries in the subtree of each of their children.
+    ///
+    /// The counts make [`BTreeMap::rank`], [`BTreeMap::select`],
+    /// [`BTreeMap::range_count`] and skipping entries of an iterator (e.g. with
+    /// `iter().skip(n)` or `iter().nth(n)`) run in `O(log n)` rather than `O(n)`. In
+    /// exchange, the nodes are larger, and inserting or removing an entry rewrites all
+    /// the nodes on the path to it, which makes these operations more expensive.
+    ///
+    /// Whether a map has counts is persisted, so maps created with this constructor
+    /// keep their counts when loaded with [`BTreeMap::init`] or [`BTreeMap::load`].
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// // Player names keyed by score and player id.
+    /// let mut leaderboard: BTreeMap<(u64, u64), String, _> =
+    ///     BTreeMap::new_with_counts(DefaultMemoryImpl::default());
+    /// for (player_id, score) in [(1, 50), (2, 70), (3, 60)] {
+    ///     leaderboard.insert((score, player_id), format!("player {player_id}"));
+    /// }
+    ///
+    /// assert_eq!(leaderboard.rank(&(60, 3)), 1);
+    /// assert_eq!(leaderboard.select(2), Some(((70, 2), String::from("player 2"))));
+    /// assert_eq!(leaderboard.range_count((55, 0)..), 2);
+    /// ```
+    pub fn new_with_counts(memory: M) -> Self {
+        Self::new_with_config(memory, Config::default().counts(true))
+    }
+
+    // Returns the page size of new V2 maps.
+    fn default_page_size() -> PageSize {
+        match (K::BOUND, V::BOUND) {
+            (
+                StorableBound::Bounded {
+                    max_size: max_key_size,
+                    ..
+                },
+                StorableBound::Bounded {
+                    max_size: max_value_size,
+                    ..
+                },
+            ) => PageSize::Derived(DerivedPageSize {
+                max_key_size,
+                max_value_size,
+            }),
+            _ => PageSize::Value(DEFAULT_PAGE_SIZE),
+        }
+    }
+
+    /// Creates a new instance of a v1 `BTreeMap`.
+    ///
+    /// V1 maps support bounded types only. See [`BTreeMap::new`] for more details.
+    pub fn new_v1(memory: M) -> Self {
+        let version = Version::V1(DerivedPageSize {
+            max_key_size: max_size::<K>(),
+            max_value_size: max_size::<V>(),
+        });
+
+        Self::new_with_version(memory, version, false, B)
+    }
+
+    fn new_with_version(
+        memory: M,
+        version: Version,
+        has_counts: bool,
+        branching_factor: usize,
+    ) -> Self {
         let btree = Self {
             root_addr: NULL,
             allocator: Allocator::new(
                 memory,
                 Address::from(ALLOCATOR_OFFSET as u64),
-                Node::<K>::size(max_key_size, max_value_size),
+                Bytes::from(version.page_size()),
             ),
-            max_key_size,
-            max_value_size,
+            version,
             length: 0,
+            migration_cursor: NULL,
+            migration_unsaved: false,
+            clear_root: NULL,
+            has_counts,
+            branching_factor,
+            modified_keys: vec![],
+            node_cache: RefCell::new(NodeCache::new(0)),
             _phantom: PhantomData,
         };
 
@@ -152,46 +392,173 @@ where
     }
 
     /// Loads the map from memory.
+    ///
+    /// If the memory contains a V1 map, the map is migrated to V2. See the
+    /// module documentation for more details on how the migration works.
     pub fn load(memory: M) -> Self {
+        Self::load_helper(memory, true)
+    }
+
+    fn load_helper(memory: M, migrate_to_v2: bool) -> Self {
         // Read the header from memory.
         let header = Self::read_header(&memory);
-        assert_eq!(&header.magic, MAGIC, "Bad magic.");
-        assert_eq!(header.version, LAYOUT_VERSION, "Unsupported version.");
-        let expected_key_size = header.max_key_size;
-        assert!(
-            max_size::<K>() <= expected_key_size,
-            "max_key_size must be <= {expected_key_size}"
-        );
-        let expected_value_size = header.max_value_size;
-        assert!(
-            max_size::<V>() <= expected_value_size,
-            "max_value_size must be <= {expected_value_size}"
-        );
 
         let allocator_addr = Address::from(ALLOCATOR_OFFSET as u64);
-        Self {
+        let mut btree = Self {
             root_addr: header.root_addr,
             allocator: Allocator::load(memory, allocator_addr),
-            max_key_size: header.max_key_size,
-            max_value_size: header.max_value_size,
+            version: header.version,
             length: header.length,
+            migration_cursor: header.migration_cursor,
+            migration_unsaved: false,
+            clear_root: header.clear_root,
+            has_counts: header.has_counts,
+            branching_factor: header.branching_factor,
+            modified_keys: vec![],
+            node_cache: RefCell::new(NodeCache::new(0)),
             _phantom: PhantomData,
+        };
+
+        if let Version::V1(page_size) = header.version {
+            if migrate_to_v2 {
+                btree.start_migration(page_size);
+                btree.migration_unsaved = true;
+            } else {
+                // V1 nodes have fixed-size slots, so the types must fit in them.
+                let max_key_size = page_size.max_key_size;
+                let max_value_size = page_size.max_value_size;
+                assert!(
+                    max_size::<K>() <= max_key_size,
+                    "max_key_size must be <= {max_key_size}"
+                );
+                assert!(
+                    max_size::<V>() <= max_value_size,
+                    "max_value_size must be <= {max_value_size}"
+                );
+            }
+        }
+
+        btree
+    }
+
+    /// Migrates up to `budget` nodes of the map from V1 to V2.
+    ///
+    /// Loading a V1 map with [`BTreeMap::init`] or [`BTreeMap::load`] already
+    /// migrates nodes as they are rewritten. This method can be called repeatedly
+    /// (e.g. in a timer) to migrate the remaining nodes with a bounded amount of work
+    /// per call. Each call visits at most `budget` chunks of memory, each containing
+    /// a node (or part of a node), and progress is persisted across calls and upgrades.
+    ///
+    /// Calling this method on a V1 map loaded with [`BTreeMap::init_v1`] starts its
+    /// migration.
+    ///
+    /// Returns `true` if the migration is complete, `false` otherwise.
+    pub fn migrate_step(&mut self, budget: u64) -> bool {
+        if let Version::V1(page_size) = self.version {
+            self.start_migration(page_size);
+            self.save();
+        }
+
+        let mut visited = 0;
+        while self.migration_cursor != NULL && visited < budget {
+            let address = self.migration_cursor;
+            match self.allocator.chunk_status(address) {
+                ChunkStatus::Allocated => {
+                    // The chunk contains either a node or an overflow page.
+                    // Only nodes still in the V1 layout need to be rewritten.
+                    if Node::<K>::is_v1(address, self.memory()) {
+                        let mut node = self.load_node(address);
+                        self.write_node(&mut node);
+                    }
+                    self.migration_cursor = self.allocator.next_chunk(address);
+                }
+                ChunkStatus::Free => {
+                    self.migration_cursor = self.allocator.next_chunk(address);
+                }
+                ChunkStatus::End => {
+                    // All the chunks have been visited.
+                    self.migration_cursor = NULL;
+                }
+            }
+            visited += 1;
+        }
+
+        if visited > 0 {
+            self.save();
+        }
+
+        self.migration_cursor == NULL
+    }
+
+    // Switches the map to V2, with nodes to be migrated starting from the first chunk.
+    fn start_migration(&mut self, page_size: DerivedPageSize) {
+        self.version = Version::V2(PageSize::Derived(page_size));
+        // The cached nodes are loaded as V1 nodes, and would be saved in the V1 layout.
+        self.node_cache.get_mut().clear();
+        self.migration_cursor = self.allocator.first_chunk();
+    }
+
+    // Saves the header of a map whose migration was started by `load`. Must be called
+    // before writing nodes, as the V2 nodes can't be loaded from a map with a V1 header.
+    fn save_migration(&mut self) {
+        if self.migration_unsaved {
+            self.migration_unsaved = false;
+            self.save();
         }
     }
 
     /// Reads the header from the specified memory.
-    fn read_header(memory: &M) -> BTreeHeaderV1 {
+    fn read_header(memory: &M) -> BTreeHeader {
         // Read the header
-        let mut buf = [0; PACKED_HEADER_SIZE];
+        let mut buf = [0; PACKED_HEADER_SIZE_V2];
         memory.read(0, &mut buf);
+        assert_eq!(&buf[0..3], MAGIC, "Bad magic.");
+
         // Deserialize the fields
-        BTreeHeaderV1 {
-            magic: buf[0..3].try_into().unwrap(),
-            version: buf[3],
-            max_key_size: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
-            max_value_size: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+        let first_size = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        let second_size = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+        let version = match buf[3] {
+            LAYOUT_VERSION => Version::V1(DerivedPageSize {
+                max_key_size: first_size,
+                max_value_size: second_size,
+            }),
+            LAYOUT_VERSION_2 => {
+                if second_size == PAGE_SIZE_VALUE_MARKER {
+                    Version::V2(PageSize::Value(first_size))
+                } else {
+                    Version::V2(PageSize::Derived(DerivedPageSize {
+                        max_key_size: first_size,
+                        max_value_size: second_size,
+                    }))
+                }
+            }
+            other => panic!("Unsupported version: {other}."),
+        };
+
+        let (migration_cursor, clear_root, flags, branching_factor) = match version {
+            // The migration cursor, cleared root, flags and branching factor aren't part
+            // of the V1 layout.
+            Version::V1(_) => (NULL, NULL, 0, 0),
+            Version::V2(_) => (
+                Address::from(u64::from_le_bytes(buf[28..36].try_into().unwrap())),
+                Address::from(u64::from_le_bytes(buf[36..44].try_into().unwrap())),
+                buf[44],
+                u16::from_le_bytes(buf[45..47].try_into().unwrap()),
+            ),
+        };
+
+        BTreeHeader {
+            version,
             root_addr: Address::from(u64::from_le_bytes(buf[12..20].try_into().unwrap())),
             length: u64::from_le_bytes(buf[20..28].try_into().unwrap()),
+            migration_cursor,
+            clear_root,
+            has_counts: flags & COUNTS_FLAG != 0,
+            branching_factor: match branching_factor {
+                // Maps that predate configurable branching factors have the default one.
+                0 => B,
+                branching_factor => branching_factor as usize,
+            },
         }
     }
 
@@ -200,30 +567,25 @@ where
     /// The previous value of the key, if present, is returned.
     ///
     /// PRECONDITION:
-    ///   Key is bounded in size
-    ///   Value is bounded in size
-    ///   key.to_bytes().len() <= max_size(Key)
-    ///   value.to_bytes().len() <= max_size(Value)
+    ///   key.to_bytes().len() <= max_size(Key) (if Key is bounded)
+    ///   value.to_bytes().len() <= max_size(Value) (if Value is bounded)
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        let key_bytes = key.to_bytes();
-        let value_bytes = value.to_bytes();
+        Self::assert_key_size(&key);
+        let value = Self::encode_value(&value);
 
-        assert!(
-            key_bytes.len() <= self.max_key_size as usize,
-            "Key is too large. Expected <= {} bytes, found {} bytes",
-            self.max_key_size,
-            key_bytes.len()
-        );
-
-        assert!(
-            value_bytes.len() <= self.max_value_size as usize,
-            "Value is too large. Expected <= {} bytes, found {} bytes",
-            self.max_value_size,
-            value_bytes.len()
-        );
+        self.insert_encoded(key, value)
+            .map(Cow::Owned)
+            .map(V::from_bytes)
+    }
 
-        let value = value_bytes.to_vec();
+    // Inserts a key and an encoded value into the map, returning the previous encoded value.
+    pub(crate) fn insert_encoded(&mut self, key: K, value: Vec<u8>) -> Option<Vec<u8>> {
+        let previous_value = self.insert_encoded_helper(key, value);
+        self.update_counts();
+        previous_value
+    }
 
+    fn insert_encoded_helper(&mut self, key: K, value: Vec<u8>) -> Option<Vec<u8>> {
         let root = if self.root_addr == NULL {
             // No root present. Allocate one.
             let node = self.allocate_node(NodeType::Leaf);
@@ -238,8 +600,8 @@ where
             if let Ok(idx) = root.search(&key) {
                 // The key exists. Overwrite it and return the previous value.
                 let (_, previous_value) = root.swap_entry(idx, (key, value), self.memory());
-                root.save(self.memory());
-                return Some(V::from_bytes(Cow::Owned(previous_value)));
+                self.save_node(&mut root);
+                return Some(previous_value);
             }
 
             // If the root is full, we need to introduce a new node as the root.
@@ -268,8 +630,35 @@ where
         };
 
         self.insert_nonfull(root, key, value)
-            .map(Cow::Owned)
-            .map(V::from_bytes)
+    }
+
+    // Panics if the key is larger than the max size of `K`.
+    pub(crate) fn assert_key_size(key: &K) {
+        if let StorableBound::Bounded { max_size, .. } = K::BOUND {
+            let key_bytes = key.to_bytes();
+            assert!(
+                key_bytes.len() <= max_size as usize,
+                "Key is too large. Expected <= {} bytes, found {} bytes",
+                max_size,
+                key_bytes.len()
+            );
+        }
+    }
+
+    // Encodes the value, panicking if it's larger than the max size of `V`.
+    pub(crate) fn encode_value(value: &V) -> Vec<u8> {
+        let value_bytes = value.to_bytes();
+
+        if let StorableBound::Bounded { max_size, .. } = V::BOUND {
+            assert!(
+                value_bytes.len() <= max_size as usize,
+                "Value is too large. Expected <= {} bytes, found {} bytes",
+                max_size,
+                value_bytes.len()
+            );
+        }
+
+        value_bytes.into_owned()
     }
 
     // Inserts an entry into a node that is *not full*.
@@ -284,7 +673,7 @@ where
                 // Overwrite it and return the previous value.
                 let (_, previous_value) = node.swap_entry(idx, (key, value), self.memory());
 
-                node.save(self.memory());
+                self.save_node(&mut node);
                 Some(previous_value)
             }
             Err(idx) => {
@@ -295,7 +684,7 @@ where
                         // The node is a non-full leaf.
                         // Insert the entry at the proper location.
                         node.insert_entry(idx, (key, value));
-                        node.save(self.memory());
+                        self.save_node(&mut node);
 
                         // Update the length.
                         self.length += 1;
@@ -315,7 +704,7 @@ where
                                 // The key exists. Overwrite it and return the previous value.
                                 let (_, previous_value) =
                                     child.swap_entry(idx, (key, value), self.memory());
-                                child.save(self.memory());
+                                self.save_node(&mut child);
                                 return Some(previous_value);
                             }
 
@@ -373,9 +762,9 @@ where
 
         node.insert_entry(full_child_idx, (median_key, median_value));
 
-        sibling.save(self.memory());
-        full_child.save(self.memory());
-        node.save(self.memory());
+        self.save_node(&mut sibling);
+        self.save_node(&mut full_child);
+        self.save_node(node);
     }
 
     /// Returns the value associated with the given key if it exists.
@@ -405,6 +794,85 @@ where
         }
     }
 
+    /// Returns the entry of the given key for in-place manipulation.
+    ///
+    /// The entry is located with a single descent from the root. Inserting into a
+    /// vacant entry only requires another descent if the leaf where the key belongs
+    /// is full and needs to be split.
+    pub fn entry(&mut self, key: K) -> Entry<K, V, M> {
+        if self.root_addr == NULL {
+            // Map is empty.
+            return Entry::Vacant(VacantEntry::new(self, key, None));
+        }
+
+        let mut node = self.load_node(self.root_addr);
+        loop {
+            match node.search(&key) {
+                Ok(idx) => return Entry::Occupied(OccupiedEntry::new(self, node, idx)),
+                Err(idx) => match node.node_type() {
+                    NodeType::Leaf => {
+                        return Entry::Vacant(VacantEntry::new(self, key, Some((node, idx))))
+                    }
+                    NodeType::Internal => {
+                        // The key isn't in the node. Look for the key in the child.
+                        node = self.load_node(node.child(idx));
+                    }
+                },
+            }
+        }
+    }
+
+    /// Updates the value associated with the given key in place.
+    ///
+    /// Returns `true` if the key exists in the map, `false` otherwise.
+    ///
+    /// The entry is located with a single descent from the root. Where the node layout
+    /// permits, e.g. when the size of the encoded value doesn't change, only the value's
+    /// bytes are rewritten rather than the whole node.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// map.insert(1, 10);
+    ///
+    /// assert!(map.update(&1, |v| *v += 1));
+    /// assert_eq!(map.get(&1), Some(11));
+    /// assert!(!map.update(&2, |v| *v += 1));
+    /// ```
+    pub fn update<F: FnOnce(&mut V)>(&mut self, key: &K, f: F) -> bool {
+        self.update_with(key, f).is_some()
+    }
+
+    /// Updates the value associated with the given key in place, returning the result
+    /// of `f`, or `None` if the key doesn't exist in the map.
+    ///
+    /// See [`BTreeMap::update`] for more details.
+    pub fn update_with<R, F: FnOnce(&mut V) -> R>(&mut self, key: &K, f: F) -> Option<R> {
+        if self.root_addr == NULL {
+            return None;
+        }
+
+        let mut node = self.load_node(self.root_addr);
+        loop {
+            match node.search(key) {
+                Ok(idx) => {
+                    let mut value = V::from_bytes(Cow::Borrowed(&node.value(idx, self.memory())));
+                    let result = f(&mut value);
+                    self.update_node_value(&mut node, idx, Self::encode_value(&value));
+                    return Some(result);
+                }
+                Err(idx) => match node.node_type() {
+                    NodeType::Leaf => return None, // Key not found.
+                    NodeType::Internal => {
+                        // The key isn't in the node. Look for the key in the child.
+                        node = self.load_node(node.child(idx));
+                    }
+                },
+            }
+        }
+    }
+
     /// Returns `true` if the key exists in the map, `false` otherwise.
     pub fn contains_key(&self, key: &K) -> bool {
         self.get(key).is_some()
@@ -426,9 +894,46 @@ where
     }
 
     /// Removes all elements from the map.
+    ///
+    /// The map keeps its layout, including the V1 layout of a map loaded with
+    /// [`BTreeMap::init_v1`]. See [`BTreeMap::clear_step`] for clearing a map in bounded
+    /// steps.
     pub fn clear(self) -> Self {
+        let version = self.version;
+        let config = Config::default()
+            .branching_factor(self.branching_factor)
+            .counts(self.has_counts);
+        let node_cache_size = self.node_cache.borrow().capacity();
         let mem = self.allocator.into_memory();
-        Self::new(mem)
+        let mut map = match version {
+            Version::V1(_) => Self::new_with_version(mem, version, false, B),
+            Version::V2(_) => Self::new_with_config(mem, config),
+        };
+        map.set_node_cache_size(node_cache_size);
+        map
+    }
+
+    /// Sets the maximum number of nodes kept in the map's node cache, which is disabled
+    /// (i.e. of size zero) by default.
+    ///
+    /// Every lookup starts by loading the root of the tree, followed by a node at every
+    /// level down to the key, from memory. The node cache keeps recently loaded nodes
+    /// in the heap, so that the nodes near the root, which are loaded by most lookups,
+    /// aren't read and decoded over and over. When the cache is full, the least recently
+    /// used node is evicted. Nodes are removed from the cache whenever they're modified.
+    ///
+    /// The cache isn't persisted, and has to be set again after the map is loaded.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// map.set_node_cache_size(32);
+    /// map.insert(1, 2);
+    /// assert_eq!(map.get(&1), Some(2));
+    /// ```
+    pub fn set_node_cache_size(&mut self, num_nodes: usize) {
+        self.node_cache = RefCell::new(NodeCache::new(num_nodes));
     }
 
     /// Returns the first key-value pair in the map. The key in this
@@ -438,7 +943,7 @@ where
             return None;
         }
         let root = self.load_node(self.root_addr);
-        let (k, encoded_v) = root.get_min(self.memory());
+        let (k, encoded_v) = self.subtree_min(&root);
         Some((k, V::from_bytes(Cow::Owned(encoded_v))))
     }
 
@@ -449,10 +954,139 @@ where
             return None;
         }
         let root = self.load_node(self.root_addr);
-        let (k, encoded_v) = root.get_max(self.memory());
+        let (k, encoded_v) = self.subtree_max(&root);
         Some((k, V::from_bytes(Cow::Owned(encoded_v))))
     }
 
+    /// Returns the entry with the largest key that is smaller than or equal to the given
+    /// key, if any.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// // Exchange rates by the timestamp from which they apply.
+    /// let mut rates: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// rates.insert(100, 7);
+    /// rates.insert(200, 8);
+    ///
+    /// assert_eq!(rates.floor(&150), Some((100, 7)));
+    /// assert_eq!(rates.floor(&200), Some((200, 8)));
+    /// assert_eq!(rates.floor(&50), None);
+    /// ```
+    pub fn floor(&self, key: &K) -> Option<(K, V)> {
+        self.entry_before(key, true)
+    }
+
+    /// Returns the entry with the smallest key that is greater than or equal to the given
+    /// key, if any.
+    pub fn ceiling(&self, key: &K) -> Option<(K, V)> {
+        self.entry_after(key, true)
+    }
+
+    /// Returns the entry with the largest key that is strictly smaller than the given key,
+    /// if any.
+    pub fn predecessor(&self, key: &K) -> Option<(K, V)> {
+        self.entry_before(key, false)
+    }
+
+    /// Returns the entry with the smallest key that is strictly greater than the given
+    /// key, if any.
+    pub fn successor(&self, key: &K) -> Option<(K, V)> {
+        self.entry_after(key, false)
+    }
+
+    // Returns the entry with the largest key that is smaller than the given key, or equal
+    // to it if `inclusive` is true.
+    fn entry_before(&self, key: &K, inclusive: bool) -> Option<(K, V)> {
+        if self.root_addr == NULL {
+            return None;
+        }
+
+        // The node and index of the largest key found so far.
+        let mut candidate = None;
+        let mut node = self.load_node(self.root_addr);
+        loop {
+            // The keys before `idx` are all smaller than the given key.
+            let idx = match node.search(key) {
+                Ok(idx) if inclusive => return Some(self.decode_entry(&node, idx)),
+                Ok(idx) => match node.node_type() {
+                    // The largest smaller key is the largest key of the left subtree.
+                    NodeType::Internal => {
+                        let (key, encoded_value) =
+                            self.subtree_max(&self.load_node(node.child(idx)));
+                        return Some((key, V::from_bytes(Cow::Owned(encoded_value))));
+                    }
+                    NodeType::Leaf => idx,
+                },
+                Err(idx) => idx,
+            };
+
+            let child = match node.node_type() {
+                NodeType::Internal => Some(self.load_node(node.child(idx))),
+                NodeType::Leaf => None,
+            };
+            if idx > 0 {
+                candidate = Some((node, idx - 1));
+            }
+
+            match child {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+
+        candidate.map(|(node, idx)| self.decode_entry(&node, idx))
+    }
+
+    // Returns the entry with the smallest key that is greater than the given key, or
+    // equal to it if `inclusive` is true.
+    fn entry_after(&self, key: &K, inclusive: bool) -> Option<(K, V)> {
+        if self.root_addr == NULL {
+            return None;
+        }
+
+        // The node and index of the smallest key found so far.
+        let mut candidate = None;
+        let mut node = self.load_node(self.root_addr);
+        loop {
+            // The keys from `idx` onwards are all greater than the given key.
+            let idx = match node.search(key) {
+                Ok(idx) if inclusive => return Some(self.decode_entry(&node, idx)),
+                Ok(idx) => match node.node_type() {
+                    // The smallest greater key is the smallest key of the right subtree.
+                    NodeType::Internal => {
+                        let (key, encoded_value) =
+                            self.subtree_min(&self.load_node(node.child(idx + 1)));
+                        return Some((key, V::from_bytes(Cow::Owned(encoded_value))));
+                    }
+                    NodeType::Leaf => idx + 1,
+                },
+                Err(idx) => idx,
+            };
+
+            let child = match node.node_type() {
+                NodeType::Internal => Some(self.load_node(node.child(idx))),
+                NodeType::Leaf => None,
+            };
+            if idx < node.entries_len() {
+                candidate = Some((node, idx));
+            }
+
+            match child {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+
+        candidate.map(|(node, idx)| self.decode_entry(&node, idx))
+    }
+
+    // Returns the decoded entry at the given index of the node.
+    fn decode_entry(&self, node: &Node<K>, idx: usize) -> (K, V) {
+        let (key, encoded_value) = node.entry(idx, self.memory());
+        (key, V::from_bytes(Cow::Owned(encoded_value)))
+    }
+
     fn memory(&self) -> &M {
         self.allocator.memory()
     }
@@ -464,9 +1098,9 @@ where
         }
 
         let root_node = self.load_node(self.root_addr);
-        self.remove_helper(root_node, key)
-            .map(Cow::Owned)
-            .map(V::from_bytes)
+        let value = self.remove_helper(root_node, key);
+        self.update_counts();
+        value.map(Cow::Owned).map(V::from_bytes)
     }
 
     // A helper method for recursively removing a key from the B-tree.
@@ -495,10 +1129,10 @@ where
                             );
 
                             // Deallocate the empty node.
-                            self.allocator.deallocate(node.address());
+                            self.deallocate_node(node);
                             self.root_addr = NULL;
                         } else {
-                            node.save(self.memory());
+                            self.save_node(&mut node);
                         }
 
                         self.save();
@@ -536,14 +1170,14 @@ where
 
                             // Recursively delete the predecessor.
                             // TODO(EXC-1034): Do this in a single pass.
-                            let predecessor = left_child.get_max(self.memory());
+                            let predecessor = self.subtree_max(&left_child);
                             self.remove_helper(left_child, &predecessor.0)?;
 
                             // Replace the `key` with its predecessor.
                             let (_, old_value) = node.swap_entry(idx, predecessor, self.memory());
 
                             // Save the parent node.
-                            node.save(self.memory());
+                            self.save_node(&mut node);
                             return Some(old_value);
                         }
 
@@ -571,14 +1205,14 @@ where
 
                             // Recursively delete the successor.
                             // TODO(EXC-1034): Do this in a single pass.
-                            let successor = right_child.get_min(self.memory());
+                            let successor = self.subtree_min(&right_child);
                             self.remove_helper(right_child, &successor.0)?;
 
                             // Replace the `key` with its successor.
                             let (_, old_value) = node.swap_entry(idx, successor, self.memory());
 
                             // Save the parent node.
-                            node.save(self.memory());
+                            self.save_node(&mut node);
                             return Some(old_value);
                         }
 
@@ -605,7 +1239,7 @@ where
                         assert!(right_child.at_minimum());
 
                         // Merge the right child into the left child.
-                        let new_child = self.merge(
+                        let mut new_child = self.merge(
                             right_child,
                             left_child,
                             node.remove_entry(idx, self.memory()),
@@ -623,12 +1257,13 @@ where
                             self.root_addr = new_child.address();
 
                             // Deallocate the root node.
-                            self.allocator.deallocate(node.address());
+                            self.deallocate_node(node);
                             self.save();
+                        } else {
+                            self.save_node(&mut node);
                         }
 
-                        node.save(self.memory());
-                        new_child.save(self.memory());
+                        self.save_node(&mut new_child);
 
                         // Recursively delete the key.
                         self.remove_helper(new_child, key)
@@ -708,9 +1343,9 @@ where
                                     assert_eq!(child.node_type(), NodeType::Leaf);
                                 }
 
-                                left_sibling.save(self.memory());
-                                child.save(self.memory());
-                                node.save(self.memory());
+                                self.save_node(left_sibling);
+                                self.save_node(&mut child);
+                                self.save_node(&mut node);
                                 return self.remove_helper(child, key);
                             }
                         }
@@ -763,9 +1398,9 @@ where
                                     }
                                 }
 
-                                right_sibling.save(self.memory());
-                                child.save(self.memory());
-                                node.save(self.memory());
+                                self.save_node(right_sibling);
+                                self.save_node(&mut child);
+                                self.save_node(&mut node);
                                 return self.remove_helper(child, key);
                             }
                         }
@@ -785,15 +1420,15 @@ where
                             node.remove_child(idx);
 
                             if node.entries_len() == 0 {
-                                self.allocator.deallocate(node.address());
-
                                 if node.address() == self.root_addr {
                                     // Update the root.
                                     self.root_addr = left_sibling.address();
                                     self.save();
                                 }
+
+                                self.deallocate_node(node);
                             } else {
-                                node.save(self.memory());
+                                self.save_node(&mut node);
                             }
 
                             return self.remove_helper(left_sibling, key);
@@ -813,15 +1448,15 @@ where
                             node.remove_child(idx);
 
                             if node.entries_len() == 0 {
-                                self.allocator.deallocate(node.address());
-
                                 if node.address() == self.root_addr {
                                     // Update the root.
                                     self.root_addr = right_sibling.address();
                                     self.save();
                                 }
+
+                                self.deallocate_node(node);
                             } else {
-                                node.save(self.memory());
+                                self.save_node(&mut node);
                             }
 
                             return self.remove_helper(right_sibling, key);
@@ -852,169 +1487,177 @@ where
             key_range.end_bound().cloned(),
         );
 
-        let mut cursors = vec![];
+        Iter::new_in_range(self, range)
+    }
 
-        match key_range.start_bound() {
-            Bound::Unbounded => {
-                cursors.push(Cursor::Address(self.root_addr));
-                Iter::new_in_range(self, range, cursors)
-            }
-            Bound::Included(key) | Bound::Excluded(key) => {
-                let mut node = self.load_node(self.root_addr);
-                loop {
-                    match node.search(key) {
-                        Ok(idx) => {
-                            if let Bound::Included(_) = key_range.start_bound() {
-                                // We found the key exactly matching the left bound.
-                                // Here is where we'll start the iteration.
-                                cursors.push(Cursor::Node {
-                                    node,
-                                    next: Index::Entry(idx),
-                                });
-                                return Iter::new_in_range(self, range, cursors);
-                            } else {
-                                // We found the key that we must
-                                // exclude.  We add its right neighbor
-                                // to the stack and start iterating
-                                // from its right child.
-                                let right_child = match node.node_type() {
-                                    NodeType::Internal => Some(node.child(idx + 1)),
-                                    NodeType::Leaf => None,
-                                };
-
-                                if idx + 1 != node.entries_len()
-                                    && key_range.contains(node.key(idx + 1))
-                                {
-                                    cursors.push(Cursor::Node {
-                                        node,
-                                        next: Index::Entry(idx + 1),
-                                    });
-                                }
-                                if let Some(right_child) = right_child {
-                                    cursors.push(Cursor::Address(right_child));
-                                }
-                                return Iter::new_in_range(self, range, cursors);
-                            }
-                        }
-                        Err(idx) => {
-                            // The `idx` variable points to the first
-                            // key that is greater than the left
-                            // bound.
-                            //
-                            // If the index points to a valid node, we
-                            // will visit its left subtree and then
-                            // return to this key.
-                            //
-                            // If the index points at the end of
-                            // array, we'll continue with the right
-                            // child of the last key.
-
-                            // Load the left child of the node to visit if it exists.
-                            // This is done first to avoid cloning the node.
-                            let child = match node.node_type() {
-                                NodeType::Internal => {
-                                    // Note that loading a child node cannot fail since
-                                    // len(children) = len(entries) + 1
-                                    Some(self.load_node(node.child(idx)))
-                                }
-                                NodeType::Leaf => None,
-                            };
-
-                            if idx < node.entries_len() && key_range.contains(node.key(idx)) {
-                                cursors.push(Cursor::Node {
-                                    node,
-                                    next: Index::Entry(idx),
-                                });
-                            }
+    /// Returns up to `limit` entries starting at the given cursor, along with a cursor
+    /// pointing to the entries that follow them.
+    ///
+    /// The returned cursor is `None` once all the entries have been returned.
+    /// Entries inserted or removed between calls are handled gracefully: iteration
+    /// resumes at the first key greater than the last key returned.
+    ///
+    /// ```
+    /// use ic_stable_structures::{btreemap::PageCursor, BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// for i in 0..5 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let (page, cursor) = map.iter_from_cursor(&PageCursor::start(), 3);
+    /// assert_eq!(page, vec![(0, 0), (1, 1), (2, 2)]);
+    ///
+    /// let (page, cursor) = map.iter_from_cursor(&cursor.unwrap(), 3);
+    /// assert_eq!(page, vec![(3, 3), (4, 4)]);
+    /// assert_eq!(cursor, None);
+    /// ```
+    pub fn iter_from_cursor(
+        &self,
+        cursor: &PageCursor<K>,
+        limit: usize,
+    ) -> (Vec<(K, V)>, Option<PageCursor<K>>) {
+        let start = match cursor.last_key() {
+            Some(key) => Bound::Excluded(key.clone()),
+            None => Bound::Unbounded,
+        };
 
-                            match child {
-                                None => {
-                                    // Leaf node. Return an iterator with the found cursors.
-                                    return Iter::new_in_range(self, range, cursors);
-                                }
-                                Some(child) => {
-                                    // Iterate over the child node.
-                                    node = child;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        let page: Vec<_> = if self.root_addr == NULL {
+            vec![]
+        } else {
+            Iter::new_in_range(self, (start, Bound::Unbounded))
+                .take(limit)
+                .collect()
+        };
+
+        if page.len() < limit {
+            // There are no more entries.
+            return (page, None);
         }
+
+        let next_cursor = match page.last() {
+            Some((key, _)) => PageCursor::after(key.clone()),
+            // The limit is zero. Iteration resumes at the same position.
+            None => cursor.clone(),
+        };
+
+        (page, Some(next_cursor))
     }
 
-    /// Returns an iterator pointing to the first element below the given bound.
-    /// Returns an empty iterator if there are no keys below the given bound.
-    pub fn iter_upper_bound(&self, bound: &K) -> Iter<K, V, M> {
+    /// Returns an iterator over the entries in the map where keys start with the
+    /// given prefix.
+    ///
+    /// The iterator seeks directly to the first key that starts with the prefix and
+    /// stops at the first key that doesn't.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<(u64, u64), u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// map.insert((1, 1), 10);
+    /// map.insert((2, 1), 20);
+    /// map.insert((2, 2), 30);
+    /// map.insert((3, 1), 40);
+    ///
+    /// let entries: Vec<_> = map.prefix_iter(&2).collect();
+    /// assert_eq!(entries, vec![((2, 1), 20), ((2, 2), 30)]);
+    /// ```
+    pub fn prefix_iter<P: KeyPrefix<K> + ?Sized>(&self, prefix: &P) -> Iter<K, V, M> {
         if self.root_addr == NULL {
             // Map is empty.
             return Iter::null(self);
         }
 
-        let dummy_bounds = (Bound::Unbounded, Bound::Unbounded);
-        // INVARIANT: all cursors point to keys greater than or equal to bound.
-        let mut cursors = vec![];
+        let is_before_end = |key: &K| prefix.cmp_key(key) != Ordering::Greater;
 
+        // The iteration stops at the first key after the keys with the prefix, which is
+        // looked up along with the first key with the prefix. As the keys with the prefix
+        // are contiguous, both keys are reached through the same nodes until a node holds
+        // keys with the prefix, or a child between them. Only the subtree after these
+        // keys is then descended separately to find the end.
+        let mut end = None;
+        let mut end_subtree = None;
+
+        // Seek to the first key with the prefix.
+        let mut cursors = vec![];
         let mut node = self.load_node(self.root_addr);
         loop {
-            match node.search(bound) {
-                Ok(idx) | Err(idx) => {
-                    match node.node_type() {
-                        NodeType::Leaf => {
-                            if idx == 0 {
-                                // We descended into a leaf but didn't find a node less than
-                                // the upper bound. Thus we unwind the cursor stack until we
-                                // hit a cursor pointing to an element other than the first key,
-                                // and we shift the position backward. If there is no such cursor,
-                                // the bound must be <= min element, so we return an empty iterator.
-                                while let Some(cursor) = cursors.pop() {
-                                    match cursor {
-                                        Cursor::Node {
-                                            node,
-                                            next: Index::Entry(n),
-                                        } => {
-                                            if n == 0 {
-                                                debug_assert!(node.key(n) >= bound);
-                                                continue;
-                                            } else {
-                                                debug_assert!(node.key(n - 1) < bound);
-                                                cursors.push(Cursor::Node {
-                                                    node,
-                                                    next: Index::Entry(n - 1),
-                                                });
-                                                break;
-                                            }
-                                        }
-                                        _ => panic!("BUG: unexpected cursor shape"),
-                                    }
-                                }
-                                // If the cursors are empty, the iterator will be empty.
-                                return Iter::new_in_range(self, dummy_bounds, cursors);
-                            }
-                            debug_assert!(node.key(idx - 1) < bound);
+            let idx = node.partition_point(|key| prefix.cmp_key(key) == Ordering::Less);
+            if end_subtree.is_none() {
+                let end_idx = node.partition_point(is_before_end);
+                if end_idx < node.entries_len() {
+                    end = Some(node.key(end_idx).clone());
+                }
+                if end_idx > idx && node.node_type() == NodeType::Internal {
+                    end_subtree = Some(node.child(end_idx));
+                }
+            }
+
+            // Load the left child of the key to visit if it exists.
+            let child = match node.node_type() {
+                NodeType::Internal => Some(self.load_node(node.child(idx))),
+                NodeType::Leaf => None,
+            };
+
+            if idx < node.entries_len() {
+                cursors.push(Cursor::Node {
+                    node,
+                    next: Index::Entry(idx),
+                });
+            }
+
+            match child {
+                None => {
+                    // The cursor at the top of the stack points to the first key with the
+                    // prefix. It's also the start of the range when iterating backwards.
+                    let start = match cursors.last() {
+                        Some(Cursor::Node {
+                            node,
+                            next: Index::Entry(idx),
+                        }) => Bound::Included(node.key(*idx).clone()),
+                        _ => return Iter::null(self),
+                    };
+                    if let Some(address) = end_subtree {
+                        end = self.partition_point_key(address, is_before_end).or(end);
+                    }
+                    let end = match end {
+                        Some(key) => Bound::Excluded(key),
+                        None => Bound::Unbounded,
+                    };
+                    return Iter::new_with_cursors(self, (start, end), cursors);
+                }
+                Some(child) => node = child,
+            }
+        }
+    }
+
+    // Returns the first key in the subtree at the given address for which the predicate
+    // is false, assuming that the predicate is true for all the keys before it and false
+    // for all the keys after.
+    fn partition_point_key(&self, address: Address, pred: impl Fn(&K) -> bool) -> Option<K> {
+        let mut first = None;
+        let mut node = self.load_node(address);
+        loop {
+            let idx = node.partition_point(&pred);
+            if idx < node.entries_len() {
+                first = Some(node.key(idx).clone());
+            }
 
-                            cursors.push(Cursor::Node {
-                                node,
-                                next: Index::Entry(idx - 1),
-                            });
-                            return Iter::new_in_range(self, dummy_bounds, cursors);
-                        }
-                        NodeType::Internal => {
-                            let child = self.load_node(node.child(idx));
-                            // We push the node even if idx == node.entries_len()
-                            // If we find the position in the child, the iterator will skip this
-                            // cursor. But if the all keys in the child are greater than or equal to
-                            // the bound, we will be able to use this cursor as a fallback.
-                            cursors.push(Cursor::Node {
-                                node,
-                                next: Index::Entry(idx),
-                            });
-                            node = child;
-                        }
-                    }
-                }
+            match node.node_type() {
+                NodeType::Internal => node = self.load_node(node.child(idx)),
+                NodeType::Leaf => return first,
+            }
+        }
+    }
+
+    /// Returns an iterator pointing to the first element below the given bound.
+    /// Returns an empty iterator if there are no keys below the given bound.
+    pub fn iter_upper_bound(&self, bound: &K) -> Iter<K, V, M> {
+        match self.predecessor(bound) {
+            Some((start_key, _)) => {
+                Iter::new_in_range(self, (Bound::Included(start_key), Bound::Unbounded))
             }
+            None => Iter::null(self),
         }
     }
 
@@ -1030,60 +1673,166 @@ where
     // Output:
     //   [1, 2, 3, 4, 5, 6, 7] (stored in the `into` node)
     //   `source` is deallocated.
-    fn merge(&mut self, source: Node<K>, mut into: Node<K>, median: Entry<K>) -> Node<K> {
-        let source_address = source.address();
-        into.merge(source, median, self.memory());
-        into.save(self.memory());
-        self.allocator.deallocate(source_address);
+    fn merge(&mut self, source: Node<K>, mut into: Node<K>, median: NodeEntry<K>) -> Node<K> {
+        // The source node is deallocated.
+        self.node_cache.get_mut().remove(source.address());
+        into.merge(source, median, &mut self.allocator);
+        self.save_node(&mut into);
         into
     }
 
     fn allocate_node(&mut self, node_type: NodeType) -> Node<K> {
-        Node::new(
+        let mut node = Node::new(
             self.allocator.allocate(),
             node_type,
-            self.max_key_size,
-            self.max_value_size,
+            self.version,
+            self.branching_factor,
+        );
+        if Self::has_empty_values() && matches!(self.version, Version::V2(_)) {
+            node.set_keys_only();
+        }
+        if self.has_counts {
+            node.set_counted();
+        }
+        node
+    }
+
+    // Returns the entry with the max key in the subtree of the given node.
+    fn subtree_max(&self, node: &Node<K>) -> NodeEntry<K> {
+        match node.node_type() {
+            NodeType::Leaf => node.get_max(self.memory()),
+            NodeType::Internal => {
+                self.subtree_max(&self.load_node(node.child(node.children_len() - 1)))
+            }
+        }
+    }
+
+    // Returns the entry with the min key in the subtree of the given node.
+    fn subtree_min(&self, node: &Node<K>) -> NodeEntry<K> {
+        match node.node_type() {
+            NodeType::Leaf => node.get_min(self.memory()),
+            NodeType::Internal => self.subtree_min(&self.load_node(node.child(0))),
+        }
+    }
+
+    // Saves the node to memory. If the map has counts, the node is tracked so that the
+    // counts of its ancestors are updated at the end of the operation.
+    fn save_node(&mut self, node: &mut Node<K>) {
+        self.write_node(node);
+        if self.has_counts && node.entries_len() > 0 {
+            self.modified_keys.push(node.key(0).clone());
+        }
+    }
+
+    // Saves the node to memory without tracking it for the counts.
+    fn write_node(&mut self, node: &mut Node<K>) {
+        self.save_migration();
+        self.node_cache.get_mut().remove(node.address());
+        node.save(&mut self.allocator);
+    }
+
+    // Replaces the value at the given index of the node and persists the change.
+    fn update_node_value(&mut self, node: &mut Node<K>, idx: usize, value: Vec<u8>) {
+        self.save_migration();
+        self.node_cache.get_mut().remove(node.address());
+        node.update_value(idx, value, &mut self.allocator);
+    }
+
+    // Deallocates the node, along with any overflow pages it may have.
+    fn deallocate_node(&mut self, node: Node<K>) {
+        self.node_cache.get_mut().remove(node.address());
+        node.deallocate(&mut self.allocator);
+    }
+
+    // Returns true if the values are always empty, in which case they don't need to be
+    // stored in V2 nodes.
+    //
+    // NOTE: `()` isn't fixed in size for backward-compatibility, so its values are stored.
+    fn has_empty_values() -> bool {
+        matches!(
+            V::BOUND,
+            StorableBound::Bounded {
+                max_size: 0,
+                is_fixed_size: true
+            }
         )
     }
 
     fn load_node(&self, address: Address) -> Node<K> {
-        Node::load(
+        let mut node_cache = self.node_cache.borrow_mut();
+        if let Some(node) = node_cache.get(address) {
+            return node;
+        }
+
+        // Nodes that haven't been migrated yet are in the V1 layout, which only needs to be
+        // checked for while a migration is running.
+        let node = Node::load(
             address,
             self.memory(),
-            Version::V1(DerivedPageSize {
-                max_key_size: self.max_key_size,
-                max_value_size: self.max_value_size,
-            }),
-        )
+            self.version,
+            self.branching_factor,
+            self.migration_cursor != NULL,
+        );
+        node_cache.insert(&node);
+        node
     }
 
     // Saves the map to memory.
     fn save(&self) {
-        let header = BTreeHeaderV1 {
-            magic: *MAGIC,
-            version: LAYOUT_VERSION,
-            max_key_size: self.max_key_size,
-            max_value_size: self.max_value_size,
+        let header = BTreeHeader {
+            version: self.version,
             root_addr: self.root_addr,
             length: self.length,
+            migration_cursor: self.migration_cursor,
+            clear_root: self.clear_root,
+            has_counts: self.has_counts,
+            branching_factor: self.branching_factor,
         };
 
         Self::write_header(&header, self.memory());
     }
 
     /// Write the layout header to the memory.
-    fn write_header(header: &BTreeHeaderV1, memory: &M) {
+    fn write_header(header: &BTreeHeader, memory: &M) {
+        let (version, first_size, second_size) = match header.version {
+            Version::V1(DerivedPageSize {
+                max_key_size,
+                max_value_size,
+            }) => (LAYOUT_VERSION, max_key_size, max_value_size),
+            Version::V2(PageSize::Derived(DerivedPageSize {
+                max_key_size,
+                max_value_size,
+            })) => (LAYOUT_VERSION_2, max_key_size, max_value_size),
+            Version::V2(PageSize::Value(page_size)) => {
+                (LAYOUT_VERSION_2, page_size, PAGE_SIZE_VALUE_MARKER)
+            }
+        };
+
         // Serialize the header
-        let mut buf = [0; PACKED_HEADER_SIZE];
-        buf[0..3].copy_from_slice(&header.magic);
-        buf[3] = header.version;
-        buf[4..8].copy_from_slice(&header.max_key_size.to_le_bytes());
-        buf[8..12].copy_from_slice(&header.max_value_size.to_le_bytes());
+        let mut buf = [0; PACKED_HEADER_SIZE_V2];
+        buf[0..3].copy_from_slice(MAGIC);
+        buf[3] = version;
+        buf[4..8].copy_from_slice(&first_size.to_le_bytes());
+        buf[8..12].copy_from_slice(&second_size.to_le_bytes());
         buf[12..20].copy_from_slice(&header.root_addr.get().to_le_bytes());
         buf[20..28].copy_from_slice(&header.length.to_le_bytes());
+        let header_size = match header.version {
+            Version::V1(_) => PACKED_HEADER_SIZE,
+            Version::V2(_) => {
+                buf[28..36].copy_from_slice(&header.migration_cursor.get().to_le_bytes());
+                buf[36..44].copy_from_slice(&header.clear_root.get().to_le_bytes());
+                buf[44] = if header.has_counts { COUNTS_FLAG } else { 0 };
+                // The default branching factor is stored as zero, as in older maps.
+                let branching_factor = match header.branching_factor {
+                    B => 0,
+                    branching_factor => branching_factor as u16,
+                };
+                buf[45..47].copy_from_slice(&branching_factor.to_le_bytes());
+                PACKED_HEADER_SIZE_V2
+            }
+        };
         // Write the header
-        crate::write(memory, 0, &buf);
+        crate::write(memory, 0, &buf[..header_size]);
     }
 }
 
@@ -2346,36 +3095,36 @@ mod test {
     #[should_panic(expected = "max_key_size must be <= 4")]
     fn rejects_larger_key_sizes() {
         let mem = make_memory();
-        let btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init(mem);
-        let _btree: BTreeMap<Blob<5>, Blob<3>, _> = BTreeMap::init(btree.into_memory());
+        let btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init_v1(mem);
+        let _btree: BTreeMap<Blob<5>, Blob<3>, _> = BTreeMap::init_v1(btree.into_memory());
     }
 
     #[test]
     fn accepts_small_or_equal_key_sizes() {
         let mem = make_memory();
-        let btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init(mem);
+        let btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init_v1(mem);
         // Smaller key size
-        let btree: BTreeMap<Blob<3>, Blob<3>, _> = BTreeMap::init(btree.into_memory());
+        let btree: BTreeMap<Blob<3>, Blob<3>, _> = BTreeMap::init_v1(btree.into_memory());
         // Equal key size
-        let _btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init(btree.into_memory());
+        let _btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init_v1(btree.into_memory());
     }
 
     #[test]
     #[should_panic(expected = "max_value_size must be <= 3")]
     fn rejects_larger_value_sizes() {
         let mem = make_memory();
-        let btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init(mem);
-        let _btree: BTreeMap<Blob<4>, Blob<4>, _> = BTreeMap::init(btree.into_memory());
+        let btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init_v1(mem);
+        let _btree: BTreeMap<Blob<4>, Blob<4>, _> = BTreeMap::init_v1(btree.into_memory());
     }
 
     #[test]
     fn accepts_small_or_equal_value_sizes() {
         let mem = make_memory();
-        let btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init(mem);
+        let btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init_v1(mem);
         // Smaller key size
-        let btree: BTreeMap<Blob<4>, Blob<2>, _> = BTreeMap::init(btree.into_memory());
+        let btree: BTreeMap<Blob<4>, Blob<2>, _> = BTreeMap::init_v1(btree.into_memory());
         // Equal key size
-        let _btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init(btree.into_memory());
+        let _btree: BTreeMap<Blob<4>, Blob<3>, _> = BTreeMap::init_v1(btree.into_memory());
     }
 
     #[test]
@@ -2462,6 +3211,209 @@ mod test {
         }
     }
 
+    #[test]
+    fn floor_ceiling_predecessor_successor() {
+        let mut btree = BTreeMap::new(make_memory());
+        assert_eq!(btree.floor(&1), None);
+        assert_eq!(btree.successor(&1), None);
+
+        // Even keys, spanning several levels of the tree.
+        for k in (0..1000u64).step_by(2) {
+            btree.insert(k, k * 10);
+        }
+
+        for k in 1..998u64 {
+            let (below, above) = if k % 2 == 0 { (k, k) } else { (k - 1, k + 1) };
+            assert_eq!(btree.floor(&k), Some((below, below * 10)));
+            assert_eq!(btree.ceiling(&k), Some((above, above * 10)));
+
+            let (below, above) = if k % 2 == 0 {
+                (k - 2, k + 2)
+            } else {
+                (k - 1, k + 1)
+            };
+            assert_eq!(btree.predecessor(&k), Some((below, below * 10)));
+            assert_eq!(btree.successor(&k), Some((above, above * 10)));
+        }
+
+        assert_eq!(btree.floor(&0), Some((0, 0)));
+        assert_eq!(btree.predecessor(&0), None);
+        assert_eq!(btree.ceiling(&999), None);
+        assert_eq!(btree.successor(&998), None);
+        assert_eq!(btree.floor(&5000), Some((998, 9980)));
+    }
+
+    #[test]
+    fn iter_from_cursor() {
+        let mut btree = BTreeMap::new(make_memory());
+        for i in 0..100u64 {
+            btree.insert(i, i + 1);
+        }
+
+        let mut cursor = Some(PageCursor::start());
+        let mut entries = vec![];
+        while let Some(c) = cursor {
+            let (page, next_cursor) = btree.iter_from_cursor(&c, 7);
+            assert!(page.len() <= 7);
+            entries.extend(page);
+            cursor = next_cursor;
+        }
+
+        assert_eq!(entries, btree.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn iter_from_cursor_with_concurrent_modifications() {
+        let mut btree = BTreeMap::new(make_memory());
+        for i in 0..10u64 {
+            btree.insert(i * 10, ());
+        }
+
+        let (page, cursor) = btree.iter_from_cursor(&PageCursor::start(), 3);
+        assert_eq!(page, vec![(0, ()), (10, ()), (20, ())]);
+        let cursor = cursor.unwrap();
+
+        // Remove the last key returned, insert a key before the cursor and one after it.
+        btree.remove(&20);
+        btree.insert(15, ());
+        btree.insert(25, ());
+
+        // Iteration resumes after the last key returned, even though it was removed.
+        let (page, _) = btree.iter_from_cursor(&cursor, 3);
+        assert_eq!(page, vec![(25, ()), (30, ()), (40, ())]);
+
+        // The cursor survives a roundtrip through its serialized form.
+        let cursor = PageCursor::<u64>::from_bytes(cursor.to_bytes());
+        assert_eq!(btree.iter_from_cursor(&cursor, 1).0, vec![(25, ())]);
+    }
+
+    #[test]
+    fn iter_from_cursor_on_empty_map() {
+        let btree: BTreeMap<u64, u64, _> = BTreeMap::new(make_memory());
+        assert_eq!(
+            btree.iter_from_cursor(&PageCursor::start(), 10),
+            (vec![], None)
+        );
+    }
+
+    #[test]
+    fn iter_from_cursor_with_zero_limit() {
+        let mut btree = BTreeMap::new(make_memory());
+        btree.insert(1u64, 1u64);
+
+        let cursor = PageCursor::after(0);
+        assert_eq!(btree.iter_from_cursor(&cursor, 0), (vec![], Some(cursor)));
+    }
+
+    #[test]
+    fn update() {
+        for mut btree in [
+            BTreeMap::<u64, u64, _>::new(make_memory()),
+            BTreeMap::new_v1(make_memory()),
+        ] {
+            for i in 0..500u64 {
+                btree.insert(i, i);
+            }
+
+            for i in 0..500u64 {
+                assert!(btree.update(&i, |v| *v += 1));
+                assert_eq!(btree.update_with(&i, |v| *v * 2), Some((i + 1) * 2));
+            }
+            assert!(!btree.update(&500, |v| *v += 1));
+            assert_eq!(btree.update_with(&500, |v| *v), None);
+
+            // Reload the map to check that the updates were persisted.
+            let btree: BTreeMap<u64, u64, _> = BTreeMap::load(btree.into_memory());
+            assert_eq!(btree.len(), 500);
+            for i in 0..500u64 {
+                assert_eq!(btree.get(&i), Some(i + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn update_unbounded_values() {
+        let mut btree = BTreeMap::new(make_memory());
+        for i in 0..100u64 {
+            btree.insert(i, vec![i as u8]);
+        }
+
+        // Updates that change the size of the values.
+        for i in 0..100u64 {
+            assert!(btree.update(&i, |v| v.extend(vec![i as u8; 100])));
+        }
+        for i in 0..100u64 {
+            assert!(btree.update(&i, |v| v.truncate(2)));
+        }
+
+        let btree: BTreeMap<u64, Vec<u8>, _> = BTreeMap::load(btree.into_memory());
+        for i in 0..100u64 {
+            assert_eq!(btree.get(&i), Some(vec![i as u8; 2]));
+        }
+    }
+
+    #[test]
+    fn update_during_migration() {
+        let mut btree = BTreeMap::<u64, u64, _>::new_v1(make_memory());
+        for i in 0..500u64 {
+            btree.insert(i, i);
+        }
+
+        // Loading the map starts migrating it, and updates are applied to v1 nodes.
+        let mut btree: BTreeMap<u64, u64, _> = BTreeMap::load(btree.into_memory());
+        for i in 0..500u64 {
+            assert!(btree.update(&i, |v| *v += 1));
+        }
+        while !btree.migrate_step(10) {}
+
+        let btree: BTreeMap<u64, u64, _> = BTreeMap::load(btree.into_memory());
+        for i in 0..500u64 {
+            assert_eq!(btree.get(&i), Some(i + 1));
+        }
+    }
+
+    #[test]
+    fn prefix_iter() {
+        let mut btree = BTreeMap::new(make_memory());
+        for a in 0..10u64 {
+            for b in 0..20u64 {
+                btree.insert((a, b), a * b);
+            }
+        }
+
+        for a in 0..10u64 {
+            let entries: Vec<_> = btree.prefix_iter(&a).collect();
+            assert_eq!(
+                entries,
+                (0..20).map(|b| ((a, b), a * b)).collect::<Vec<_>>()
+            );
+        }
+
+        assert_eq!(btree.prefix_iter(&10).next(), None);
+    }
+
+    #[test]
+    fn prefix_iter_strings() {
+        let mut btree = BTreeMap::new(make_memory());
+        for key in ["a", "ab", "abc", "abd", "ac", "b"] {
+            btree.insert(key.to_string(), ());
+        }
+
+        let keys =
+            |prefix: &str| -> Vec<String> { btree.prefix_iter(prefix).map(|(k, _)| k).collect() };
+        assert_eq!(keys("ab"), vec!["ab", "abc", "abd"]);
+        assert_eq!(keys("a").len(), 5);
+        assert_eq!(keys(""), vec!["a", "ab", "abc", "abd", "ac", "b"]);
+        assert_eq!(keys("abe"), Vec::<String>::new());
+        assert_eq!(keys("c"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn prefix_iter_empty() {
+        let btree: BTreeMap<(u64, u64), u64, _> = BTreeMap::new(make_memory());
+        assert_eq!(btree.prefix_iter(&0).next(), None);
+    }
+
     #[test]
     #[should_panic(expected = "Key is too large. Expected <= 0 bytes, found 4 bytes")]
     fn panics_if_key_is_too_large() {
@@ -2526,17 +3478,288 @@ mod test {
 
         use std::io::prelude::*;
         let mut file =
-            std::fs::File::create(format!("dumps/btreemap_v{LAYOUT_VERSION}.dump")).unwrap();
+            std::fs::File::create(format!("dumps/btreemap_v{LAYOUT_VERSION_2}.dump")).unwrap();
         file.write_all(&mem.borrow()).unwrap();
     }
 
     #[test]
-    fn produces_layout_identical_to_layout_version_1_with_packed_headers() {
+    fn produces_layout_identical_to_layout_version_2() {
         let mem = make_memory();
         let mut btree = BTreeMap::init(mem.clone());
         assert_eq!(btree.insert(b(&[1, 2, 3]), b(&[4, 5, 6])), None);
         assert_eq!(btree.get(&b(&[1, 2, 3])), Some(b(&[4, 5, 6])));
 
+        let btreemap_v2 = include_bytes!("../dumps/btreemap_v2.dump");
+        assert_eq!(*mem.borrow(), btreemap_v2);
+    }
+
+    #[test]
+    fn init_v1_does_not_migrate_v1_maps() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::init_v1(mem.clone());
+        assert_eq!(btree.insert(b(&[1, 2, 3]), b(&[4, 5, 6])), None);
+
+        let btree: BTreeMap<Blob<10>, Blob<10>, _> = BTreeMap::init_v1(mem.clone());
+        assert!(matches!(btree.version, Version::V1(_)));
+        assert_eq!(btree.get(&b(&[1, 2, 3])), Some(b(&[4, 5, 6])));
+
+        // The memory is identical to that of a v1 map.
+        let btreemap_v1 = include_bytes!("../dumps/btreemap_v1_packed_headers.dump");
+        assert_eq!(*mem.borrow(), btreemap_v1);
+    }
+
+    #[test]
+    fn clearing_a_v1_map_keeps_the_v1_layout() {
+        let mem = make_memory();
+        let mut btree: BTreeMap<Blob<10>, u64, _> = BTreeMap::init_v1(mem.clone());
+        assert_eq!(btree.insert(b(&[1, 2, 3]), 456), None);
+
+        let mut btree = btree.clear();
+        assert!(btree.is_empty());
+        assert!(matches!(
+            btree.version,
+            Version::V1(DerivedPageSize {
+                max_key_size: 10,
+                max_value_size: 8,
+            })
+        ));
+        assert_eq!(btree.insert(b(&[7]), 8), None);
+
+        let btree: BTreeMap<Blob<10>, u64, _> = BTreeMap::init_v1(mem);
+        assert!(matches!(btree.version, Version::V1(_)));
+        assert_eq!(btree.get(&b(&[7])), Some(8));
+        assert_eq!(btree.len(), 1);
+    }
+
+    // Returns the number of nodes in the map that are still in the v1 layout.
+    fn count_v1_nodes<K: Storable + Ord + Clone, V: Storable, M: Memory>(
+        btree: &BTreeMap<K, V, M>,
+    ) -> u64 {
+        let mut count = 0;
+        let mut address = btree.allocator.first_chunk();
+        loop {
+            match btree.allocator.chunk_status(address) {
+                ChunkStatus::Allocated => {
+                    if Node::<K>::is_v1(address, btree.memory()) {
+                        count += 1;
+                    }
+                }
+                ChunkStatus::Free => {}
+                ChunkStatus::End => return count,
+            }
+            address = btree.allocator.next_chunk(address);
+        }
+    }
+
+    #[test]
+    fn loading_v1_dump_migrates_to_v2() {
+        let mem = make_memory();
+        let btreemap_v1 = include_bytes!("../dumps/btreemap_v1_packed_headers.dump");
+        *mem.borrow_mut() = btreemap_v1.to_vec();
+
+        let mut btree: BTreeMap<Blob<10>, Blob<10>, _> = BTreeMap::init(mem.clone());
+        assert_eq!(
+            btree.version,
+            Version::V2(PageSize::Derived(DerivedPageSize {
+                max_key_size: 10,
+                max_value_size: 10
+            }))
+        );
+        assert_eq!(btree.get(&b(&[1, 2, 3])), Some(b(&[4, 5, 6])));
+        assert_eq!(count_v1_nodes(&btree), 1);
+
+        assert!(btree.migrate_step(10));
+        assert_eq!(count_v1_nodes(&btree), 0);
+
+        let btree: BTreeMap<Blob<10>, Blob<10>, _> = BTreeMap::init(mem);
+        assert_eq!(btree.get(&b(&[1, 2, 3])), Some(b(&[4, 5, 6])));
+    }
+
+    #[test]
+    fn loading_v1_map_does_not_write_to_memory() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::init_v1(mem.clone());
+        for i in 0..100u64 {
+            btree.insert(i, i);
+        }
+        let v1_memory = mem.borrow().clone();
+
+        // Reads don't save the header of the migration.
+        let btree: BTreeMap<u64, u64, _> = BTreeMap::init(mem.clone());
+        assert_eq!(btree.get(&7), Some(7));
+        assert_eq!(btree.iter().count(), 100);
+        assert_eq!(*mem.borrow(), v1_memory);
+
+        // The header is saved before the first node is written, even by an update
+        // that leaves the rest of the header as it is.
+        let mut btree: BTreeMap<u64, u64, _> = BTreeMap::init(mem.clone());
+        assert!(btree.update(&7, |v| *v += 1));
+        assert_ne!(*mem.borrow(), v1_memory);
+        let btree: BTreeMap<u64, u64, _> = BTreeMap::init_v1(mem);
+        assert!(matches!(btree.version, Version::V2(_)));
+        assert_ne!(btree.migration_cursor, NULL);
+        assert_eq!(btree.get(&7), Some(8));
+    }
+
+    #[test]
+    fn migrate_step_migrates_all_nodes_incrementally() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::init_v1(mem.clone());
+        for i in 0..1_000u64 {
+            btree.insert(i, i);
+        }
+        let num_v1_nodes = count_v1_nodes(&btree);
+        assert_eq!(num_v1_nodes, btree.allocator.num_allocated_chunks());
+
+        let mut btree: BTreeMap<u64, u64, _> = BTreeMap::init(mem.clone());
+        let mut steps = 0;
+        while !btree.migrate_step(10) {
+            steps += 1;
+
+            // Progress is preserved when the map is reloaded.
+            btree = BTreeMap::init(btree.into_memory());
+        }
+        assert!(steps >= num_v1_nodes / 10);
+        assert_eq!(count_v1_nodes(&btree), 0);
+
+        // Migration is idempotent.
+        assert!(btree.migrate_step(10));
+
+        for i in 0..1_000u64 {
+            assert_eq!(btree.get(&i), Some(i));
+        }
+        assert_eq!(btree.iter().count(), 1_000);
+    }
+
+    #[test]
+    fn migration_with_interleaved_mutations() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::init_v1(mem.clone());
+        let mut std_map = std::collections::BTreeMap::new();
+        for i in 0..1_000u64 {
+            btree.insert(i, i);
+            std_map.insert(i, i);
+        }
+
+        // Mutations are interleaved with migration steps.
+        let mut btree: BTreeMap<u64, u64, _> = BTreeMap::init(mem);
+        let mut i = 0;
+        while !btree.migrate_step(3) {
+            assert_eq!(
+                btree.remove(&(i * 7 % 1_000)),
+                std_map.remove(&(i * 7 % 1_000))
+            );
+            assert_eq!(btree.insert(1_000 + i, i), std_map.insert(1_000 + i, i));
+            i += 1;
+        }
+        assert_eq!(count_v1_nodes(&btree), 0);
+
+        assert_eq!(btree.len(), std_map.len() as u64);
+        assert_eq!(
+            btree.iter().collect::<Vec<_>>(),
+            std_map.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn migrated_map_accepts_larger_types() {
+        let mem = make_memory();
+        let mut btree: BTreeMap<Blob<4>, Blob<4>, _> = BTreeMap::init_v1(mem.clone());
+        btree.insert(
+            Blob::try_from(&[1, 2, 3, 4][..]).unwrap(),
+            Blob::try_from(&[5, 6, 7, 8][..]).unwrap(),
+        );
+
+        // V2 nodes can store keys and values larger than the v1 max sizes.
+        let mut btree: BTreeMap<Blob<10>, Blob<10>, _> = BTreeMap::init(mem);
+        assert_eq!(btree.get(&b(&[1, 2, 3, 4])), Some(b(&[5, 6, 7, 8])));
+        for i in 0..100 {
+            btree.insert(b(&[i; 10]), b(&[i; 10]));
+        }
+        for i in 0..100 {
+            assert_eq!(btree.get(&b(&[i; 10])), Some(b(&[i; 10])));
+        }
+        assert_eq!(btree.get(&b(&[1, 2, 3, 4])), Some(b(&[5, 6, 7, 8])));
+    }
+
+    #[test]
+    fn new_maps_use_v2() {
+        let btree: BTreeMap<u64, Blob<10>, _> = BTreeMap::new(make_memory());
+        assert_eq!(
+            btree.version,
+            Version::V2(PageSize::Derived(DerivedPageSize {
+                max_key_size: 8,
+                max_value_size: 10
+            }))
+        );
+
+        let btree: BTreeMap<String, u64, _> = BTreeMap::new(make_memory());
+        assert_eq!(
+            btree.version,
+            Version::V2(PageSize::Value(DEFAULT_PAGE_SIZE))
+        );
+
+        // The page size is persisted in the header.
+        let btree: BTreeMap<String, u64, _> = BTreeMap::load(btree.into_memory());
+        assert_eq!(
+            btree.version,
+            Version::V2(PageSize::Value(DEFAULT_PAGE_SIZE))
+        );
+    }
+
+    #[test]
+    fn unbounded_keys_and_values() {
+        let mem = make_memory();
+        let mut btree: BTreeMap<String, Vec<u8>, _> = BTreeMap::init(mem.clone());
+
+        // Values larger than a page are stored in overflow pages.
+        for i in 0..500u32 {
+            let key = format!("key-{i}");
+            let value = vec![i as u8; (i as usize * 37) % 5_000];
+            assert_eq!(btree.insert(key, value), None);
+        }
+
+        // Reload the map and verify its contents.
+        let mut btree: BTreeMap<String, Vec<u8>, _> = BTreeMap::init(mem);
+        assert_eq!(btree.len(), 500);
+        for i in 0..500u32 {
+            let value = vec![i as u8; (i as usize * 37) % 5_000];
+            assert_eq!(btree.get(&format!("key-{i}")), Some(value));
+        }
+
+        // Overwrite the values with smaller ones.
+        for i in 0..500u32 {
+            assert!(btree.insert(format!("key-{i}"), vec![1]).is_some());
+            assert_eq!(btree.get(&format!("key-{i}")), Some(vec![1]));
+        }
+    }
+
+    #[test]
+    fn removing_unbounded_entries_deallocates_overflow_pages() {
+        let mut btree: BTreeMap<String, Vec<u8>, _> = BTreeMap::new(make_memory());
+
+        for i in 0..300u32 {
+            btree.insert(format!("{i:05}"), vec![0; 3 * DEFAULT_PAGE_SIZE as usize]);
+        }
+
+        for i in 0..300u32 {
+            assert_eq!(
+                btree.remove(&format!("{i:05}")),
+                Some(vec![0; 3 * DEFAULT_PAGE_SIZE as usize])
+            );
+        }
+
+        assert!(btree.is_empty());
+        assert_eq!(btree.allocator.num_allocated_chunks(), 0);
+    }
+
+    #[test]
+    fn produces_layout_identical_to_layout_version_1_with_packed_headers() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::init_v1(mem.clone());
+        assert_eq!(btree.insert(b(&[1, 2, 3]), b(&[4, 5, 6])), None);
+        assert_eq!(btree.get(&b(&[1, 2, 3])), Some(b(&[4, 5, 6])));
+
         let btreemap_v1 = include_bytes!("../dumps/btreemap_v1_packed_headers.dump");
         assert_eq!(*mem.borrow(), btreemap_v1);
     }
@@ -2566,13 +3789,17 @@ mod test {
         let packed_mem = make_memory();
         crate::write_struct(&packed_header, Address::from(0), &packed_mem);
 
-        let v1_header = BTreeHeaderV1 {
-            magic: *MAGIC,
-            version: LAYOUT_VERSION,
-            max_key_size: 0x12345678,
-            max_value_size: 0x87654321,
+        let v1_header = BTreeHeader {
+            version: Version::V1(DerivedPageSize {
+                max_key_size: 0x12345678,
+                max_value_size: 0x87654321,
+            }),
             root_addr: Address::from(0xDEADBEEF),
             length: 0xA1B2D3C4,
+            migration_cursor: NULL,
+            clear_root: NULL,
+            has_counts: false,
+            branching_factor: node::B,
         };
 
         let v1_mem = make_memory();
@@ -2582,10 +3809,18 @@ mod test {
 
         let packed_header: BTreePackedHeader = crate::read_struct(Address::from(0), &v1_mem);
         let v1_header = BTreeMap::<Vec<_>, Vec<_>, RefCell<Vec<_>>>::read_header(&v1_mem);
-        assert!(packed_header.magic == v1_header.magic);
-        assert!(packed_header.version == v1_header.version);
-        assert!(packed_header.max_key_size == v1_header.max_key_size);
-        assert!(packed_header.max_value_size == v1_header.max_value_size);
+        assert!(packed_header.magic == *MAGIC);
+        assert!(packed_header.version == LAYOUT_VERSION);
+        match v1_header.version {
+            Version::V1(DerivedPageSize {
+                max_key_size,
+                max_value_size,
+            }) => {
+                assert!(packed_header.max_key_size == max_key_size);
+                assert!(packed_header.max_value_size == max_value_size);
+            }
+            _ => panic!("Unexpected version."),
+        };
         assert!(packed_header.root_addr == v1_header.root_addr);
         assert!(packed_header.length == v1_header.length);
     }
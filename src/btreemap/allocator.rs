@@ -223,6 +223,36 @@ impl<M: Memory> Allocator<M> {
         self.num_allocated_chunks
     }
 
+    /// Returns the address of the first chunk in memory.
+    ///
+    /// As with `allocate`, the address returned is that of the chunk's user data.
+    pub fn first_chunk(&self) -> Address {
+        self.header_addr + AllocatorHeader::size() + ChunkHeader::size()
+    }
+
+    /// Returns the address of the chunk that follows the given chunk in memory.
+    pub fn next_chunk(&self, address: Address) -> Address {
+        address + self.chunk_size()
+    }
+
+    /// Returns the status of the chunk at the given address.
+    ///
+    /// Chunks are laid out contiguously in memory, so starting from `first_chunk`
+    /// and following `next_chunk` visits every chunk the allocator has ever created,
+    /// until `ChunkStatus::End` is reached.
+    pub fn chunk_status(&self, address: Address) -> ChunkStatus {
+        let chunk = ChunkHeader::load(address - ChunkHeader::size(), &self.memory);
+        if chunk.allocated {
+            ChunkStatus::Allocated
+        } else if chunk.next == NULL {
+            // The free list always ends with the chunk that follows all the chunks
+            // that have been created so far.
+            ChunkStatus::End
+        } else {
+            ChunkStatus::Free
+        }
+    }
+
     // The full size of a chunk, which is the size of the header + the `allocation_size` that's
     // available to the user.
     fn chunk_size(&self) -> Bytes {
@@ -242,6 +272,17 @@ impl<M: Memory> Allocator<M> {
     }
 }
 
+/// The status of a chunk of memory managed by the allocator.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkStatus {
+    /// The chunk is allocated.
+    Allocated,
+    /// The chunk has been deallocated and is in the free list.
+    Free,
+    /// The chunk has never been allocated. No chunks exist after it.
+    End,
+}
+
 #[derive(Debug)]
 #[repr(C, packed)]
 struct ChunkHeader {
@@ -420,6 +461,29 @@ mod test {
         assert_eq!(allocator.free_list_head, chunk_addr_3 + allocation_size);
     }
 
+    #[test]
+    fn chunk_status() {
+        let mem = make_memory();
+        let allocation_size = Bytes::from(16u64);
+        let mut allocator = Allocator::new(mem, Address::from(0), allocation_size);
+
+        let chunk_1 = allocator.allocate();
+        let chunk_2 = allocator.allocate();
+        let chunk_3 = allocator.allocate();
+        allocator.deallocate(chunk_2);
+
+        assert_eq!(allocator.first_chunk(), chunk_1);
+        assert_eq!(allocator.next_chunk(chunk_1), chunk_2);
+        assert_eq!(allocator.next_chunk(chunk_2), chunk_3);
+        assert_eq!(allocator.chunk_status(chunk_1), ChunkStatus::Allocated);
+        assert_eq!(allocator.chunk_status(chunk_2), ChunkStatus::Free);
+        assert_eq!(allocator.chunk_status(chunk_3), ChunkStatus::Allocated);
+        assert_eq!(
+            allocator.chunk_status(allocator.next_chunk(chunk_3)),
+            ChunkStatus::End
+        );
+    }
+
     #[test]
     #[should_panic]
     fn deallocate_free_chunk() {
@@ -0,0 +1,363 @@
+//! Bulk loading of sorted entries.
+//!
+//! Rather than inserting entries one at a time, which splits nodes constantly and
+//! writes each node many times, entries are appended to the rightmost node of every
+//! level of the tree. Once a node is full it is saved, and the next entry becomes the
+//! separator between that node and its right sibling in the level above.
+//!
+//! When the input is exhausted, the rightmost nodes along the right spine of the tree
+//! may have fewer entries than required. These are fixed from the top down by moving
+//! entries in from their (full) left siblings.
+use super::{
+    node::{Entry as NodeEntry, Node, NodeType},
+    BTreeMap,
+};
+use crate::{types::NULL, Address, Memory, Storable};
+use std::mem;
+
+/// An error returned when bulk loading entries into the map.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BulkLoadError {
+    /// The keys aren't in strictly ascending order. `index` is the position in the input
+    /// of the first entry whose key isn't greater than the key before it.
+    NotSorted { index: u64 },
+}
+
+impl std::fmt::Display for BulkLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotSorted { index } => {
+                write!(
+                    f,
+                    "BulkLoadError::NotSorted Expected keys in strictly ascending order, but the key at index {index} is not greater than the key before it."
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BulkLoadError {}
+
+impl<K, V, M> BTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Creates a new map from entries sorted by key in strictly ascending order.
+    ///
+    /// This is much faster than inserting the entries one at a time, as nodes are
+    /// built bottom-up and written (almost always) once.
+    ///
+    /// Returns an error if the keys aren't in strictly ascending order.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let map: BTreeMap<u64, u64, _> =
+    ///     BTreeMap::from_sorted_iter(DefaultMemoryImpl::default(), (0..1000).map(|i| (i, i * 2)))
+    ///         .unwrap();
+    /// assert_eq!(map.len(), 1000);
+    /// assert_eq!(map.get(&10), Some(20));
+    /// ```
+    pub fn from_sorted_iter<I>(memory: M, iter: I) -> Result<Self, BulkLoadError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut map = Self::new(memory);
+        map.extend_sorted(iter)?;
+        Ok(map)
+    }
+
+    /// Inserts entries sorted by key in strictly ascending order into the map.
+    ///
+    /// If the map is empty, the entries are bulk loaded as in [`BTreeMap::from_sorted_iter`].
+    /// Otherwise, they're inserted one at a time.
+    ///
+    /// Returns an error if the keys aren't in strictly ascending order, in which case
+    /// only the entries before the offending entry are inserted.
+    pub fn extend_sorted<I>(&mut self, iter: I) -> Result<(), BulkLoadError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        if self.root_addr != NULL {
+            // The map isn't empty. Insert the entries one at a time.
+            let mut last_key: Option<K> = None;
+            for (index, (key, value)) in iter.into_iter().enumerate() {
+                if let Some(last_key) = &last_key {
+                    if &key <= last_key {
+                        return Err(BulkLoadError::NotSorted {
+                            index: index as u64,
+                        });
+                    }
+                }
+                last_key = Some(key.clone());
+                self.insert(key, value);
+            }
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        let mut last_key: Option<K> = None;
+        let entries = iter
+            .into_iter()
+            .enumerate()
+            .map_while(|(index, (key, value))| {
+                if let Some(last_key) = &last_key {
+                    if &key <= last_key {
+                        result = Err(BulkLoadError::NotSorted {
+                            index: index as u64,
+                        });
+                        return None;
+                    }
+                }
+
+                Self::assert_key_size(&key);
+                last_key = Some(key.clone());
+                Some((key, Self::encode_value(&value)))
+            });
+
+        self.root_addr = self.bulk_build(entries);
+        self.update_counts();
+        self.save();
+        result
+    }
+
+    // Builds a tree out of entries sorted by key, returning the address of its root, or
+    // NULL if there are no entries. The entries are added to the length of the map.
+    pub(super) fn bulk_build<I>(&mut self, entries: I) -> Address
+    where
+        I: Iterator<Item = NodeEntry<K>>,
+    {
+        // The rightmost node of every level of the tree, starting from the leaves.
+        let mut levels = vec![self.allocate_node(NodeType::Leaf)];
+
+        for entry in entries {
+            self.bulk_push_entry(&mut levels, entry);
+            self.length += 1;
+        }
+
+        self.bulk_finish(levels)
+    }
+
+    // Appends an entry to the rightmost leaf.
+    fn bulk_push_entry(&mut self, levels: &mut Vec<Node<K>>, entry: NodeEntry<K>) {
+        if !levels[0].is_full() {
+            levels[0].push_entry(entry);
+            return;
+        }
+
+        // The leaf is full. Save it and use the entry as the separator between the leaf
+        // and the next one.
+        let new_leaf = self.allocate_node(NodeType::Leaf);
+        let mut leaf = mem::replace(&mut levels[0], new_leaf);
+        self.save_node(&mut leaf);
+        self.bulk_push_child(levels, 1, leaf.address(), entry);
+    }
+
+    // Appends a child, followed by a separator, to the rightmost node at the given level.
+    fn bulk_push_child(
+        &mut self,
+        levels: &mut Vec<Node<K>>,
+        level: usize,
+        child: Address,
+        separator: NodeEntry<K>,
+    ) {
+        if level == levels.len() {
+            // This is a new level at the top of the tree.
+            levels.push(self.allocate_node(NodeType::Internal));
+        }
+
+        levels[level].push_child(child);
+        if !levels[level].is_full() {
+            levels[level].push_entry(separator);
+            return;
+        }
+
+        // The node is full and has all its children. Save it and use the separator
+        // between it and the next node in the level above.
+        let new_node = self.allocate_node(NodeType::Internal);
+        let mut node = mem::replace(&mut levels[level], new_node);
+        self.save_node(&mut node);
+        self.bulk_push_child(levels, level + 1, node.address(), separator);
+    }
+
+    // Completes the tree by attaching the rightmost nodes of each level to their parents,
+    // rebalancing them as needed, and saving them. Returns the address of the root.
+    fn bulk_finish(&mut self, mut levels: Vec<Node<K>>) -> Address {
+        let top = levels.len() - 1;
+
+        // The rightmost node of every level is the last child of the rightmost node of the
+        // level above.
+        for level in 0..top {
+            let address = levels[level].address();
+            levels[level + 1].push_child(address);
+        }
+
+        // The rightmost nodes may not have enough entries. Starting from the top, move
+        // entries into them from their left siblings, which are full.
+        for level in (0..top).rev() {
+            let (lower, upper) = levels.split_at_mut(level + 1);
+            let node = &mut lower[level];
+            let parent = &mut upper[0];
+
+            if !node.is_underfull() {
+                continue;
+            }
+
+            let mut left_sibling = self.load_node(parent.child(parent.children_len() - 2));
+            while node.is_underfull() {
+                // Rotate an entry from the left sibling through the parent into the node.
+                let separator = parent.pop_entry(self.memory()).unwrap();
+                let entry = left_sibling.pop_entry(self.memory()).unwrap();
+                parent.push_entry(entry);
+                node.insert_entry(0, separator);
+
+                if node.node_type() == NodeType::Internal {
+                    let child = left_sibling.pop_child().unwrap();
+                    node.insert_child(0, child);
+                }
+            }
+            self.save_node(&mut left_sibling);
+        }
+
+        if levels[top].entries_len() == 0 {
+            // No entries were loaded. The tree consists of a single empty leaf.
+            debug_assert_eq!(top, 0);
+            self.deallocate_node(levels.pop().unwrap());
+            return NULL;
+        }
+
+        let root = levels[top].address();
+        for mut node in levels {
+            self.save_node(&mut node);
+        }
+        root
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+        Rc::new(RefCell::new(Vec::new()))
+    }
+
+    // Checks that all the non-root nodes have enough entries and that all the leaves are
+    // at the same depth. Returns the depth of the leaves.
+    fn check_structure<K: Storable + Ord + Clone, V: Storable, M: Memory>(
+        map: &BTreeMap<K, V, M>,
+        address: Address,
+    ) -> usize {
+        let node = map.load_node(address);
+        assert!(address == map.root_addr || !node.is_underfull());
+        match node.node_type() {
+            NodeType::Leaf => 0,
+            NodeType::Internal => {
+                assert_eq!(node.children_len(), node.entries_len() + 1);
+                let depths: Vec<_> = (0..node.children_len())
+                    .map(|i| check_structure(map, node.child(i)))
+                    .collect();
+                assert!(depths.iter().all(|d| *d == depths[0]));
+                depths[0] + 1
+            }
+        }
+    }
+
+    #[test]
+    fn from_sorted_iter() {
+        for n in [
+            0, 1, 10, 11, 12, 21, 22, 23, 100, 132, 133, 1000, 1453, 5000,
+        ] {
+            let map =
+                BTreeMap::from_sorted_iter(make_memory(), (0..n).map(|i| (i, i + 1))).unwrap();
+
+            assert_eq!(map.len(), n);
+            assert_eq!(
+                map.iter().collect::<Vec<_>>(),
+                (0..n).map(|i| (i, i + 1)).collect::<Vec<_>>()
+            );
+            if n > 0 {
+                check_structure(&map, map.root_addr);
+            }
+
+            // The map can be reloaded and modified after being bulk loaded.
+            let mut map: BTreeMap<u64, u64, _> = BTreeMap::load(map.into_memory());
+            for i in 0..n {
+                assert_eq!(map.get(&i), Some(i + 1));
+            }
+            map.insert(n, n + 1);
+            for i in (0..=n).step_by(2) {
+                assert_eq!(map.remove(&i), Some(i + 1));
+            }
+            assert_eq!(
+                map.iter().collect::<Vec<_>>(),
+                (1..=n).step_by(2).map(|i| (i, i + 1)).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn from_sorted_iter_with_v1_layout() {
+        let mut map = BTreeMap::new_v1(make_memory());
+        map.extend_sorted((0..1000u64).map(|i| (i, i))).unwrap();
+        check_structure(&map, map.root_addr);
+
+        let map: BTreeMap<u64, u64, _> = BTreeMap::init_v1(map.into_memory());
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            (0..1000).map(|i| (i, i)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rejects_unsorted_input() {
+        let mut map = BTreeMap::new(make_memory());
+        assert_eq!(
+            map.extend_sorted([(1u64, 1u64), (3, 3), (2, 2), (4, 4)]),
+            Err(BulkLoadError::NotSorted { index: 2 })
+        );
+
+        // The entries before the unsorted entry are inserted.
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(1, 1), (3, 3)]);
+        check_structure(&map, map.root_addr);
+    }
+
+    #[test]
+    fn rejects_duplicate_keys() {
+        let result = BTreeMap::<u64, u64, _>::from_sorted_iter(make_memory(), [(1, 1), (1, 2)]);
+        assert_eq!(result.err(), Some(BulkLoadError::NotSorted { index: 1 }));
+    }
+
+    #[test]
+    fn extend_sorted_non_empty_map() {
+        let mut map = BTreeMap::new(make_memory());
+        map.insert(5u64, 5u64);
+
+        map.extend_sorted((0..10).map(|i| (i, i * 10))).unwrap();
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            (0..10).map(|i| (i, i * 10)).collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            map.extend_sorted([(20, 20), (10, 10)]),
+            Err(BulkLoadError::NotSorted { index: 1 })
+        );
+        assert_eq!(map.get(&20), Some(20));
+        assert_eq!(map.get(&10), None);
+    }
+
+    #[test]
+    fn bulk_loading_unbounded_entries() {
+        let entries: Vec<_> = (0..500u64)
+            .map(|i| (format!("{i:05}"), vec![i as u8; i as usize]))
+            .collect();
+        let map = BTreeMap::from_sorted_iter(make_memory(), entries.clone()).unwrap();
+        check_structure(&map, map.root_addr);
+        assert_eq!(map.iter().collect::<Vec<_>>(), entries);
+    }
+}
@@ -0,0 +1,225 @@
+use super::{
+    node::{Node, NodeType, Version},
+    BTreeMap,
+};
+use crate::{types::NULL, Memory, Storable};
+
+impl<K, V, M> BTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Clears the map in bounded steps, deallocating up to `max_nodes` nodes per call.
+    ///
+    /// The first call empties the map right away by detaching its tree. The nodes of the
+    /// detached tree are then deallocated by this and subsequent calls, e.g. in a timer,
+    /// with progress persisted across calls and upgrades. The map can be used as usual in
+    /// the meantime: entries inserted before the clearing is complete are kept, and are
+    /// only removed by calling this method again afterwards.
+    ///
+    /// Calling this method on a V1 map loaded with [`BTreeMap::init_v1`] starts its
+    /// migration to V2, as the V1 layout has no room to persist the progress. Use
+    /// [`BTreeMap::clear`] to clear a V1 map while keeping its layout.
+    ///
+    /// Returns `true` if all the detached nodes have been deallocated, `false` otherwise.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// assert!(!map.clear_step(10));
+    /// assert!(map.is_empty());
+    ///
+    /// while !map.clear_step(10) {}
+    /// ```
+    pub fn clear_step(&mut self, max_nodes: u64) -> bool {
+        if let Version::V1(page_size) = self.version {
+            self.start_migration(page_size);
+        }
+
+        if self.clear_root == NULL && self.root_addr != NULL {
+            // Detach the tree, leaving the map empty.
+            self.clear_root = self.root_addr;
+            self.root_addr = NULL;
+            self.length = 0;
+        }
+
+        // The path from the root of the detached tree to the node being deallocated, along
+        // with whether each node was modified. Nodes are deallocated right to left, and
+        // each node is deallocated after its children.
+        let mut path: Vec<(Node<K>, bool)> = vec![];
+
+        let mut deallocated = 0;
+        while deallocated < max_nodes && self.clear_root != NULL {
+            if path.is_empty() {
+                path.push((self.load_node(self.clear_root), false));
+            }
+
+            let (node, _) = path.last().unwrap();
+            if node.node_type() == NodeType::Leaf {
+                let (node, _) = path.pop().unwrap();
+                self.deallocate_node(node);
+                deallocated += 1;
+
+                // Remove the leaf, along with an entry, from its parent.
+                match path.last_mut() {
+                    Some((parent, modified)) => {
+                        parent.pop_child();
+                        parent.pop_entry(self.memory());
+                        *modified = true;
+                    }
+                    None => self.clear_root = NULL,
+                }
+            } else if node.entries_len() == 0 {
+                // The node has a single child left, which takes its place.
+                let (node, _) = path.pop().unwrap();
+                let child = node.child(0);
+                self.deallocate_node(node);
+                deallocated += 1;
+
+                match path.last_mut() {
+                    Some((parent, modified)) => {
+                        parent.pop_child();
+                        parent.push_child(child);
+                        *modified = true;
+                    }
+                    None => self.clear_root = child,
+                }
+            } else {
+                let child = self.load_node(node.child(node.children_len() - 1));
+                path.push((child, false));
+            }
+        }
+
+        for (mut node, modified) in path {
+            if modified {
+                self.write_node(&mut node);
+            }
+        }
+
+        self.save();
+        self.clear_root == NULL
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+        Rc::new(RefCell::new(Vec::new()))
+    }
+
+    #[test]
+    fn clear_step() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..10_000u64 {
+            map.insert(i, i);
+        }
+
+        let mut allocated = map.allocator.num_allocated_chunks();
+        assert!(!map.clear_step(0));
+        assert!(map.is_empty());
+        assert_eq!(map.get(&0), None);
+        assert_eq!(map.iter().next(), None);
+        assert_eq!(map.allocator.num_allocated_chunks(), allocated);
+
+        let mut steps = 0;
+        while !map.clear_step(100) {
+            let new_allocated = map.allocator.num_allocated_chunks();
+            assert_eq!(allocated - new_allocated, 100);
+            allocated = new_allocated;
+            steps += 1;
+        }
+        assert!(steps > 10);
+        assert_eq!(map.allocator.num_allocated_chunks(), 0);
+        assert_eq!(map.clear_root, NULL);
+
+        // Clearing an empty map is a no-op.
+        assert!(map.clear_step(100));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn map_is_usable_while_clearing() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..1000u64 {
+            map.insert(i, i);
+        }
+        assert!(!map.clear_step(20));
+
+        // Entries inserted while clearing are kept.
+        let mut done = false;
+        for i in 500..1500u64 {
+            map.insert(i, i + 1);
+            if !done {
+                done = map.clear_step(1);
+            }
+        }
+        assert!(done);
+        for i in 500..1000u64 {
+            assert_eq!(map.remove(&i), Some(i + 1));
+        }
+
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            (1000..1500).map(|i| (i, i + 1)).collect::<Vec<_>>()
+        );
+
+        // Clearing again removes them.
+        assert!(!map.clear_step(0));
+        assert!(map.is_empty());
+        while !map.clear_step(20) {}
+        assert_eq!(map.allocator.num_allocated_chunks(), 0);
+    }
+
+    #[test]
+    fn clear_step_is_resumable_after_reload() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..1000u64 {
+            map.insert(i, i);
+        }
+        map.clear_step(50);
+
+        let mut map: BTreeMap<u64, u64, _> = BTreeMap::load(map.into_memory());
+        assert!(map.is_empty());
+        assert_ne!(map.clear_root, NULL);
+
+        while !map.clear_step(50) {}
+        assert_eq!(map.allocator.num_allocated_chunks(), 0);
+    }
+
+    #[test]
+    fn clear_step_deallocates_overflow_pages() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..200u64 {
+            map.insert(i, vec![i as u8; 5000]);
+        }
+
+        while !map.clear_step(3) {}
+        assert_eq!(map.allocator.num_allocated_chunks(), 0);
+    }
+
+    #[test]
+    fn clear_step_migrates_v1_maps() {
+        let mut map = BTreeMap::new_v1(make_memory());
+        for i in 0..1000u64 {
+            map.insert(i, i);
+        }
+
+        while !map.clear_step(10) {}
+        assert!(matches!(map.version, Version::V2(_)));
+        assert_eq!(map.allocator.num_allocated_chunks(), 0);
+
+        map.insert(1, 1);
+        let map: BTreeMap<u64, u64, _> = BTreeMap::load(map.into_memory());
+        assert_eq!(map.get(&1), Some(1));
+    }
+}
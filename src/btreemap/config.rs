@@ -0,0 +1,171 @@
+use super::node::{B, MAX_BRANCHING_FACTOR, MIN_BRANCHING_FACTOR};
+
+/// The configuration of a new [`BTreeMap`](crate::BTreeMap).
+///
+/// See [`BTreeMap::new_with_config`](crate::BTreeMap::new_with_config).
+///
+/// ```
+/// use ic_stable_structures::btreemap::Config;
+/// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+///
+/// let config = Config::default().branching_factor(32);
+/// let mut map: BTreeMap<u64, u64, _> =
+///     BTreeMap::new_with_config(DefaultMemoryImpl::default(), config);
+/// map.insert(1, 2);
+/// assert_eq!(map.get(&1), Some(2));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub(super) branching_factor: usize,
+    pub(super) counts: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            branching_factor: B,
+            counts: false,
+        }
+    }
+}
+
+impl Config {
+    /// Sets the branching factor of the tree, which defaults to 6.
+    ///
+    /// The branching factor is the minimum degree of the tree: every node other than the
+    /// root holds between `branching_factor - 1` and `2 * branching_factor - 1` entries.
+    /// A larger branching factor makes the tree shallower, so fewer nodes are read from
+    /// memory per lookup, but makes each node larger and more expensive to rewrite.
+    ///
+    /// PRECONDITION: `2 <= branching_factor <= 32768`
+    pub fn branching_factor(mut self, branching_factor: usize) -> Self {
+        assert!(
+            (MIN_BRANCHING_FACTOR..=MAX_BRANCHING_FACTOR).contains(&branching_factor),
+            "branching_factor must be between {MIN_BRANCHING_FACTOR} and {MAX_BRANCHING_FACTOR}"
+        );
+        self.branching_factor = branching_factor;
+        self
+    }
+
+    /// Sets whether the internal nodes store the number of entries in the subtree of
+    /// each of their children, which defaults to false.
+    ///
+    /// See [`BTreeMap::new_with_counts`](crate::BTreeMap::new_with_counts).
+    pub fn counts(mut self, counts: bool) -> Self {
+        self.counts = counts;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::btreemap::{node::NodeType, BTreeMap};
+    use crate::{types::NULL, Address, Memory, Storable};
+    use proptest::collection::vec as pvec;
+    use proptest::prelude::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap as StdBTreeMap;
+    use std::rc::Rc;
+
+    fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+        Rc::new(RefCell::new(Vec::new()))
+    }
+
+    // Checks that the nodes have a valid number of entries and that all the leaves are
+    // at the same depth. Returns the depth of the leaves.
+    fn check_structure<K: Storable + Ord + Clone, V: Storable, M: Memory>(
+        map: &BTreeMap<K, V, M>,
+        address: Address,
+    ) -> usize {
+        let node = map.load_node(address);
+        assert!(node.entries_len() > 0);
+        assert!(node.entries_len() < 2 * map.branching_factor);
+        assert!(address == map.root_addr || !node.is_underfull());
+        match node.node_type() {
+            NodeType::Leaf => 0,
+            NodeType::Internal => {
+                let depths: Vec<_> = (0..node.children_len())
+                    .map(|i| check_structure(map, node.child(i)))
+                    .collect();
+                assert!(depths.iter().all(|d| *d == depths[0]));
+                depths[0] + 1
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20))]
+        #[test]
+        fn matches_std_btreemap(
+            branching_factor in 2..64usize,
+            counts in any::<bool>(),
+            ops in pvec((any::<bool>(), 0..500u32), 0..2000),
+        ) {
+            let config = Config::default().branching_factor(branching_factor).counts(counts);
+            let mut map = BTreeMap::new_with_config(make_memory(), config);
+            let mut std_map = StdBTreeMap::new();
+            for (insert, key) in ops {
+                if insert {
+                    prop_assert_eq!(map.insert(key, key), std_map.insert(key, key));
+                } else {
+                    prop_assert_eq!(map.remove(&key), std_map.remove(&key));
+                }
+            }
+
+            // The configuration is kept when the map is reloaded.
+            let map: BTreeMap<u32, u32, _> = BTreeMap::init(map.into_memory());
+            prop_assert_eq!(map.branching_factor, branching_factor);
+            prop_assert_eq!(map.has_counts, counts);
+            prop_assert_eq!(map.iter().collect::<Vec<_>>(), std_map.into_iter().collect::<Vec<_>>());
+            if map.root_addr != NULL {
+                check_structure(&map, map.root_addr);
+            }
+        }
+    }
+
+    #[test]
+    fn larger_branching_factor_makes_tree_shallower() {
+        let mut map = BTreeMap::new(make_memory());
+        let config = Config::default().branching_factor(64);
+        let mut wide_map = BTreeMap::new_with_config(make_memory(), config);
+        for i in 0..10_000u64 {
+            map.insert(i, i);
+            wide_map.insert(i, i);
+        }
+
+        assert_eq!(check_structure(&map, map.root_addr), 4);
+        assert_eq!(check_structure(&wide_map, wide_map.root_addr), 2);
+    }
+
+    #[test]
+    fn clear_keeps_config() {
+        let config = Config::default().branching_factor(3).counts(true);
+        let mut map = BTreeMap::new_with_config(make_memory(), config);
+        map.insert(1u64, 1u64);
+
+        let map = map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.branching_factor, 3);
+        assert!(map.has_counts);
+    }
+
+    #[test]
+    fn default_branching_factor_is_stored_as_zero() {
+        let map: BTreeMap<u64, u64, _> = BTreeMap::new(make_memory());
+        let mut buf = [0; 2];
+        map.memory().read(45, &mut buf);
+        assert_eq!(buf, [0, 0]);
+
+        let config = Config::default().branching_factor(300);
+        let map: BTreeMap<u64, u64, _> = BTreeMap::new_with_config(make_memory(), config);
+        map.memory().read(45, &mut buf);
+        assert_eq!(u16::from_le_bytes(buf), 300);
+    }
+
+    #[test]
+    #[should_panic(expected = "branching_factor must be between 2 and 32768")]
+    fn branching_factor_must_be_at_least_two() {
+        Config::default().branching_factor(1);
+    }
+}
@@ -0,0 +1,429 @@
+//! Order statistics on maps whose internal nodes store the counts of their children's
+//! subtrees. See [`BTreeMap::new_with_counts`].
+//!
+//! The counts are updated at the end of every operation that modifies the tree. While
+//! the tree is being modified, the first key of every saved node is recorded. Since a
+//! node is found by searching for any of its keys, the paths to the recorded keys cover
+//! all the modified nodes, along with their ancestors, whose counts may have changed.
+//! These paths are then visited bottom-up, recomputing the counts of their children.
+//!
+//! Children that were moved between nodes (e.g. when rebalancing) have an unknown count
+//! until then. Their subtrees are otherwise unmodified, so their counts are computed from
+//! the counts stored in their own nodes.
+use super::{
+    node::{Node, NodeType, UNKNOWN_COUNT},
+    BTreeMap,
+};
+use crate::{types::NULL, Address, Memory, Storable};
+use std::borrow::Cow;
+use std::ops::{Bound, RangeBounds};
+
+impl<K, V, M> BTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Returns the number of entries whose keys are smaller than the given key, i.e. the
+    /// index of the key in the sorted order of the keys if it's in the map.
+    ///
+    /// Runs in `O(log n)` if the map was created with [`BTreeMap::new_with_counts`], and
+    /// in `O(n)` otherwise.
+    pub fn rank(&self, key: &K) -> u64 {
+        if !self.has_counts {
+            return self.range(..key.clone()).count() as u64;
+        }
+
+        self.count_before(key, false)
+    }
+
+    /// Returns the entry at the given index in the sorted order of the keys, if any.
+    ///
+    /// Runs in `O(log n)` if the map was created with [`BTreeMap::new_with_counts`], and
+    /// in `O(n)` otherwise.
+    pub fn select(&self, index: u64) -> Option<(K, V)> {
+        if !self.has_counts {
+            return self.iter().nth(index as usize);
+        }
+
+        let (node, idx) = self.select_node(index)?;
+        let (key, encoded_value) = node.entry(idx, self.memory());
+        Some((key, V::from_bytes(Cow::Owned(encoded_value))))
+    }
+
+    /// Returns the number of entries whose keys are in the given range.
+    ///
+    /// Runs in `O(log n)` if the map was created with [`BTreeMap::new_with_counts`], and
+    /// in `O(n)` otherwise.
+    pub fn range_count(&self, key_range: impl RangeBounds<K>) -> u64 {
+        if !self.has_counts {
+            return self
+                .range((
+                    key_range.start_bound().cloned(),
+                    key_range.end_bound().cloned(),
+                ))
+                .count() as u64;
+        }
+
+        let start = self.start_position(key_range.start_bound());
+        let end = match key_range.end_bound() {
+            Bound::Included(key) => self.count_before(key, true),
+            Bound::Excluded(key) => self.count_before(key, false),
+            Bound::Unbounded => self.length,
+        };
+        end.saturating_sub(start)
+    }
+
+    // Returns the index of the first entry after the given start bound.
+    //
+    // PRECONDITION: the map has counts.
+    pub(super) fn start_position(&self, bound: Bound<&K>) -> u64 {
+        match bound {
+            Bound::Included(key) => self.count_before(key, false),
+            Bound::Excluded(key) => self.count_before(key, true),
+            Bound::Unbounded => 0,
+        }
+    }
+
+    // Returns the key at the given index in the sorted order of the keys, if any.
+    //
+    // PRECONDITION: the map has counts.
+    pub(super) fn select_key(&self, index: u64) -> Option<K> {
+        self.select_node(index)
+            .map(|(node, idx)| node.key(idx).clone())
+    }
+
+    // Returns the number of entries whose keys are smaller than the given key, or smaller
+    // than or equal to it if `inclusive` is true.
+    fn count_before(&self, key: &K, inclusive: bool) -> u64 {
+        let mut count = 0;
+        let mut address = self.root_addr;
+        while address != NULL {
+            let node = self.load_node(address);
+            let (idx, found) = match node.search(key) {
+                Ok(idx) => (idx, true),
+                Err(idx) => (idx, false),
+            };
+
+            // The entries before `idx` and the subtrees on their left are all smaller.
+            count += idx as u64;
+            if node.node_type() == NodeType::Internal {
+                count += (0..idx).map(|i| node.child_count(i)).sum::<u64>();
+            }
+
+            if found {
+                // The subtree on the left of the key is smaller too.
+                if node.node_type() == NodeType::Internal {
+                    count += node.child_count(idx);
+                }
+                return count + inclusive as u64;
+            }
+
+            address = match node.node_type() {
+                NodeType::Internal => node.child(idx),
+                NodeType::Leaf => NULL,
+            };
+        }
+        count
+    }
+
+    // Returns the node holding the entry at the given index in the sorted order of the
+    // keys, along with the entry's index in the node.
+    fn select_node(&self, mut index: u64) -> Option<(Node<K>, usize)> {
+        if index >= self.length {
+            return None;
+        }
+
+        let mut node = self.load_node(self.root_addr);
+        loop {
+            if node.node_type() == NodeType::Leaf {
+                return Some((node, index as usize));
+            }
+
+            let mut idx = 0;
+            loop {
+                let count = node.child_count(idx);
+                if index < count {
+                    // The entry is in the subtree of this child.
+                    break;
+                }
+                index -= count;
+                if index == 0 {
+                    return Some((node, idx));
+                }
+                index -= 1;
+                idx += 1;
+            }
+            node = self.load_node(node.child(idx));
+        }
+    }
+
+    // Updates the counts of the nodes modified since the counts were last updated, and
+    // of their ancestors. Called at the end of every operation that modifies the tree.
+    pub(super) fn update_counts(&mut self) {
+        if self.modified_keys.is_empty() {
+            return;
+        }
+
+        let mut keys = std::mem::take(&mut self.modified_keys);
+        if self.root_addr != NULL {
+            keys.sort();
+            keys.dedup();
+            self.recount(self.root_addr, &keys);
+        }
+    }
+
+    // Recomputes the counts of the children of the node at the given address whose
+    // subtrees contain any of the given sorted keys, or whose counts are unknown.
+    //
+    // Returns the number of entries in the node's subtree.
+    fn recount(&mut self, address: Address, mut keys: &[K]) -> u64 {
+        let mut node = self.load_node(address);
+        if node.node_type() == NodeType::Leaf {
+            return node.entries_len() as u64;
+        }
+
+        let mut modified = false;
+        for idx in 0..node.children_len() {
+            // The keys in the child's subtree are the ones smaller than the key of the
+            // entry on its right. The entry's own key belongs to this node.
+            let (child_keys, rest) = if idx < node.entries_len() {
+                let entry_key = node.key(idx);
+                let end = keys.partition_point(|key| key < entry_key);
+                let rest = keys.partition_point(|key| key <= entry_key);
+                (&keys[..end], &keys[rest..])
+            } else {
+                (keys, &keys[keys.len()..])
+            };
+            keys = rest;
+
+            let count = if !child_keys.is_empty() {
+                self.recount(node.child(idx), child_keys)
+            } else if node.child_count(idx) == UNKNOWN_COUNT {
+                self.load_node(node.child(idx)).subtree_len()
+            } else {
+                continue;
+            };
+
+            if count != node.child_count(idx) {
+                node.set_child_count(idx, count);
+                modified = true;
+            }
+        }
+
+        if modified {
+            self.write_node(&mut node);
+        }
+        node.subtree_len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::collection::{btree_set as pset, vec as pvec};
+    use proptest::prelude::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap as StdBTreeMap;
+    use std::rc::Rc;
+
+    fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+        Rc::new(RefCell::new(Vec::new()))
+    }
+
+    // Checks that the counts of the subtree rooted at the given address are correct, and
+    // returns the number of entries in the subtree.
+    fn check_counts<K: Storable + Ord + Clone, V: Storable, M: Memory>(
+        map: &BTreeMap<K, V, M>,
+        address: Address,
+    ) -> u64 {
+        let node = map.load_node(address);
+        let mut count = node.entries_len() as u64;
+        if node.node_type() == NodeType::Internal {
+            for idx in 0..node.children_len() {
+                let child_count = check_counts(map, node.child(idx));
+                assert_eq!(node.child_count(idx), child_count);
+                count += child_count;
+            }
+        }
+        count
+    }
+
+    fn check_map<M: Memory>(map: &BTreeMap<u16, u16, M>, std_map: &StdBTreeMap<u16, u16>) {
+        if map.root_addr != NULL {
+            assert_eq!(check_counts(map, map.root_addr), map.len());
+        }
+        assert_eq!(map.len(), std_map.len() as u64);
+
+        for (index, (key, value)) in std_map.iter().enumerate() {
+            assert_eq!(map.rank(key), index as u64);
+            assert_eq!(map.select(index as u64), Some((*key, *value)));
+        }
+        assert_eq!(map.select(std_map.len() as u64), None);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Insert(u16),
+        Remove(u16),
+        RemoveRange(u16, u16),
+        InsertEntry(u16),
+        Append(Vec<u16>),
+    }
+
+    fn arb_op() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            20 => any::<u16>().prop_map(Operation::Insert),
+            10 => any::<u16>().prop_map(Operation::Remove),
+            1 => (any::<u16>(), 0..5000u16).prop_map(|(start, len)| Operation::RemoveRange(start, len)),
+            5 => any::<u16>().prop_map(Operation::InsertEntry),
+            1 => pvec(any::<u16>(), 0..300).prop_map(Operation::Append),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20))]
+        #[test]
+        fn counts_are_maintained(ops in pvec(arb_op(), 0..2000)) {
+            let mut map = BTreeMap::new_with_counts(make_memory());
+            let mut std_map = StdBTreeMap::new();
+
+            for op in ops {
+                match op {
+                    Operation::Insert(key) => {
+                        prop_assert_eq!(map.insert(key, key), std_map.insert(key, key));
+                    }
+                    Operation::Remove(key) => {
+                        prop_assert_eq!(map.remove(&key), std_map.remove(&key));
+                    }
+                    Operation::RemoveRange(start, len) => {
+                        let range = start..start.saturating_add(len);
+                        let removed: Vec<_> = std_map.range(range.clone()).map(|(k, _)| *k).collect();
+                        for key in removed.iter() {
+                            std_map.remove(key);
+                        }
+                        prop_assert_eq!(map.remove_range(range), removed.len() as u64);
+                    }
+                    Operation::InsertEntry(key) => {
+                        map.entry(key).or_insert(1);
+                        std_map.entry(key).or_insert(1);
+                    }
+                    Operation::Append(keys) => {
+                        let mut other = BTreeMap::new(make_memory());
+                        for key in keys {
+                            other.insert(key, key);
+                            std_map.insert(key, key);
+                        }
+                        map.append(&mut other);
+                    }
+                }
+            }
+
+            check_map(&map, &std_map);
+        }
+
+        #[test]
+        fn range_count_and_nth(
+            keys in pset(any::<u16>(), 0..3000),
+            start in any::<u16>(),
+            len in 0..5000u16,
+            skips in pvec(0..100usize, 0..50),
+        ) {
+            let map = BTreeMap::from_sorted_iter(make_memory(), keys.iter().map(|k| (*k, ()))).unwrap();
+            let mut counted = BTreeMap::new_with_counts(make_memory());
+            counted.extend_sorted(keys.iter().map(|k| (*k, ()))).unwrap();
+
+            let end = start.saturating_add(len);
+            let expected = keys.range(start..end).count() as u64;
+            prop_assert_eq!(counted.range_count(start..end), expected);
+            prop_assert_eq!(map.range_count(start..end), expected);
+            prop_assert_eq!(counted.range_count(start..=end), keys.range(start..=end).count() as u64);
+            prop_assert_eq!(counted.range_count(..end), keys.range(..end).count() as u64);
+            prop_assert_eq!(counted.range_count(start..), keys.range(start..).count() as u64);
+            prop_assert_eq!(
+                counted.range_count((Bound::Excluded(start), Bound::Unbounded)),
+                keys.range((Bound::Excluded(start), Bound::Unbounded)).count() as u64
+            );
+
+            // Skipping entries gives the same results with and without counts.
+            let mut std_iter = keys.range(start..end).cloned();
+            let mut iter = counted.range(start..end).map(|(k, _)| k);
+            let mut uncounted_iter = map.range(start..end).map(|(k, _)| k);
+            for n in skips {
+                let expected = std_iter.nth(n);
+                prop_assert_eq!(iter.nth(n), expected);
+                prop_assert_eq!(uncounted_iter.nth(n), expected);
+
+                let expected = std_iter.next_back();
+                prop_assert_eq!(iter.next_back(), expected);
+                prop_assert_eq!(uncounted_iter.next_back(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_are_persisted() {
+        let mem = make_memory();
+        let mut map = BTreeMap::new_with_counts(mem.clone());
+        for i in 0..1000u64 {
+            map.insert(i, i);
+        }
+
+        let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(map.into_memory());
+        assert!(map.has_counts);
+        for i in 1000..2000u64 {
+            map.insert(i, i);
+        }
+        assert_eq!(check_counts(&map, map.root_addr), 2000);
+        assert_eq!(map.rank(&1500), 1500);
+        assert_eq!(map.select(1999), Some((1999, 1999)));
+
+        // Maps created with `new` don't have counts.
+        let map: BTreeMap<u64, u64, _> =
+            BTreeMap::init(BTreeMap::<u64, u64, _>::new(make_memory()).into_memory());
+        assert!(!map.has_counts);
+    }
+
+    #[test]
+    fn skipping_entries_doesnt_visit_them() {
+        let mut map = BTreeMap::new_with_counts(make_memory());
+        for i in 0..10_000u64 {
+            map.insert(i, i);
+        }
+
+        let mut iter = map.iter();
+        assert_eq!(iter.next(), Some((0, 0)));
+        assert_eq!(iter.nth(5000), Some((5001, 5001)));
+        assert_eq!(iter.next_back(), Some((9999, 9999)));
+        assert_eq!(iter.nth(4997), None);
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+
+        assert_eq!(
+            map.iter().skip(9998).map(|(k, _)| k).collect::<Vec<_>>(),
+            vec![9998, 9999]
+        );
+        assert_eq!(map.range(..100).nth(100), None);
+    }
+
+    #[test]
+    fn split_off_keeps_counts() {
+        let mut map = BTreeMap::new_with_counts(make_memory());
+        let mut std_map = StdBTreeMap::new();
+        for i in 0..3000u16 {
+            map.insert(i, i);
+            std_map.insert(i, i);
+        }
+
+        let other = map.split_off(&1234, make_memory());
+        std_map.split_off(&1234);
+        assert_eq!(other.len(), 3000 - 1234);
+        check_map(&map, &std_map);
+
+        map.clear_step(1);
+        while !map.clear_step(1) {
+            map.insert(7, 7);
+        }
+        check_map(&map, &[(7, 7)].into_iter().collect());
+    }
+}
@@ -0,0 +1,272 @@
+use super::{node::Node, BTreeMap};
+use crate::{Memory, Storable};
+use std::borrow::Cow;
+
+/// A view into a single entry of a [`BTreeMap`], which may either be vacant or occupied.
+///
+/// This is constructed by [`BTreeMap::entry`]. The entry is located with a single
+/// descent from the root, and reading or updating it doesn't traverse the tree again.
+pub enum Entry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, K, V, M>),
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, K, V, M>),
+}
+
+/// A view into a vacant entry of a [`BTreeMap`].
+pub struct VacantEntry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    map: &'a mut BTreeMap<K, V, M>,
+    key: K,
+
+    // The leaf where the key would be inserted, along with the index to insert it at.
+    // This is `None` if the map is empty.
+    leaf: Option<(Node<K>, usize)>,
+}
+
+/// A view into an occupied entry of a [`BTreeMap`].
+pub struct OccupiedEntry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    map: &'a mut BTreeMap<K, V, M>,
+
+    // The node containing the entry, along with the entry's index in the node.
+    node: Node<K>,
+    idx: usize,
+}
+
+impl<'a, K, V, M> Entry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant, and returns the value of the entry.
+    pub fn or_insert(self, default: V) -> V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is vacant, and returns the value
+    /// of the entry.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(default()),
+            Entry::Occupied(entry) => entry.get(),
+        }
+    }
+
+    /// Modifies the value of the entry in place if it's occupied.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    ///
+    /// // A counter.
+    /// for _ in 0..3 {
+    ///     map.entry(1).and_modify(|count| *count += 1).or_insert(1);
+    /// }
+    /// assert_eq!(map.get(&1), Some(3));
+    /// ```
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+            Entry::Occupied(mut entry) => {
+                let mut value = entry.get();
+                f(&mut value);
+                entry.insert(value);
+                Entry::Occupied(entry)
+            }
+        }
+    }
+}
+
+impl<'a, K, V, M> VacantEntry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    pub(crate) fn new(
+        map: &'a mut BTreeMap<K, V, M>,
+        key: K,
+        leaf: Option<(Node<K>, usize)>,
+    ) -> Self {
+        Self { map, key, leaf }
+    }
+
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes ownership of the key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts a value into the entry and returns it.
+    pub fn insert(self, value: V) -> V {
+        BTreeMap::<K, V, M>::assert_key_size(&self.key);
+        let encoded_value = BTreeMap::<K, V, M>::encode_value(&value);
+
+        match self.leaf {
+            Some((mut leaf, idx)) if !leaf.is_full() => {
+                // The leaf has room for the entry. Insert it directly.
+                leaf.insert_entry(idx, (self.key, encoded_value));
+                self.map.save_node(&mut leaf);
+                self.map.update_counts();
+
+                // Update the length.
+                self.map.length += 1;
+                self.map.save();
+            }
+            _ => {
+                // Either the map is empty or the leaf is full, in which case nodes need
+                // to be split on the way down. Fall back to a regular insert.
+                let previous = self.map.insert_encoded(self.key, encoded_value);
+                debug_assert!(previous.is_none());
+            }
+        }
+
+        value
+    }
+}
+
+impl<'a, K, V, M> OccupiedEntry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    pub(crate) fn new(map: &'a mut BTreeMap<K, V, M>, node: Node<K>, idx: usize) -> Self {
+        Self { map, node, idx }
+    }
+
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        self.node.key(self.idx)
+    }
+
+    /// Returns the value of the entry.
+    pub fn get(&self) -> V {
+        V::from_bytes(Cow::Borrowed(&self.node.value(self.idx, self.map.memory())))
+    }
+
+    /// Sets the value of the entry, and returns the entry's previous value.
+    pub fn insert(&mut self, value: V) -> V {
+        let previous_value = self.get();
+        let encoded_value = BTreeMap::<K, V, M>::encode_value(&value);
+        self.map
+            .update_node_value(&mut self.node, self.idx, encoded_value);
+        previous_value
+    }
+
+    /// Removes the entry from the map and returns its value.
+    pub fn remove(self) -> V {
+        let key = self.key().clone();
+        self.map
+            .remove(&key)
+            .expect("the entry must exist in the map")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+        Rc::new(RefCell::new(Vec::new()))
+    }
+
+    #[test]
+    fn or_insert() {
+        let mut btree = BTreeMap::new(make_memory());
+
+        assert_eq!(btree.entry(1u64).or_insert(10u64), 10);
+        assert_eq!(btree.entry(1).or_insert(20), 10);
+        assert_eq!(btree.get(&1), Some(10));
+        assert_eq!(btree.len(), 1);
+    }
+
+    #[test]
+    fn and_modify() {
+        let mut btree = BTreeMap::new(make_memory());
+
+        for i in 0..1000u64 {
+            for _ in 0..=(i % 3) {
+                btree.entry(i).and_modify(|v| *v += 1).or_insert(1u64);
+            }
+        }
+
+        assert_eq!(btree.len(), 1000);
+        for i in 0..1000u64 {
+            assert_eq!(btree.get(&i), Some(i % 3 + 1));
+        }
+    }
+
+    #[test]
+    fn vacant_and_occupied_entries() {
+        let mut btree = BTreeMap::new(make_memory());
+
+        match btree.entry(1u64) {
+            Entry::Vacant(entry) => {
+                assert_eq!(entry.key(), &1);
+                assert_eq!(entry.insert(10u64), 10);
+            }
+            Entry::Occupied(_) => panic!("expected a vacant entry"),
+        }
+
+        match btree.entry(1) {
+            Entry::Vacant(_) => panic!("expected an occupied entry"),
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.key(), &1);
+                assert_eq!(entry.get(), 10);
+                assert_eq!(entry.insert(20), 10);
+                assert_eq!(entry.get(), 20);
+                assert_eq!(entry.remove(), 20);
+            }
+        }
+
+        assert_eq!(btree.get(&1), None);
+        assert!(btree.is_empty());
+    }
+
+    #[test]
+    fn inserting_into_full_leaves() {
+        let mut btree = BTreeMap::new(make_memory());
+
+        // Insert enough entries for leaves to be full and split.
+        for i in (0..500u64).rev() {
+            assert_eq!(btree.entry(i).or_insert(i + 1), i + 1);
+        }
+
+        assert_eq!(btree.len(), 500);
+        assert_eq!(
+            btree.iter().collect::<Vec<_>>(),
+            (0..500).map(|i| (i, i + 1)).collect::<Vec<_>>()
+        );
+    }
+}
@@ -19,6 +19,9 @@ pub(crate) enum Index {
 }
 
 /// An iterator over the entries of a [`BTreeMap`].
+///
+/// The iterator is double-ended: entries can be taken from both ends of the range,
+/// e.g. `map.iter().rev()` iterates over the entries in descending order.
 #[must_use = "iterators are lazy and do nothing unless consumed"]
 pub struct Iter<'a, K, V, M>
 where
@@ -29,10 +32,20 @@ where
     // A reference to the map being iterated on.
     map: &'a BTreeMap<K, V, M>,
 
-    // A stack of cursors indicating the current position in the tree.
-    cursors: Vec<Cursor<K>>,
+    // Flags indicating whether the cursors have been initialized yet. The cursors
+    // are initialized lazily, so that iterating in one direction doesn't pay for
+    // seeking the other end of the range.
+    forward_cursors_initialized: bool,
+    backward_cursors_initialized: bool,
 
-    // The range of keys we want to traverse.
+    // Stacks of cursors indicating the current positions in the tree when iterating
+    // forwards and backwards.
+    forward_cursors: Vec<Cursor<K>>,
+    backward_cursors: Vec<Cursor<K>>,
+
+    // The range of keys we want to traverse. Once the cursors of both directions
+    // are initialized, the range shrinks as entries are returned from either end,
+    // so that the two ends never cross.
     range: (Bound<K>, Bound<K>),
 }
 
@@ -43,33 +56,329 @@ where
     M: Memory,
 {
     pub(crate) fn new(map: &'a BTreeMap<K, V, M>) -> Self {
+        Self::new_in_range(map, (Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// Returns an empty iterator.
+    pub(crate) fn null(map: &'a BTreeMap<K, V, M>) -> Self {
         Self {
             map,
-            // Initialize the cursors with the address of the root of the map.
-            cursors: vec![Cursor::Address(map.root_addr)],
+            forward_cursors_initialized: true,
+            backward_cursors_initialized: true,
+            forward_cursors: vec![],
+            backward_cursors: vec![],
             range: (Bound::Unbounded, Bound::Unbounded),
         }
     }
 
-    /// Returns an empty iterator.
-    pub(crate) fn null(map: &'a BTreeMap<K, V, M>) -> Self {
+    pub(crate) fn new_in_range(map: &'a BTreeMap<K, V, M>, range: (Bound<K>, Bound<K>)) -> Self {
         Self {
             map,
-            cursors: vec![],
-            range: (Bound::Unbounded, Bound::Unbounded),
+            forward_cursors_initialized: false,
+            backward_cursors_initialized: false,
+            forward_cursors: vec![],
+            backward_cursors: vec![],
+            range,
         }
     }
 
-    pub(crate) fn new_in_range(
+    /// Returns an iterator over the given range, with forward cursors that already
+    /// point to the start of the range.
+    pub(crate) fn new_with_cursors(
         map: &'a BTreeMap<K, V, M>,
         range: (Bound<K>, Bound<K>),
-        cursors: Vec<Cursor<K>>,
+        forward_cursors: Vec<Cursor<K>>,
     ) -> Self {
         Self {
-            map,
-            cursors,
-            range,
+            forward_cursors_initialized: true,
+            forward_cursors,
+            ..Self::new_in_range(map, range)
+        }
+    }
+
+    // Positions the forward cursors at the first key in the range.
+    fn initialize_forward_cursors(&mut self) {
+        debug_assert!(!self.forward_cursors_initialized);
+        self.forward_cursors_initialized = true;
+
+        if self.backward_cursors_initialized {
+            // The range doesn't shrink while iterating backwards only, so end it at
+            // the key the backward cursors point to instead.
+            match self.backward_cursors_key() {
+                Some(key) => self.range.1 = Bound::Included(key),
+                // There are no entries left.
+                None => return,
+            }
+        }
+
+        if self.map.root_addr == NULL {
+            // Map is empty.
+            return;
+        }
+
+        match self.range.start_bound() {
+            Bound::Unbounded => {
+                self.forward_cursors
+                    .push(Cursor::Address(self.map.root_addr));
+            }
+            Bound::Included(key) | Bound::Excluded(key) => {
+                let mut node = self.map.load_node(self.map.root_addr);
+                loop {
+                    match node.search(key) {
+                        Ok(idx) => {
+                            if let Bound::Included(_) = self.range.start_bound() {
+                                // We found the key exactly matching the left bound.
+                                // Here is where we'll start the iteration.
+                                self.forward_cursors.push(Cursor::Node {
+                                    node,
+                                    next: Index::Entry(idx),
+                                });
+                                return;
+                            } else {
+                                // We found the key that we must
+                                // exclude.  We add its right neighbor
+                                // to the stack and start iterating
+                                // from its right child.
+                                let right_child = match node.node_type() {
+                                    NodeType::Internal => Some(node.child(idx + 1)),
+                                    NodeType::Leaf => None,
+                                };
+
+                                if idx + 1 != node.entries_len()
+                                    && self.range.contains(node.key(idx + 1))
+                                {
+                                    self.forward_cursors.push(Cursor::Node {
+                                        node,
+                                        next: Index::Entry(idx + 1),
+                                    });
+                                }
+                                if let Some(right_child) = right_child {
+                                    self.forward_cursors.push(Cursor::Address(right_child));
+                                }
+                                return;
+                            }
+                        }
+                        Err(idx) => {
+                            // The `idx` variable points to the first
+                            // key that is greater than the left
+                            // bound.
+                            //
+                            // If the index points to a valid node, we
+                            // will visit its left subtree and then
+                            // return to this key.
+                            //
+                            // If the index points at the end of
+                            // array, we'll continue with the right
+                            // child of the last key.
+
+                            // Load the left child of the node to visit if it exists.
+                            // This is done first to avoid cloning the node.
+                            let child = match node.node_type() {
+                                NodeType::Internal => {
+                                    // Note that loading a child node cannot fail since
+                                    // len(children) = len(entries) + 1
+                                    Some(self.map.load_node(node.child(idx)))
+                                }
+                                NodeType::Leaf => None,
+                            };
+
+                            if idx < node.entries_len() && self.range.contains(node.key(idx)) {
+                                self.forward_cursors.push(Cursor::Node {
+                                    node,
+                                    next: Index::Entry(idx),
+                                });
+                            }
+
+                            match child {
+                                None => {
+                                    // Leaf node. The cursors are now initialized.
+                                    return;
+                                }
+                                Some(child) => {
+                                    // Iterate over the child node.
+                                    node = child;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Positions the backward cursors at the last key in the range.
+    fn initialize_backward_cursors(&mut self) {
+        debug_assert!(!self.backward_cursors_initialized);
+        self.backward_cursors_initialized = true;
+
+        if self.forward_cursors_initialized {
+            // The range doesn't shrink while iterating forwards only, so start it at
+            // the key the forward cursors point to instead.
+            match self.forward_cursors_key() {
+                Some(key) => self.range.0 = Bound::Included(key),
+                // There are no entries left.
+                None => return,
+            }
+        }
+
+        if self.map.root_addr == NULL {
+            // Map is empty.
+            return;
+        }
+
+        match self.range.end_bound() {
+            Bound::Unbounded => {
+                self.backward_cursors
+                    .push(Cursor::Address(self.map.root_addr));
+            }
+            Bound::Included(key) | Bound::Excluded(key) => {
+                let mut node = self.map.load_node(self.map.root_addr);
+                loop {
+                    match node.search(key) {
+                        Ok(idx) => {
+                            if let Bound::Included(_) = self.range.end_bound() {
+                                // We found the key exactly matching the right bound.
+                                // Here is where we'll start the iteration.
+                                self.backward_cursors.push(Cursor::Node {
+                                    node,
+                                    next: Index::Entry(idx),
+                                });
+                                return;
+                            } else {
+                                // We found the key that we must exclude. We add its
+                                // left neighbor to the stack and start iterating
+                                // from its left child.
+                                let left_child = match node.node_type() {
+                                    NodeType::Internal => Some(node.child(idx)),
+                                    NodeType::Leaf => None,
+                                };
+
+                                if idx > 0 && self.range.contains(node.key(idx - 1)) {
+                                    self.backward_cursors.push(Cursor::Node {
+                                        node,
+                                        next: Index::Entry(idx - 1),
+                                    });
+                                }
+                                if let Some(left_child) = left_child {
+                                    self.backward_cursors.push(Cursor::Address(left_child));
+                                }
+                                return;
+                            }
+                        }
+                        Err(idx) => {
+                            // The `idx` variable points to the first key that is
+                            // greater than the right bound, so the key before it
+                            // is the last key in this node that is less than it.
+                            //
+                            // We will visit the child at `idx`, which contains
+                            // keys between these two keys, and then return to
+                            // the key before `idx`, if there is one.
+                            let child = match node.node_type() {
+                                NodeType::Internal => Some(self.map.load_node(node.child(idx))),
+                                NodeType::Leaf => None,
+                            };
+
+                            if idx > 0 && self.range.contains(node.key(idx - 1)) {
+                                self.backward_cursors.push(Cursor::Node {
+                                    node,
+                                    next: Index::Entry(idx - 1),
+                                });
+                            }
+
+                            match child {
+                                None => {
+                                    // Leaf node. The cursors are now initialized.
+                                    return;
+                                }
+                                Some(child) => {
+                                    // Iterate over the child node.
+                                    node = child;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns the key that the next call to `next` would return, ignoring the end of
+    // the range, or `None` if the forward cursors have no entries left.
+    fn forward_cursors_key(&self) -> Option<K> {
+        for cursor in self.forward_cursors.iter().rev() {
+            let key = match cursor {
+                Cursor::Address(address) => self.first_key(*address),
+                Cursor::Node {
+                    node,
+                    next: Index::Child(child_idx),
+                } => self.first_key(node.child(*child_idx)),
+                Cursor::Node {
+                    node,
+                    next: Index::Entry(entry_idx),
+                } => (*entry_idx < node.entries_len()).then(|| node.key(*entry_idx).clone()),
+            };
+            if key.is_some() {
+                return key;
+            }
+        }
+        None
+    }
+
+    // Returns the key that the next call to `next_back` would return, ignoring the
+    // start of the range, or `None` if the backward cursors have no entries left.
+    fn backward_cursors_key(&self) -> Option<K> {
+        for cursor in self.backward_cursors.iter().rev() {
+            let key = match cursor {
+                Cursor::Address(address) => self.last_key(*address),
+                Cursor::Node {
+                    node,
+                    next: Index::Child(child_idx),
+                } => self.last_key(node.child(*child_idx)),
+                Cursor::Node {
+                    node,
+                    next: Index::Entry(entry_idx),
+                } => Some(node.key(*entry_idx).clone()),
+            };
+            if key.is_some() {
+                return key;
+            }
         }
+        None
+    }
+
+    // Returns the smallest key in the subtree at the given address.
+    fn first_key(&self, mut address: Address) -> Option<K> {
+        while address != NULL {
+            let node = self.map.load_node(address);
+            match node.node_type() {
+                NodeType::Internal => address = node.child(0),
+                NodeType::Leaf => return (node.entries_len() > 0).then(|| node.key(0).clone()),
+            }
+        }
+        None
+    }
+
+    // Returns the largest key in the subtree at the given address.
+    fn last_key(&self, mut address: Address) -> Option<K> {
+        while address != NULL {
+            let node = self.map.load_node(address);
+            match node.node_type() {
+                NodeType::Internal => address = node.child(node.children_len() - 1),
+                NodeType::Leaf => {
+                    return node
+                        .entries_len()
+                        .checked_sub(1)
+                        .map(|idx| node.key(idx).clone())
+                }
+            }
+        }
+        None
+    }
+
+    // Clears the cursors in both directions, ending the iteration.
+    fn clear(&mut self) {
+        self.forward_cursors = vec![];
+        self.backward_cursors = vec![];
     }
 }
 
@@ -82,12 +391,16 @@ where
     type Item = (K, V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.cursors.pop() {
+        if !self.forward_cursors_initialized {
+            self.initialize_forward_cursors();
+        }
+
+        match self.forward_cursors.pop() {
             Some(Cursor::Address(address)) => {
                 if address != NULL {
                     // Load the node at the given address, and add it to the cursors.
                     let node = self.map.load_node(address);
-                    self.cursors.push(Cursor::Node {
+                    self.forward_cursors.push(Cursor::Node {
                         next: match node.node_type() {
                             // Iterate on internal nodes starting from the first child.
                             NodeType::Internal => Index::Child(0),
@@ -108,13 +421,13 @@ where
 
                 // After iterating on the child, iterate on the next _entry_ in this node.
                 // The entry immediately after the child has the same index as the child's.
-                self.cursors.push(Cursor::Node {
+                self.forward_cursors.push(Cursor::Node {
                     node,
                     next: Index::Entry(child_idx),
                 });
 
                 // Add the child to the top of the cursors to be iterated on first.
-                self.cursors.push(Cursor::Address(child_address));
+                self.forward_cursors.push(Cursor::Address(child_address));
 
                 self.next()
             }
@@ -128,10 +441,17 @@ where
                     return self.next();
                 }
 
+                // If the key does not belong to the range, iteration stops.
+                if !self.range.contains(node.key(entry_idx)) {
+                    // Clear all cursors to avoid needless work in subsequent calls.
+                    self.clear();
+                    return None;
+                }
+
                 let (key, encoded_value) = node.entry(entry_idx, self.map.memory());
 
                 // Add to the cursors the next element to be traversed.
-                self.cursors.push(Cursor::Node {
+                self.forward_cursors.push(Cursor::Node {
                     next: match node.node_type() {
                         // If this is an internal node, add the next child to the cursors.
                         NodeType::Internal => Index::Child(entry_idx + 1),
@@ -141,13 +461,147 @@ where
                     node,
                 });
 
+                // Shrink the range so that iterating backwards stops before this key.
+                // Until the backward cursors are initialized, they start from the
+                // forward cursors instead, which saves cloning every key.
+                if self.backward_cursors_initialized {
+                    self.range.0 = Bound::Excluded(key.clone());
+                }
+
+                Some((key, V::from_bytes(Cow::Owned(encoded_value))))
+            }
+            None => {
+                // The cursors are empty. Iteration is complete.
+                None
+            }
+        }
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        if n > 0 && self.map.has_counts {
+            // The range doesn't shrink while iterating in one direction only, so take
+            // the current position of that direction from its cursors.
+            if self.forward_cursors_initialized && !self.backward_cursors_initialized {
+                self.range.0 = Bound::Included(self.forward_cursors_key()?);
+            }
+            if self.backward_cursors_initialized && !self.forward_cursors_initialized {
+                self.range.1 = Bound::Included(self.backward_cursors_key()?);
+            }
+
+            // Move the start of the range to the n-th entry, without visiting the
+            // entries before it.
+            let index = self.map.start_position(self.range.start_bound()) + n as u64;
+            match self.map.select_key(index) {
+                Some(key) if self.range.contains(&key) => {
+                    self.range.0 = Bound::Included(key);
+                    self.forward_cursors = vec![];
+                    self.forward_cursors_initialized = false;
+                }
+                _ => {
+                    // There are fewer than n entries left. Iteration is complete.
+                    self.clear();
+                    self.forward_cursors_initialized = true;
+                    self.backward_cursors_initialized = true;
+                    return None;
+                }
+            }
+        } else {
+            for _ in 0..n {
+                self.next()?;
+            }
+        }
+
+        self.next()
+    }
+}
+
+impl<K, V, M> DoubleEndedIterator for Iter<'_, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if !self.backward_cursors_initialized {
+            self.initialize_backward_cursors();
+        }
+
+        match self.backward_cursors.pop() {
+            Some(Cursor::Address(address)) => {
+                if address != NULL {
+                    // Load the node at the given address, and add it to the cursors.
+                    let node = self.map.load_node(address);
+                    if let Some(next) = match node.node_type() {
+                        // Iterate on internal nodes starting from the last child.
+                        NodeType::Internal => Some(Index::Child(node.entries_len())),
+                        // Iterate on leaf nodes starting from the last entry.
+                        NodeType::Leaf => node.entries_len().checked_sub(1).map(Index::Entry),
+                    } {
+                        self.backward_cursors.push(Cursor::Node { next, node });
+                    }
+                }
+                self.next_back()
+            }
+
+            Some(Cursor::Node {
+                node,
+                next: Index::Child(child_idx),
+            }) => {
+                let child_address = node.child(child_idx);
+
+                // After iterating on the child, iterate on the previous _entry_ in this node.
+                // The entry immediately before the child has the child's index minus one.
+                if child_idx > 0 {
+                    self.backward_cursors.push(Cursor::Node {
+                        node,
+                        next: Index::Entry(child_idx - 1),
+                    });
+                }
+
+                // Add the child to the top of the cursors to be iterated on first.
+                self.backward_cursors.push(Cursor::Address(child_address));
+
+                self.next_back()
+            }
+
+            Some(Cursor::Node {
+                node,
+                next: Index::Entry(entry_idx),
+            }) => {
                 // If the key does not belong to the range, iteration stops.
-                if !self.range.contains(&key) {
+                if !self.range.contains(node.key(entry_idx)) {
                     // Clear all cursors to avoid needless work in subsequent calls.
-                    self.cursors = vec![];
+                    self.clear();
                     return None;
                 }
 
+                let (key, encoded_value) = node.entry(entry_idx, self.map.memory());
+
+                // Add to the cursors the previous element to be traversed.
+                match node.node_type() {
+                    // If this is an internal node, add the previous child to the cursors.
+                    NodeType::Internal => self.backward_cursors.push(Cursor::Node {
+                        next: Index::Child(entry_idx),
+                        node,
+                    }),
+                    // If this is a leaf node, add the previous entry to the cursors.
+                    NodeType::Leaf => {
+                        if entry_idx > 0 {
+                            self.backward_cursors.push(Cursor::Node {
+                                next: Index::Entry(entry_idx - 1),
+                                node,
+                            });
+                        }
+                    }
+                }
+
+                // Shrink the range so that iterating forwards stops before this key.
+                // Until the forward cursors are initialized, they start from the
+                // backward cursors instead, which saves cloning every key.
+                if self.forward_cursors_initialized {
+                    self.range.1 = Bound::Excluded(key.clone());
+                }
+
                 Some((key, V::from_bytes(Cow::Owned(encoded_value))))
             }
             None => {
@@ -207,4 +661,155 @@ mod test {
 
         assert_eq!(i, 100);
     }
+
+    #[test]
+    fn iterate_leaf_rev() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::new(mem);
+
+        for i in 0..10u8 {
+            btree.insert(i, i + 1);
+        }
+
+        let mut i = 10;
+        for (key, value) in btree.iter().rev() {
+            i -= 1;
+            assert_eq!(key, i);
+            assert_eq!(value, i + 1);
+        }
+
+        assert_eq!(i, 0);
+    }
+
+    #[test]
+    fn iterate_children_rev() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::new(mem);
+
+        for i in 0..100u64 {
+            btree.insert(i, i + 1);
+        }
+
+        // Iteration should be in descending order.
+        let mut i = 100;
+        for (key, value) in btree.iter().rev() {
+            i -= 1;
+            assert_eq!(key, i);
+            assert_eq!(value, i + 1);
+        }
+
+        assert_eq!(i, 0);
+    }
+
+    #[test]
+    fn iterate_from_both_ends() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::new(mem);
+
+        for i in 0..100u64 {
+            btree.insert(i, ());
+        }
+
+        // Alternate between the two ends until they meet in the middle.
+        let mut iter = btree.iter();
+        for i in 0..50 {
+            assert_eq!(iter.next(), Some((i, ())));
+            assert_eq!(iter.next_back(), Some((99 - i, ())));
+        }
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn changing_direction_after_iterating() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::new(mem);
+
+        for i in 0..100u64 {
+            btree.insert(i, ());
+        }
+
+        let mut iter = btree.iter();
+        assert_eq!(iter.nth(29), Some((29, ())));
+        assert_eq!(
+            iter.rev().map(|(k, _)| k).collect::<Vec<_>>(),
+            (30..100).rev().collect::<Vec<_>>()
+        );
+
+        let mut iter = btree.range(10..90).rev();
+        assert_eq!(iter.nth(29), Some((60, ())));
+        assert_eq!(
+            iter.rev().map(|(k, _)| k).collect::<Vec<_>>(),
+            (10..60).collect::<Vec<_>>()
+        );
+
+        // Changing direction after one direction is exhausted returns nothing.
+        let mut iter = btree.iter();
+        assert_eq!(iter.by_ref().count(), 100);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn iterating_in_one_direction_clones_each_key_once() {
+        thread_local! {
+            static CLONES: std::cell::Cell<usize> = std::cell::Cell::new(0);
+        }
+
+        #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+        struct Key(u64);
+
+        impl Clone for Key {
+            fn clone(&self) -> Self {
+                CLONES.with(|clones| clones.set(clones.get() + 1));
+                Self(self.0)
+            }
+        }
+
+        impl Storable for Key {
+            fn to_bytes(&self) -> Cow<[u8]> {
+                self.0.to_bytes()
+            }
+
+            fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                Self(u64::from_bytes(bytes))
+            }
+
+            const BOUND: crate::storable::Bound = u64::BOUND;
+        }
+
+        let mut btree = BTreeMap::new(make_memory());
+        for i in 0..100 {
+            btree.insert(Key(i), ());
+        }
+
+        CLONES.with(|clones| clones.set(0));
+        assert_eq!(btree.iter().count(), 100);
+        assert_eq!(CLONES.with(|clones| clones.get()), 100);
+
+        CLONES.with(|clones| clones.set(0));
+        assert_eq!(btree.iter().rev().count(), 100);
+        assert_eq!(CLONES.with(|clones| clones.get()), 100);
+    }
+
+    #[test]
+    fn range_rev() {
+        let mem = make_memory();
+        let mut btree = BTreeMap::new(mem);
+
+        for i in 0..100u64 {
+            btree.insert(i * 2, ());
+        }
+
+        let keys = |iter: Iter<u64, (), _>| iter.rev().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys(btree.range(10..20)), vec![18, 16, 14, 12, 10]);
+        assert_eq!(keys(btree.range(10..=20)), vec![20, 18, 16, 14, 12, 10]);
+        assert_eq!(keys(btree.range(11..19)), vec![18, 16, 14, 12]);
+        assert_eq!(keys(btree.range(..5)), vec![4, 2, 0]);
+        assert_eq!(keys(btree.range(195..)), vec![198, 196]);
+        assert_eq!(keys(btree.range(1000..)), Vec::<u64>::new());
+        assert_eq!(
+            keys(btree.range((Bound::Excluded(10), Bound::Excluded(16)))),
+            vec![14, 12]
+        );
+    }
 }
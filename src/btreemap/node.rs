@@ -1,4 +1,5 @@
 use crate::{
+    btreemap::Allocator,
     read_struct, read_u32, read_u64,
     storable::Storable,
     types::{Address, Bytes},
@@ -10,20 +11,20 @@ use std::cell::{Ref, RefCell};
 #[cfg(test)]
 mod tests;
 mod v1;
-
-// V2 nodes are currently only used in tests.
-#[allow(dead_code)]
 mod v2;
 
-// The minimum degree to use in the btree.
+// The default minimum degree to use in the btree.
 // This constant is taken from Rust's std implementation of BTreeMap.
-const B: usize = 6;
-// The maximum number of entries per node.
+pub const B: usize = 6;
+// The maximum number of entries per node with the default minimum degree.
+// V1 nodes always have this capacity.
 const CAPACITY: usize = 2 * B - 1;
 const LAYOUT_VERSION: u8 = 1;
 const MAGIC: &[u8; 3] = b"BTN";
 const LEAF_NODE_TYPE: u8 = 0;
 const INTERNAL_NODE_TYPE: u8 = 1;
+// The type of internal nodes that store the number of entries in each child's subtree.
+const COUNTED_INTERNAL_NODE_TYPE: u8 = 2;
 // The size of u32 in bytes.
 const U32_SIZE: Bytes = Bytes::new(4);
 
@@ -36,6 +37,28 @@ pub enum NodeType {
 
 pub type Entry<K> = (K, Vec<u8>);
 
+/// A placeholder for the count of a child's subtree that isn't known yet, e.g. because
+/// the child has just been moved from another node.
+pub const UNKNOWN_COUNT: u64 = u64::MAX;
+
+/// The smallest supported branching factor.
+pub const MIN_BRANCHING_FACTOR: usize = 2;
+
+/// The largest supported branching factor, bounded by the number of entries that
+/// a node can record in its header.
+pub const MAX_BRANCHING_FACTOR: usize = u16::MAX as usize / 2 + 1;
+
+/// Returns the maximum size of the counts stored in an internal node with the given
+/// branching factor.
+fn counts_size(branching_factor: usize) -> u32 {
+    (capacity(branching_factor) as u32 + 1) * 8
+}
+
+/// Returns the maximum number of entries in a node with the given branching factor.
+fn capacity(branching_factor: usize) -> usize {
+    2 * branching_factor - 1
+}
+
 /// A node of a B-Tree.
 ///
 /// There are two versions of a `Node`:
@@ -44,7 +67,7 @@ pub type Entry<K> = (K, Vec<u8>);
 /// 2. `V2`, which supports both bounded and unbounded types.
 ///
 /// See `v1.rs` and `v2.rs` for more details.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Node<K: Storable + Ord + Clone> {
     address: Address,
     keys: Vec<K>,
@@ -59,8 +82,19 @@ pub struct Node<K: Storable + Ord + Clone> {
 
     // The address of the overflow page.
     // In V2, a node can span multiple pages if it exceeds a certain size.
-    #[allow(dead_code)]
     overflow: Option<Address>,
+
+    // Whether the node stores its keys only, as all its values are empty.
+    // Only V2 nodes can be keys-only.
+    keys_only: bool,
+
+    // The number of entries in the subtree of each child, if the node stores them.
+    // Only internal V2 nodes can store counts.
+    child_counts: Option<Vec<u64>>,
+
+    // The minimum degree of the tree the node belongs to. The node holds at most
+    // `2 * branching_factor - 1` entries. Only V2 nodes can have a non-default one.
+    branching_factor: usize,
 }
 
 impl<K: Storable + Ord + Clone> Node<K> {
@@ -68,84 +102,190 @@ impl<K: Storable + Ord + Clone> Node<K> {
     pub fn new(
         address: Address,
         node_type: NodeType,
-        max_key_size: u32,
-        max_value_size: u32,
+        version: Version,
+        branching_factor: usize,
     ) -> Node<K> {
-        Node::new_v1(address, node_type, max_key_size, max_value_size)
+        let mut node = match version {
+            Version::V1(DerivedPageSize {
+                max_key_size,
+                max_value_size,
+            }) => Node::new_v1(address, node_type, max_key_size, max_value_size),
+            Version::V2(page_size) => Node::new_v2(address, node_type, page_size),
+        };
+        node.set_branching_factor(branching_factor);
+        node
     }
 
     /// Loads a node from memory at the given address.
-    pub fn load<M: Memory>(address: Address, memory: &M, version: Version) -> Self {
+    ///
+    /// If `migrating` is true, the node belongs to a map that is being migrated from
+    /// v1, and may still be in the v1 layout.
+    pub fn load<M: Memory>(
+        address: Address,
+        memory: &M,
+        version: Version,
+        branching_factor: usize,
+        migrating: bool,
+    ) -> Self {
+        let mut node = Self::load_helper(address, memory, version, migrating);
+        node.set_branching_factor(branching_factor);
+        node
+    }
+
+    fn load_helper<M: Memory>(
+        address: Address,
+        memory: &M,
+        version: Version,
+        migrating: bool,
+    ) -> Self {
         match version {
             Version::V1(DerivedPageSize {
                 max_key_size,
                 max_value_size,
             }) => Self::load_v1(address, max_key_size, max_value_size, memory),
-            Version::V2(_) => unreachable!("Only v1 is currently supported."),
+            Version::V2(PageSize::Derived(DerivedPageSize {
+                max_key_size,
+                max_value_size,
+            })) if migrating && Self::is_v1(address, memory) => {
+                // The node belongs to a map that is being migrated from v1 and the node
+                // itself hasn't been migrated yet. The node is loaded as a v2 node so that
+                // it's saved in the v2 layout.
+                let mut node = Self::load_v1(address, max_key_size, max_value_size, memory);
+                node.version = version;
+                node
+            }
+            Version::V2(page_size) => Self::load_v2(address, page_size, memory),
         }
     }
 
-    /// Saves the node to memory.
-    pub fn save<M: Memory>(&self, memory: &M) {
-        // NOTE: new versions of `Node` will be introduced.
-        self.save_v1(memory)
+    // Sets the minimum degree of the tree the node belongs to.
+    fn set_branching_factor(&mut self, branching_factor: usize) {
+        assert!((MIN_BRANCHING_FACTOR..=MAX_BRANCHING_FACTOR).contains(&branching_factor));
+        // V1 nodes have a fixed number of slots for their entries.
+        assert!(matches!(self.version, Version::V2(_)) || branching_factor == B);
+        self.branching_factor = branching_factor;
     }
 
-    /// Returns the address of the node.
-    pub fn address(&self) -> Address {
-        self.address
+    /// Makes the node store its keys only. All the values of a keys-only node must be empty.
+    pub fn set_keys_only(&mut self) {
+        assert!(matches!(self.version, Version::V2(_)));
+        self.keys_only = true;
     }
 
-    pub fn node_type(&self) -> NodeType {
-        self.node_type
+    /// Makes the node store the number of entries in the subtree of each of its children.
+    /// Only internal nodes store counts, so this has no effect on leaves.
+    pub fn set_counted(&mut self) {
+        assert!(matches!(self.version, Version::V2(_)));
+        assert!(self.children.is_empty());
+        if self.node_type == NodeType::Internal {
+            self.child_counts = Some(vec![]);
+        }
     }
 
-    /// Returns the entry with the max key in the subtree.
-    pub fn get_max<M: Memory>(&self, memory: &M) -> Entry<K> {
-        match self.node_type {
-            NodeType::Leaf => {
-                let last_idx = self.encoded_values.borrow().len() - 1;
-                (
-                    self.keys.last().expect("A node can never be empty").clone(),
-                    self.value(last_idx, memory).to_vec(),
-                )
-            }
+    /// Returns the number of entries in the subtree of the child at the given index,
+    /// which is `UNKNOWN_COUNT` if the count isn't known.
+    pub fn child_count(&self, idx: usize) -> u64 {
+        self.child_counts
+            .as_ref()
+            .expect("the node must be counted")[idx]
+    }
+
+    /// Sets the number of entries in the subtree of the child at the given index.
+    pub fn set_child_count(&mut self, idx: usize, count: u64) {
+        self.child_counts
+            .as_mut()
+            .expect("the node must be counted")[idx] = count;
+    }
+
+    /// Returns the number of entries in the node's subtree.
+    ///
+    /// PRECONDITION: the node is a leaf, or it's counted and all its counts are known.
+    pub fn subtree_len(&self) -> u64 {
+        let children: u64 = match self.node_type {
+            NodeType::Leaf => 0,
             NodeType::Internal => {
-                let last_child = Self::load(
-                    *self
-                        .children
-                        .last()
-                        .expect("An internal node must have children."),
-                    memory,
-                    self.version,
-                );
-                last_child.get_max(memory)
+                let counts = self
+                    .child_counts
+                    .as_ref()
+                    .expect("the node must be counted");
+                debug_assert!(!counts.contains(&UNKNOWN_COUNT));
+                counts.iter().sum()
             }
-        }
+        };
+        children + self.entries_len() as u64
     }
 
-    /// Returns the entry with min key in the subtree.
-    pub fn get_min<M: Memory>(&self, memory: &M) -> Entry<K> {
+    /// Returns true if the memory at the given address holds a node in the v1 layout.
+    pub fn is_v1<M: Memory>(address: Address, memory: &M) -> bool {
+        let header: NodeHeader = read_struct(address, memory);
+        &header.magic == MAGIC && header.version == LAYOUT_VERSION
+    }
+
+    /// Saves the node to memory.
+    pub fn save<M: Memory>(&mut self, allocator: &mut Allocator<M>) {
         match self.node_type {
             NodeType::Leaf => {
-                // NOTE: a node can never be empty, so this access is safe.
-                self.entry(0, memory)
+                assert!(self.children.is_empty());
             }
             NodeType::Internal => {
-                let first_child = Self::load(
-                    // NOTE: an internal node must have children, so this access is safe.
-                    self.children[0],
-                    memory,
-                    self.version,
-                );
-                first_child.get_min(memory)
+                assert_eq!(self.children.len(), self.keys.len() + 1);
             }
+        };
+
+        if let Some(counts) = &self.child_counts {
+            assert_eq!(counts.len(), self.children.len());
+        }
+
+        // We should never be saving an empty node.
+        assert!(!self.keys.is_empty() || !self.children.is_empty());
+
+        // Assert entries are sorted in strictly increasing order.
+        assert!(self.keys.windows(2).all(|e| e[0] < e[1]));
+
+        match self.version {
+            Version::V1(_) => self.save_v1(allocator.memory()),
+            Version::V2(_) => self.save_v2(allocator),
         }
     }
 
+    /// Deallocates the node, along with any overflow pages it may have.
+    pub fn deallocate<M: Memory>(self, allocator: &mut Allocator<M>) {
+        for overflow in self.get_overflow_addresses(allocator.memory()) {
+            allocator.deallocate(overflow);
+        }
+
+        allocator.deallocate(self.address);
+    }
+
+    /// Returns the address of the node.
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    pub fn node_type(&self) -> NodeType {
+        self.node_type
+    }
+
+    /// Returns the entry with the max key in the leaf.
+    pub fn get_max<M: Memory>(&self, memory: &M) -> Entry<K> {
+        assert_eq!(self.node_type, NodeType::Leaf);
+        let last_idx = self.encoded_values.borrow().len() - 1;
+        (
+            self.keys.last().expect("A node can never be empty").clone(),
+            self.value(last_idx, memory).to_vec(),
+        )
+    }
+
+    /// Returns the entry with min key in the leaf.
+    pub fn get_min<M: Memory>(&self, memory: &M) -> Entry<K> {
+        assert_eq!(self.node_type, NodeType::Leaf);
+        // NOTE: a node can never be empty, so this access is safe.
+        self.entry(0, memory)
+    }
+
     /// Returns true if the node cannot store anymore entries, false otherwise.
     pub fn is_full(&self) -> bool {
-        self.keys.len() >= CAPACITY
+        self.keys.len() >= capacity(self.branching_factor)
     }
 
     /// Swaps the entry at index `idx` with the given entry, returning the old entry.
@@ -161,6 +301,38 @@ impl<K: Storable + Ord + Clone> Node<K> {
         (key, old_value)
     }
 
+    /// Replaces the value at the specified index and persists the change.
+    ///
+    /// Where the layout permits, only the value is written to memory. Otherwise, the
+    /// whole node is saved.
+    pub fn update_value<M: Memory>(
+        &mut self,
+        idx: usize,
+        value: Vec<u8>,
+        allocator: &mut Allocator<M>,
+    ) {
+        let offset = match self.version {
+            Version::V1(DerivedPageSize {
+                max_key_size,
+                max_value_size,
+            }) => Some(v1::value_offset_v1(idx, max_key_size, max_value_size)),
+            Version::V2(_) => self.value_offset_v2(idx, value.len(), allocator.memory()),
+        };
+
+        match offset {
+            Some(offset) => {
+                let memory = allocator.memory();
+                write_u32(memory, self.address + offset, value.len() as u32);
+                write(memory, (self.address + offset + U32_SIZE).get(), &value);
+                self.encoded_values.borrow_mut()[idx] = Value::ByVal(value);
+            }
+            None => {
+                self.encoded_values.borrow_mut()[idx] = Value::ByVal(value);
+                self.save(allocator);
+            }
+        }
+    }
+
     /// Returns a copy of the entry at the specified index.
     pub fn entry<M: Memory>(&self, idx: usize, memory: &M) -> Entry<K> {
         (self.keys[idx].clone(), self.value(idx, memory).to_vec())
@@ -205,17 +377,30 @@ impl<K: Storable + Ord + Clone> Node<K> {
     }
 
     /// Inserts the given child at the given index.
+    ///
+    /// If the node is counted, the count of the child's subtree is unknown.
     pub fn insert_child(&mut self, idx: usize, address: Address) {
+        if let Some(counts) = &mut self.child_counts {
+            counts.insert(idx, UNKNOWN_COUNT);
+        }
         self.children.insert(idx, address)
     }
 
     /// Pushes the child to the far right of the node.
+    ///
+    /// If the node is counted, the count of the child's subtree is unknown.
     pub fn push_child(&mut self, address: Address) {
+        if let Some(counts) = &mut self.child_counts {
+            counts.push(UNKNOWN_COUNT);
+        }
         self.children.push(address)
     }
 
     /// Removes the child at the given index.
     pub fn remove_child(&mut self, idx: usize) -> Address {
+        if let Some(counts) = &mut self.child_counts {
+            counts.remove(idx);
+        }
         self.children.remove(idx)
     }
 
@@ -226,6 +411,9 @@ impl<K: Storable + Ord + Clone> Node<K> {
 
     /// Pops the right-most child of the node.
     pub fn pop_child(&mut self) -> Option<Address> {
+        if let Some(counts) = &mut self.child_counts {
+            counts.pop();
+        }
         self.children.pop()
     }
 
@@ -277,13 +465,18 @@ impl<K: Storable + Ord + Clone> Node<K> {
     ///   * `self` and `source` are of the same node type.
     ///
     /// POSTCONDITION:
-    ///   * `source` is empty (no entries and no children).
+    ///   * `source` is deallocated.
     ///   * all the entries of `source`, as well as the median, are merged into `self`, in sorted
     ///      order.
-    pub fn merge<M: Memory>(&mut self, mut source: Node<K>, median: Entry<K>, memory: &M) {
+    pub fn merge<M: Memory>(
+        &mut self,
+        mut source: Node<K>,
+        median: Entry<K>,
+        allocator: &mut Allocator<M>,
+    ) {
         // Load all the values from the source node first, as they will be moved out.
         for i in 0..source.entries_len() {
-            source.value(i, memory);
+            source.value(i, allocator.memory());
         }
 
         if source.key(0) > self.key(0) {
@@ -296,10 +489,13 @@ impl<K: Storable + Ord + Clone> Node<K> {
             Self::append(&mut source, self, median);
 
             // Move the entries and children into self.
-            self.keys = source.keys;
-            self.encoded_values = source.encoded_values;
-            self.children = source.children;
+            core::mem::swap(&mut self.keys, &mut source.keys);
+            self.encoded_values.swap(&source.encoded_values);
+            core::mem::swap(&mut self.children, &mut source.children);
+            core::mem::swap(&mut self.child_counts, &mut source.child_counts);
         }
+
+        source.deallocate(allocator);
     }
 
     // Appends the entries and children of node `b` into `a`, along with the median entry.
@@ -328,8 +524,11 @@ impl<K: Storable + Ord + Clone> Node<K> {
             .borrow_mut()
             .append(&mut b.encoded_values.borrow_mut());
 
-        // Move the children (if any exist).
+        // Move the children (if any exist), along with their counts.
         a.children.append(&mut b.children);
+        if let (Some(a_counts), Some(b_counts)) = (&mut a.child_counts, &mut b.child_counts) {
+            a_counts.append(b_counts);
+        }
 
         // Assert postconditions.
         assert_eq!(b.keys.len(), 0);
@@ -361,16 +560,26 @@ impl<K: Storable + Ord + Clone> Node<K> {
         self.keys.binary_search(key)
     }
 
-    /// Returns the size of a node in bytes.
-    ///
-    /// See the documentation of [`Node`] for the memory layout.
-    pub fn size(max_key_size: u32, max_value_size: u32) -> Bytes {
-        v1::size_v1(max_key_size, max_value_size)
+    /// Returns the index of the first key for which the predicate is false, assuming
+    /// the predicate is true for all the keys before it and false for all the keys after.
+    pub fn partition_point(&self, pred: impl Fn(&K) -> bool) -> usize {
+        self.keys.partition_point(pred)
     }
 
     /// Returns true if the node is at the minimum required size, false otherwise.
     pub fn at_minimum(&self) -> bool {
-        self.keys.len() < B
+        self.keys.len() < self.branching_factor
+    }
+
+    /// Returns true if the node has fewer entries than the minimum required of a non-root node.
+    pub fn is_underfull(&self) -> bool {
+        self.keys.len() < self.branching_factor - 1
+    }
+
+    /// Returns true if the entries of the node and its sibling, along with the median entry
+    /// between them, fit into a single node.
+    pub fn can_merge_with(&self, sibling: &Node<K>) -> bool {
+        self.keys.len() + sibling.keys.len() < capacity(self.branching_factor)
     }
 
     /// Returns true if an entry can be removed without having to merge it into another node
@@ -382,17 +591,23 @@ impl<K: Storable + Ord + Clone> Node<K> {
     /// Moves elements from own node to a sibling node and returns the median element.
     pub fn split<M: Memory>(&mut self, sibling: &mut Node<K>, memory: &M) -> Entry<K> {
         debug_assert!(self.is_full());
+        let b = self.branching_factor;
 
         // Load the values that will be moved out of the node and into the new sibling.
-        for idx in B..self.entries_len() {
+        for idx in b..self.entries_len() {
             self.value(idx, memory);
         }
 
         // Move the entries and children above the median into the new sibling.
-        sibling.keys = self.keys.split_off(B);
-        *sibling.encoded_values.borrow_mut() = self.encoded_values.borrow_mut().split_off(B);
+        sibling.keys = self.keys.split_off(b);
+        *sibling.encoded_values.borrow_mut() = self.encoded_values.borrow_mut().split_off(b);
         if self.node_type == NodeType::Internal {
-            sibling.children = self.children.split_off(B);
+            sibling.children = self.children.split_off(b);
+            if let (Some(counts), Some(sibling_counts)) =
+                (&mut self.child_counts, &mut sibling.child_counts)
+            {
+                *sibling_counts = counts.split_off(b);
+            }
         }
 
         // Return the median entry.
@@ -417,7 +632,7 @@ impl NodeHeader {
 }
 
 // The value in a K/V pair.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Value {
     // The value's encoded bytes.
     ByVal(Vec<u8>),
@@ -436,7 +651,8 @@ pub enum Version {
 }
 
 impl Version {
-    fn page_size(&self) -> u32 {
+    /// Returns the size of the pages that nodes of this version are stored in.
+    pub fn page_size(&self) -> u32 {
         match self {
             Self::V2(page_size) => page_size.get(),
             Self::V1(page_size) => page_size.get(),
@@ -446,7 +662,6 @@ impl Version {
 
 /// The size of an individual page in the memory where nodes are stored.
 /// A node, if it's bigger than a single page, overflows into multiple pages.
-#[allow(dead_code)]
 #[derive(Debug, PartialEq, Copy, Clone, Eq)]
 pub enum PageSize {
     /// Derived page sizes are used when migrating nodes from v1 to v2.
@@ -476,6 +691,17 @@ pub struct DerivedPageSize {
 impl DerivedPageSize {
     // Returns the page size derived from the max key/value sizes.
     fn get(&self) -> u32 {
-        v1::size_v1(self.max_key_size, self.max_value_size).get() as u32
+        v1::size_v1(self.max_key_size, self.max_value_size, CAPACITY).get() as u32
+    }
+
+    /// Returns a page size that fits a node with the max key/value sizes and the given
+    /// branching factor, along with the counts of its children's subtrees if `counted`.
+    pub fn get_for(&self, branching_factor: usize, counted: bool) -> u32 {
+        let capacity = capacity(branching_factor);
+        let mut page_size = v1::size_v1(self.max_key_size, self.max_value_size, capacity).get();
+        if counted {
+            page_size += counts_size(branching_factor) as u64;
+        }
+        page_size.max(v2::MINIMUM_PAGE_SIZE as u64) as u32
     }
 }
@@ -144,7 +144,7 @@ fn saving_and_loading_v2_preserves_data(node_data: NodeV2Data) {
 
     // Create a new node and save it into memory.
     let node_addr = allocator.allocate();
-    let node = node_data.get(node_addr);
+    let mut node = node_data.get(node_addr);
     node.save_v2(&mut allocator);
 
     // Reload the node and double check all the entries and children are correct.
@@ -157,9 +157,104 @@ fn saving_and_loading_v2_preserves_data(node_data: NodeV2Data) {
     );
 }
 
+#[proptest]
+fn saving_and_loading_keys_only_nodes_preserves_keys(
+    node_data: NodeV2Data,
+    #[strategy(128..10_000_u32)] page_size: u32,
+) {
+    let mem = make_memory();
+    let mut allocator =
+        Allocator::new(mem.clone(), Address::from(0), Bytes::from(page_size as u64));
+
+    // Create a keys-only node with the keys of the generated entries.
+    let node_addr = allocator.allocate();
+    let mut node = Node::new_v2(node_addr, node_data.node_type, PageSize::Value(page_size));
+    node.set_keys_only();
+    for key in node_data.entries.keys() {
+        node.push_entry((key.clone(), vec![]));
+    }
+    for child in node_data.children() {
+        node.push_child(child);
+    }
+    node.save_v2(&mut allocator);
+
+    // Reload the node and double check all the keys and children are correct.
+    let mut node: Node<Vec<u8>> = Node::load_v2(node_addr, PageSize::Value(page_size), &mem);
+    assert!(node.keys_only);
+    assert_eq!(node.children, node_data.children());
+    assert_eq!(
+        node.entries(&mem),
+        node_data
+            .entries
+            .keys()
+            .map(|key| (key.clone(), vec![]))
+            .collect::<Vec<_>>()
+    );
+
+    // Values aren't stored, so they can't be updated in place.
+    assert_eq!(node.value_offset_v2(0, 0, &mem), None);
+
+    // The node stays keys-only when saved again.
+    node.save_v2(&mut allocator);
+    let node: Node<Vec<u8>> = Node::load_v2(node_addr, PageSize::Value(page_size), &mem);
+    assert!(node.keys_only);
+}
+
+#[proptest]
+fn saving_and_loading_counted_nodes_preserves_counts(
+    node_data: NodeV2Data,
+    #[strategy(0..CAPACITY)] idx: usize,
+    byte: u8,
+) {
+    let mem = make_memory();
+    let mut allocator = Allocator::new(
+        mem.clone(),
+        Address::from(0),
+        Bytes::from(node_data.page_size as u64),
+    );
+
+    // Create a counted node with the generated entries and children.
+    let node_addr = allocator.allocate();
+    let mut node = Node::new_v2(
+        node_addr,
+        node_data.node_type,
+        PageSize::Value(node_data.page_size),
+    );
+    node.set_counted();
+    for entry in node_data.entries.clone().into_iter() {
+        node.push_entry(entry);
+    }
+    for (i, child) in node_data.children().into_iter().enumerate() {
+        node.push_child(child);
+        node.set_child_count(i, i as u64 * 1000);
+    }
+    node.save_v2(&mut allocator);
+
+    // Update a value, which may be done in place after the counts.
+    let idx = idx % node.entries_len();
+    let new_value = vec![byte; node.value(idx, &mem).len()];
+    let mut node: Node<Vec<u8>> =
+        Node::load_v2(node_addr, PageSize::Value(node_data.page_size), &mem);
+    node.update_value(idx, new_value.clone(), &mut allocator);
+
+    // Reload the node and check that the counts are preserved.
+    let node: Node<Vec<u8>> = Node::load_v2(node_addr, PageSize::Value(node_data.page_size), &mem);
+    assert_eq!(node.children, node_data.children());
+    match node_data.node_type {
+        NodeType::Leaf => assert_eq!(node.child_counts, None),
+        NodeType::Internal => assert_eq!(
+            node.child_counts,
+            Some((0..node.children_len()).map(|i| i as u64 * 1000).collect())
+        ),
+    }
+    let mut expected: Vec<_> = node_data.entries.into_iter().collect();
+    expected[idx].1 = new_value;
+    assert_eq!(node.entries(&mem), expected);
+}
+
 #[proptest]
 fn migrating_v1_nodes_to_v2(node_data: NodeV1Data) {
-    let v1_size = v1::size_v1(node_data.max_key_size, node_data.max_value_size);
+    let v1_size = v1::size_v1(node_data.max_key_size, node_data.max_value_size, CAPACITY);
     let mem = make_memory();
     let allocator_addr = Address::from(0);
     let mut allocator = Allocator::new(mem.clone(), allocator_addr, v1_size);
@@ -170,7 +265,7 @@ fn migrating_v1_nodes_to_v2(node_data: NodeV1Data) {
     node.save_v1(allocator.memory());
 
     // Reload the v1 node and save it as v2.
-    let node = Node::<Vec<u8>>::load_v1(
+    let mut node = Node::<Vec<u8>>::load_v1(
         node_addr,
         node_data.max_key_size,
         node_data.max_value_size,
@@ -194,3 +289,64 @@ fn migrating_v1_nodes_to_v2(node_data: NodeV1Data) {
         node_data.entries.into_iter().collect::<Vec<_>>()
     );
 }
+
+#[proptest]
+fn updating_values_in_place(node_data: NodeV2Data, #[strategy(0..CAPACITY)] idx: usize, byte: u8) {
+    let mem = make_memory();
+    let mut allocator = Allocator::new(
+        mem.clone(),
+        Address::from(0),
+        Bytes::from(node_data.page_size as u64),
+    );
+
+    let node_addr = allocator.allocate();
+    let mut node = node_data.get(node_addr);
+    node.save_v2(&mut allocator);
+
+    // Update a value with a new value of the same size, which may be done in place.
+    let idx = idx % node.entries_len();
+    let new_value = vec![byte; node.value(idx, &mem).len()];
+    let mut node = Node::<Vec<u8>>::load_v2(node_addr, PageSize::Value(node_data.page_size), &mem);
+    node.update_value(idx, new_value.clone(), &mut allocator);
+
+    // Reload the node and check that only the value was updated.
+    let node = Node::<Vec<u8>>::load_v2(node_addr, PageSize::Value(node_data.page_size), &mem);
+    assert_eq!(node.children, node_data.children());
+    let mut expected: Vec<_> = node_data.entries.into_iter().collect();
+    expected[idx].1 = new_value;
+    assert_eq!(node.entries(&mem), expected);
+}
+
+#[proptest]
+fn updating_v1_values_in_place(node_data: NodeV1Data, #[strategy(0..CAPACITY)] idx: usize) {
+    let v1_size = v1::size_v1(node_data.max_key_size, node_data.max_value_size, CAPACITY);
+    let mem = make_memory();
+    let mut allocator = Allocator::new(mem.clone(), Address::from(0), v1_size);
+
+    let node_addr = allocator.allocate();
+    let node = node_data.get(node_addr);
+    node.save_v1(allocator.memory());
+
+    // Update a value with a new value of a different size.
+    let idx = idx % node.entries_len();
+    let new_value = vec![1; node_data.max_value_size as usize];
+    let mut node = Node::<Vec<u8>>::load_v1(
+        node_addr,
+        node_data.max_key_size,
+        node_data.max_value_size,
+        &mem,
+    );
+    node.update_value(idx, new_value.clone(), &mut allocator);
+
+    // Reload the node and check that only the value was updated.
+    let node = Node::<Vec<u8>>::load_v1(
+        node_addr,
+        node_data.max_key_size,
+        node_data.max_value_size,
+        &mem,
+    );
+    assert_eq!(node.children, node_data.children());
+    let mut expected: Vec<_> = node_data.entries.into_iter().collect();
+    expected[idx].1 = new_value;
+    assert_eq!(node.entries(&mem), expected);
+}
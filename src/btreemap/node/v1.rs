@@ -57,6 +57,9 @@ impl<K: Storable + Ord + Clone> Node<K> {
                 max_value_size,
             }),
             overflow: None,
+            keys_only: false,
+            child_counts: None,
+            branching_factor: B,
         }
     }
 
@@ -122,25 +125,13 @@ impl<K: Storable + Ord + Clone> Node<K> {
                 max_value_size,
             }),
             overflow: None,
+            keys_only: false,
+            child_counts: None,
+            branching_factor: B,
         }
     }
 
     pub(super) fn save_v1<M: Memory>(&self, memory: &M) {
-        match self.node_type {
-            NodeType::Leaf => {
-                assert!(self.children.is_empty());
-            }
-            NodeType::Internal => {
-                assert_eq!(self.children.len(), self.keys.len() + 1);
-            }
-        };
-
-        // We should never be saving an empty node.
-        assert!(!self.keys.is_empty() || !self.children.is_empty());
-
-        // Assert entries are sorted in strictly increasing order.
-        assert!(self.keys.windows(2).all(|e| e[0] < e[1]));
-
         let (max_key_size, max_value_size) = match self.version {
             Version::V1(DerivedPageSize {
                 max_key_size,
@@ -202,8 +193,17 @@ impl<K: Storable + Ord + Clone> Node<K> {
     }
 }
 
-/// Returns the size of a v1 node in bytes.
-pub(super) fn size_v1(max_key_size: u32, max_value_size: u32) -> Bytes {
+/// Returns the offset of the value at the given index, relative to the node's address.
+///
+/// Values in a v1 node are stored in fixed-size slots, so a value can be overwritten
+/// without rewriting the rest of the node.
+pub(super) fn value_offset_v1(idx: usize, max_key_size: u32, max_value_size: u32) -> Bytes {
+    let entry_size = U32_SIZE + Bytes::from(max_key_size) + U32_SIZE + Bytes::from(max_value_size);
+    NodeHeader::size() + entry_size * idx as u64 + U32_SIZE + Bytes::from(max_key_size)
+}
+
+/// Returns the size in bytes of a v1 node with the given capacity.
+pub(super) fn size_v1(max_key_size: u32, max_value_size: u32, capacity: usize) -> Bytes {
     let node_header_size = NodeHeader::size();
     let max_key_size = Bytes::from(max_key_size);
     let max_value_size = Bytes::from(max_value_size);
@@ -211,6 +211,6 @@ pub(super) fn size_v1(max_key_size: u32, max_value_size: u32) -> Bytes {
     let child_size = Address::size();
 
     node_header_size
-        + Bytes::from(CAPACITY as u64) * entry_size
-        + Bytes::from((CAPACITY + 1) as u64) * child_size
+        + Bytes::from(capacity as u64) * entry_size
+        + Bytes::from((capacity + 1) as u64) * child_size
 }
@@ -17,9 +17,9 @@
 //! ---------------------------------------- <-- Header
 //! Magic "BTN"             ↕ 3 bytes
 //! ----------------------------------------
-//! Layout version (2)      ↕ 1 byte
+//! Layout version (2 or 3) ↕ 1 byte
 //! ----------------------------------------
-//! Node type               ↕ 1 byte
+//! Node type (0, 1 or 2)   ↕ 1 byte
 //! ----------------------------------------
 //! # Entries (k)           ↕ 2 bytes
 //! ----------------------------------------
@@ -30,6 +30,12 @@
 //! ...
 //! ----------------------------------------
 //! Child(k + 1) address    ↕ 8 bytes
+//! ---------------------------------------- <-- Counts (counted internal nodes only)
+//! Child(0) count          ↕ 8 bytes
+//! ----------------------------------------
+//! ...
+//! ----------------------------------------
+//! Child(k + 1) count      ↕ 8 bytes
 //! ---------------------------------------- <-- Keys
 //! Key(0)
 //! ----------------------------------------
@@ -60,9 +66,19 @@
 //! ----------------------------------------
 //! ```
 //!
+//! ## Counts
+//!
+//! Internal nodes of maps created with `BTreeMap::new_with_counts` have a node type of 2,
+//! and store the number of entries in the subtree of each child after the children's
+//! addresses.
+//!
 //! ## Keys and Values
 //! Keys and values are both encoded in memory as blobs.
 //!
+//! Nodes of maps whose values are always empty (i.e. values of fixed-size types with a
+//! max size of zero) are keys-only nodes. Keys-only nodes have a layout version of 3
+//! and store no values at all, not even their sizes.
+//!
 //! If they are variable in size (i.e. their `IS_FIXED` attribute is set to false),
 //! then the size of the blob is encoded before the blob itself. Otherwise, no size
 //! information is stored.
@@ -79,6 +95,8 @@ use std::cmp::min;
 
 // Initial page
 const LAYOUT_VERSION_2: u8 = 2;
+const LAYOUT_VERSION_2_KEYS_ONLY: u8 = 3;
+const LAYOUT_VERSION_OFFSET: usize = 3;
 const NODE_TYPE_OFFSET: usize = 4;
 const NUM_ENTRIES_OFFSET: usize = 5;
 const OVERFLOW_ADDRESS_OFFSET: Bytes = Bytes::new(7);
@@ -92,7 +110,7 @@ const PAGE_OVERFLOW_DATA_OFFSET: Bytes = Bytes::new(11);
 // The minimum size a page can have.
 // Rationale: a page size needs to at least store the header (15 bytes) + all the children
 // addresses (88 bytes). We round that up to 128 to get a nice binary number.
-const MINIMUM_PAGE_SIZE: u32 = 128;
+pub(super) const MINIMUM_PAGE_SIZE: u32 = 128;
 
 impl<K: Storable + Ord + Clone> Node<K> {
     /// Creates a new v2 node at the given address.
@@ -107,6 +125,9 @@ impl<K: Storable + Ord + Clone> Node<K> {
             encoded_values: RefCell::default(),
             children: vec![],
             overflow: None,
+            keys_only: false,
+            child_counts: None,
+            branching_factor: B,
         }
     }
 
@@ -118,9 +139,12 @@ impl<K: Storable + Ord + Clone> Node<K> {
         // Load the node type.
         let node_type = match node_buf[NODE_TYPE_OFFSET] {
             LEAF_NODE_TYPE => NodeType::Leaf,
-            INTERNAL_NODE_TYPE => NodeType::Internal,
+            INTERNAL_NODE_TYPE | COUNTED_INTERNAL_NODE_TYPE => NodeType::Internal,
             other => unreachable!("Unknown node type {}", other),
         };
+        let counted = node_buf[NODE_TYPE_OFFSET] == COUNTED_INTERNAL_NODE_TYPE;
+
+        let keys_only = node_buf[LAYOUT_VERSION_OFFSET] == LAYOUT_VERSION_2_KEYS_ONLY;
 
         // Load the number of entries
         let num_entries = read_u16_from_slice(&node_buf, NUM_ENTRIES_OFFSET) as usize;
@@ -137,6 +161,18 @@ impl<K: Storable + Ord + Clone> Node<K> {
             }
         }
 
+        // Load the counts of the children's subtrees if the node stores them.
+        let child_counts = if counted {
+            let mut counts = Vec::with_capacity(num_entries + 1);
+            for _ in 0..num_entries + 1 {
+                counts.push(read_u64_from_slice(&node_buf, offset.get() as usize));
+                offset += Bytes::new(8);
+            }
+            Some(counts)
+        } else {
+            None
+        };
+
         // Load the keys.
         let mut keys = Vec::with_capacity(num_entries);
         let mut encoded_values = Vec::with_capacity(num_entries);
@@ -159,6 +195,12 @@ impl<K: Storable + Ord + Clone> Node<K> {
 
         // Load the values
         for _ in 0..num_entries {
+            if keys_only {
+                // The values are all empty and aren't stored.
+                encoded_values.push(Value::ByVal(vec![]));
+                continue;
+            }
+
             // Load the value's size.
             let value_size = read_u32_from_slice(&node_buf, offset) as usize;
             offset += U32_SIZE;
@@ -187,11 +229,14 @@ impl<K: Storable + Ord + Clone> Node<K> {
             } else {
                 Some(original_overflow_address)
             },
+            keys_only,
+            child_counts,
+            branching_factor: B,
         }
     }
 
     // Saves the node to memory.
-    pub(super) fn save_v2<M: Memory>(&self, allocator: &mut Allocator<M>) {
+    pub(super) fn save_v2<M: Memory>(&mut self, allocator: &mut Allocator<M>) {
         let page_size = self.version.page_size();
         assert!(page_size >= MINIMUM_PAGE_SIZE);
         assert_eq!(self.keys.len(), self.encoded_values.borrow().len());
@@ -199,10 +244,15 @@ impl<K: Storable + Ord + Clone> Node<K> {
         // A buffer to serialize the node into first, then write to memory.
         let mut buf = vec![];
         buf.extend_from_slice(MAGIC);
-        buf.extend_from_slice(&[LAYOUT_VERSION_2]);
-        buf.push(match self.node_type {
-            NodeType::Leaf => LEAF_NODE_TYPE,
-            NodeType::Internal => INTERNAL_NODE_TYPE,
+        buf.push(if self.keys_only {
+            LAYOUT_VERSION_2_KEYS_ONLY
+        } else {
+            LAYOUT_VERSION_2
+        });
+        buf.push(match (self.node_type, &self.child_counts) {
+            (NodeType::Leaf, _) => LEAF_NODE_TYPE,
+            (NodeType::Internal, None) => INTERNAL_NODE_TYPE,
+            (NodeType::Internal, Some(_)) => COUNTED_INTERNAL_NODE_TYPE,
         });
         buf.extend_from_slice(&(self.keys.len() as u16).to_le_bytes());
 
@@ -222,6 +272,11 @@ impl<K: Storable + Ord + Clone> Node<K> {
             buf.extend_from_slice(&child.get().to_le_bytes());
         }
 
+        // Write the counts of the children's subtrees.
+        for count in self.child_counts.iter().flatten() {
+            buf.extend_from_slice(&count.to_le_bytes());
+        }
+
         // Write the keys.
         for key in self.keys.iter() {
             // Write the size of the key.
@@ -234,8 +289,13 @@ impl<K: Storable + Ord + Clone> Node<K> {
 
         // Write the values.
         for idx in 0..self.entries_len() {
-            // Write the size of the value.
             let value = self.value(idx, memory);
+            if self.keys_only {
+                assert!(value.is_empty(), "A keys-only node cannot store values.");
+                continue;
+            }
+
+            // Write the size of the value.
             buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
 
             // Write the value.
@@ -245,10 +305,50 @@ impl<K: Storable + Ord + Clone> Node<K> {
         self.write_paginated(buf, allocator, page_size as usize);
     }
 
+    // Returns the offset of the value at the given index, relative to the node's address,
+    // if a new value of the given size can be written over it without rewriting the rest
+    // of the node.
+    //
+    // This is only possible if the node is stored in the v2 layout, fits in its initial
+    // page, and the size of the value doesn't change.
+    pub(super) fn value_offset_v2<M: Memory>(
+        &self,
+        idx: usize,
+        new_value_size: usize,
+        memory: &M,
+    ) -> Option<Bytes> {
+        if self.overflow.is_some() || self.value(idx, memory).len() != new_value_size {
+            return None;
+        }
+
+        // The node may still be stored in the v1 layout if the map is being migrated.
+        let mut version = [0];
+        memory.read(
+            (self.address + Bytes::from(LAYOUT_VERSION_OFFSET as u64)).get(),
+            &mut version,
+        );
+        if version[0] != LAYOUT_VERSION_2 {
+            return None;
+        }
+
+        let mut offset = ENTRIES_OFFSET + Address::size() * self.children.len() as u64;
+        if let Some(counts) = &self.child_counts {
+            offset += Bytes::new(8) * counts.len() as u64;
+        }
+        for key in self.keys.iter() {
+            offset += U32_SIZE + Bytes::from(key.to_bytes().len() as u64);
+        }
+        for i in 0..idx {
+            offset += U32_SIZE + Bytes::from(self.value(i, memory).len() as u64);
+        }
+
+        Some(offset)
+    }
+
     // Writes a buffer into pages of the given page size.
     // Pages can be allocated and deallocated as needed.
     fn write_paginated<M: Memory>(
-        &self,
+        &mut self,
         buf: Vec<u8>,
         allocator: &mut Allocator<M>,
         page_size: usize,
@@ -309,6 +409,9 @@ impl<K: Storable + Ord + Clone> Node<K> {
 
             i += 1;
         }
+
+        // Keep track of the overflow pages so that subsequent saves reuse them.
+        self.overflow = overflow_addresses.first().copied();
     }
 
     fn reallocate_overflow_pages<M: Memory>(
@@ -333,7 +436,8 @@ impl<K: Storable + Ord + Clone> Node<K> {
         addresses
     }
 
-    fn get_overflow_addresses<M: Memory>(&self, memory: &M) -> Vec<Address> {
+    /// Returns the addresses of all the overflow pages of this node.
+    pub(super) fn get_overflow_addresses<M: Memory>(&self, memory: &M) -> Vec<Address> {
         let mut overflow_addresses = vec![];
         let mut next = self.overflow;
         while let Some(overflow_address) = next {
@@ -0,0 +1,261 @@
+//! A bounded in-heap cache of the nodes of a map.
+//!
+//! Lookups always start at the root, so the root and the levels below it are loaded
+//! from memory over and over, even though they rarely change. Caching them saves both
+//! reading them from memory and decoding their keys.
+//!
+//! Nodes are cached by address and the least recently used node is evicted when the
+//! cache is full. A cached node is only valid as long as the node in memory doesn't
+//! change, so nodes are removed from the cache whenever they're saved or deallocated.
+use super::node::Node;
+use crate::{types::Address, Storable};
+use std::collections::BTreeMap as StdBTreeMap;
+
+pub struct NodeCache<K: Storable + Ord + Clone> {
+    // The maximum number of nodes in the cache.
+    capacity: usize,
+
+    // The cached nodes by address, along with the time they were last used.
+    nodes: StdBTreeMap<Address, (u64, Node<K>)>,
+
+    // The addresses of the cached nodes by the time they were last used.
+    last_used: StdBTreeMap<u64, Address>,
+
+    // A counter incremented whenever a node is used.
+    clock: u64,
+}
+
+impl<K: Storable + Ord + Clone> NodeCache<K> {
+    /// Creates a cache holding up to `capacity` nodes. A capacity of zero disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            nodes: StdBTreeMap::new(),
+            last_used: StdBTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns the maximum number of nodes in the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns a copy of the node at the given address, if it's cached.
+    pub fn get(&mut self, address: Address) -> Option<Node<K>> {
+        let (last_used, node) = self.nodes.get_mut(&address)?;
+        self.clock += 1;
+        self.last_used.remove(last_used);
+        self.last_used.insert(self.clock, address);
+        *last_used = self.clock;
+        Some(node.clone())
+    }
+
+    /// Adds a copy of the node to the cache, evicting the least recently used node if
+    /// the cache is full.
+    pub fn insert(&mut self, node: &Node<K>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.remove(node.address());
+        if self.nodes.len() >= self.capacity {
+            let (_, address) = self
+                .last_used
+                .pop_first()
+                .expect("a full cache must have nodes");
+            self.nodes.remove(&address);
+        }
+
+        self.clock += 1;
+        self.last_used.insert(self.clock, node.address());
+        self.nodes
+            .insert(node.address(), (self.clock, node.clone()));
+    }
+
+    /// Removes the node at the given address from the cache, if it's cached.
+    pub fn remove(&mut self, address: Address) {
+        if let Some((last_used, _)) = self.nodes.remove(&address) {
+            self.last_used.remove(&last_used);
+        }
+    }
+
+    /// Removes all the nodes from the cache.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.last_used.clear();
+    }
+
+    /// Returns the number of nodes in the cache.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::btreemap::{
+        node::{NodeType, PageSize, Version},
+        BTreeMap, Config,
+    };
+    use proptest::collection::vec as pvec;
+    use proptest::prelude::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+        Rc::new(RefCell::new(Vec::new()))
+    }
+
+    #[derive(Clone, Debug)]
+    enum Operation {
+        Insert(u32, u32),
+        Remove(u32),
+        Update(u32, u32),
+        EntryInsert(u32, u32),
+        RemoveRange(u32, u32),
+        Append(Vec<u32>),
+        ClearStep(u64),
+    }
+
+    fn arb_operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            4 => (0..1000u32, any::<u32>()).prop_map(|(k, v)| Operation::Insert(k, v)),
+            2 => (0..1000u32).prop_map(Operation::Remove),
+            1 => (0..1000u32, any::<u32>()).prop_map(|(k, v)| Operation::Update(k, v)),
+            1 => (0..1000u32, any::<u32>()).prop_map(|(k, v)| Operation::EntryInsert(k, v)),
+            1 => (0..1000u32, 0..50u32).prop_map(|(k, n)| Operation::RemoveRange(k, k + n)),
+            1 => pvec(1000..2000u32, 0..100).prop_map(Operation::Append),
+            1 => (1..10u64).prop_map(Operation::ClearStep),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20))]
+        #[test]
+        fn cached_map_matches_uncached_map(
+            cache_size in 1..20usize,
+            counts in any::<bool>(),
+            ops in pvec(arb_operation(), 0..300),
+        ) {
+            let config = Config::default().branching_factor(3).counts(counts);
+            let mut cached = BTreeMap::new_with_config(make_memory(), config);
+            cached.set_node_cache_size(cache_size);
+            let mut uncached = BTreeMap::new_with_config(make_memory(), config);
+
+            for op in ops {
+                match op {
+                    Operation::Insert(k, v) => {
+                        prop_assert_eq!(cached.insert(k, v), uncached.insert(k, v));
+                    }
+                    Operation::Remove(k) => {
+                        prop_assert_eq!(cached.remove(&k), uncached.remove(&k));
+                    }
+                    Operation::Update(k, v) => {
+                        prop_assert_eq!(
+                            cached.update(&k, |x| *x = v),
+                            uncached.update(&k, |x| *x = v)
+                        );
+                    }
+                    Operation::EntryInsert(k, v) => {
+                        prop_assert_eq!(
+                            cached.entry(k).and_modify(|x| *x = v).or_insert(v),
+                            uncached.entry(k).and_modify(|x| *x = v).or_insert(v)
+                        );
+                    }
+                    Operation::RemoveRange(start, end) => {
+                        prop_assert_eq!(
+                            cached.remove_range(start..end),
+                            uncached.remove_range(start..end)
+                        );
+                    }
+                    Operation::Append(keys) => {
+                        let mut other = BTreeMap::new(make_memory());
+                        for k in keys {
+                            other.insert(k, k);
+                        }
+                        let mut other_copy = BTreeMap::new(make_memory());
+                        for (k, v) in other.iter() {
+                            other_copy.insert(k, v);
+                        }
+                        cached.append(&mut other);
+                        uncached.append(&mut other_copy);
+                    }
+                    Operation::ClearStep(max_nodes) => {
+                        prop_assert_eq!(
+                            cached.clear_step(max_nodes),
+                            uncached.clear_step(max_nodes)
+                        );
+                    }
+                }
+
+                // Lookups are served from the cache, which must be up to date.
+                prop_assert!(cached.node_cache.borrow().len() <= cache_size);
+                prop_assert_eq!(cached.len(), uncached.len());
+                prop_assert_eq!(cached.first_key_value(), uncached.first_key_value());
+                prop_assert_eq!(cached.get(&500), uncached.get(&500));
+            }
+
+            prop_assert_eq!(
+                cached.iter().collect::<Vec<_>>(),
+                uncached.iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_node() {
+        let version = Version::V2(PageSize::Value(128));
+        let node =
+            |address: u64| Node::<u64>::new(Address::from(address), NodeType::Leaf, version, 6);
+        let mut cache = NodeCache::new(2);
+        cache.insert(&node(1));
+        cache.insert(&node(2));
+
+        // Using the first node makes the second one the least recently used.
+        assert!(cache.get(Address::from(1)).is_some());
+        cache.insert(&node(3));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(Address::from(2)).is_none());
+        assert!(cache.get(Address::from(1)).is_some());
+        assert!(cache.get(Address::from(3)).is_some());
+
+        cache.remove(Address::from(1));
+        assert!(cache.get(Address::from(1)).is_none());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn cache_of_size_zero_is_disabled() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..100u64 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.get(&7), Some(7));
+        assert_eq!(map.node_cache.borrow().len(), 0);
+
+        map.set_node_cache_size(8);
+        assert_eq!(map.get(&7), Some(7));
+        assert!(map.node_cache.borrow().len() > 0);
+    }
+
+    #[test]
+    fn migrating_to_v2_clears_cache() {
+        let mem = make_memory();
+        let mut map = BTreeMap::new_v1(mem);
+        for i in 0..100u64 {
+            map.insert(i, i);
+        }
+        let mut map: BTreeMap<u64, u64, _> = BTreeMap::init_v1(map.into_memory());
+        map.set_node_cache_size(100);
+        assert_eq!(map.get(&7), Some(7));
+
+        while !map.migrate_step(10) {}
+        map.insert(100, 100);
+        for i in 0..=100u64 {
+            assert_eq!(map.get(&i), Some(i));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::storable::{Bound, Storable};
+use std::borrow::Cow;
+
+// Tags identifying the variants of a `PageCursor` in its serialized form.
+const TAG_START: u8 = 0;
+const TAG_AFTER: u8 = 1;
+
+/// A position in a [`BTreeMap`](crate::BTreeMap) used to resume an iteration across calls.
+///
+/// The cursor is derived from the last key returned, rather than from the structure of
+/// the tree, so it remains valid when entries are inserted or removed between calls.
+/// Iteration resumes at the first key greater than the last key returned.
+///
+/// Cursors implement [`Storable`], so they can be serialized and handed to clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageCursor<K> {
+    last_key: Option<K>,
+}
+
+impl<K> PageCursor<K> {
+    /// Returns a cursor pointing to the start of the map.
+    pub fn start() -> Self {
+        Self { last_key: None }
+    }
+
+    /// Returns a cursor pointing to the first key after the given key.
+    pub fn after(key: K) -> Self {
+        Self {
+            last_key: Some(key),
+        }
+    }
+
+    /// Returns the last key returned before this cursor, if any.
+    pub fn last_key(&self) -> Option<&K> {
+        self.last_key.as_ref()
+    }
+}
+
+impl<K: Storable> Storable for PageCursor<K> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        match &self.last_key {
+            None => Cow::Owned(vec![TAG_START]),
+            Some(key) => {
+                let key_bytes = key.to_bytes();
+                let mut bytes = Vec::with_capacity(1 + key_bytes.len());
+                bytes.push(TAG_AFTER);
+                bytes.extend_from_slice(&key_bytes);
+                Cow::Owned(bytes)
+            }
+        }
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match bytes[0] {
+            TAG_START => {
+                assert_eq!(bytes.len(), 1, "Invalid cursor.");
+                Self::start()
+            }
+            TAG_AFTER => Self::after(K::from_bytes(Cow::Borrowed(&bytes[1..]))),
+            other => panic!("Invalid cursor tag: {other}"),
+        }
+    }
+
+    const BOUND: Bound = match K::BOUND {
+        Bound::Bounded { max_size, .. } => Bound::Bounded {
+            max_size: max_size + 1,
+            is_fixed_size: false,
+        },
+        Bound::Unbounded => Bound::Unbounded,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let cursor = PageCursor::<u64>::start();
+        assert_eq!(cursor.to_bytes().as_ref(), &[TAG_START]);
+        assert_eq!(PageCursor::<u64>::from_bytes(cursor.to_bytes()), cursor);
+
+        let cursor = PageCursor::after(String::from("key"));
+        assert_eq!(cursor.to_bytes().as_ref(), b"\x01key");
+        assert_eq!(PageCursor::<String>::from_bytes(cursor.to_bytes()), cursor);
+
+        // An empty key is distinct from the start of the map.
+        let cursor = PageCursor::after(String::new());
+        assert_eq!(PageCursor::<String>::from_bytes(cursor.to_bytes()), cursor);
+    }
+
+    #[test]
+    fn cursor_bound() {
+        assert!(matches!(
+            PageCursor::<u64>::BOUND,
+            Bound::Bounded {
+                max_size: 9,
+                is_fixed_size: false
+            }
+        ));
+        assert!(matches!(PageCursor::<String>::BOUND, Bound::Unbounded));
+    }
+}
@@ -0,0 +1,72 @@
+use std::cmp::Ordering;
+
+/// A prefix of keys of type `K`, used in [`BTreeMap::prefix_iter`](crate::BTreeMap::prefix_iter).
+///
+/// The keys that start with a prefix must be contiguous in the map's order. This
+/// is the case for tuples, where the prefix is the first element(s) of the tuple,
+/// and for strings and byte sequences.
+pub trait KeyPrefix<K> {
+    /// Compares a key to the prefix.
+    ///
+    /// Returns `Ordering::Equal` if the key starts with the prefix, and otherwise
+    /// whether the key comes before (`Less`) or after (`Greater`) all the keys that
+    /// start with the prefix.
+    fn cmp_key(&self, key: &K) -> Ordering;
+}
+
+impl<A: Ord, B> KeyPrefix<(A, B)> for A {
+    fn cmp_key(&self, key: &(A, B)) -> Ordering {
+        key.0.cmp(self)
+    }
+}
+
+impl<A: Ord, B, C> KeyPrefix<(A, B, C)> for A {
+    fn cmp_key(&self, key: &(A, B, C)) -> Ordering {
+        key.0.cmp(self)
+    }
+}
+
+impl<A: Ord, B: Ord, C> KeyPrefix<(A, B, C)> for (A, B) {
+    fn cmp_key(&self, key: &(A, B, C)) -> Ordering {
+        (&key.0, &key.1).cmp(&(&self.0, &self.1))
+    }
+}
+
+impl<A: Ord, B, C, D> KeyPrefix<(A, B, C, D)> for A {
+    fn cmp_key(&self, key: &(A, B, C, D)) -> Ordering {
+        key.0.cmp(self)
+    }
+}
+
+impl<A: Ord, B: Ord, C, D> KeyPrefix<(A, B, C, D)> for (A, B) {
+    fn cmp_key(&self, key: &(A, B, C, D)) -> Ordering {
+        (&key.0, &key.1).cmp(&(&self.0, &self.1))
+    }
+}
+
+impl<A: Ord, B: Ord, C: Ord, D> KeyPrefix<(A, B, C, D)> for (A, B, C) {
+    fn cmp_key(&self, key: &(A, B, C, D)) -> Ordering {
+        (&key.0, &key.1, &key.2).cmp(&(&self.0, &self.1, &self.2))
+    }
+}
+
+impl KeyPrefix<Vec<u8>> for [u8] {
+    fn cmp_key(&self, key: &Vec<u8>) -> Ordering {
+        cmp_bytes(key, self)
+    }
+}
+
+impl KeyPrefix<String> for str {
+    fn cmp_key(&self, key: &String) -> Ordering {
+        cmp_bytes(key.as_bytes(), self.as_bytes())
+    }
+}
+
+/// Compares a byte sequence to a prefix of byte sequences.
+pub(crate) fn cmp_bytes(bytes: &[u8], prefix: &[u8]) -> Ordering {
+    if bytes.starts_with(prefix) {
+        Ordering::Equal
+    } else {
+        bytes.cmp(prefix)
+    }
+}
@@ -60,4 +60,179 @@ proptest! {
             prop_assert_eq!(Some((*k, ())), map.iter_upper_bound(&(k + 1)).next());
         }
     }
+
+    #[test]
+    fn prefix_iter(
+        keys in pvec((0..10u8, any::<u16>(), any::<u8>()), 0..1000),
+        a in 0..10u8,
+        b in any::<u16>(),
+    ) {
+        let mut map = BTreeMap::new(make_memory());
+        let mut std_map = std::collections::BTreeMap::new();
+        for key in keys {
+            map.insert(key, ());
+            std_map.insert(key, ());
+        }
+
+        // A prefix of one element.
+        let expected: Vec<_> = std_map.keys().filter(|k| k.0 == a).cloned().collect();
+        let actual: Vec<_> = map.prefix_iter(&a).map(|(k, _)| k).collect();
+        prop_assert_eq!(&actual, &expected);
+
+        // Iterating backwards starts from the last key with the prefix.
+        let actual: Vec<_> = map.prefix_iter(&a).rev().map(|(k, _)| k).collect();
+        prop_assert_eq!(actual, expected.into_iter().rev().collect::<Vec<_>>());
+
+        // A prefix of two elements.
+        let expected: Vec<_> = std_map.keys().filter(|k| (k.0, k.1) == (a, b)).cloned().collect();
+        let actual: Vec<_> = map.prefix_iter(&(a, b)).map(|(k, _)| k).collect();
+        prop_assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn floor_ceiling_predecessor_successor(keys in pset(any::<u16>(), 0..3000), queries in pvec(any::<u16>(), 100)) {
+        let mut map = BTreeMap::new(make_memory());
+        for k in keys.iter() {
+            map.insert(*k, *k as u32 + 1);
+        }
+
+        let entry = |k: Option<&u16>| k.map(|k| (*k, *k as u32 + 1));
+        for q in queries {
+            prop_assert_eq!(map.floor(&q), entry(keys.range(..=q).next_back()));
+            prop_assert_eq!(map.ceiling(&q), entry(keys.range(q..).next()));
+            prop_assert_eq!(map.predecessor(&q), entry(keys.range(..q).next_back()));
+            prop_assert_eq!(
+                map.successor(&q),
+                entry(keys.range((std::ops::Bound::Excluded(q), std::ops::Bound::Unbounded)).next())
+            );
+        }
+    }
+
+    #[test]
+    fn range_rev(keys in pset(any::<u16>(), 0..1000), start in any::<u16>(), len in 0..5000u16) {
+        let mut map = BTreeMap::new(make_memory());
+        for k in keys.iter() {
+            map.insert(*k, ());
+        }
+
+        let end = start.saturating_add(len);
+        let expected: Vec<_> = keys.range(start..end).rev().cloned().collect();
+        let actual: Vec<_> = map.range(start..end).rev().map(|(k, _)| k).collect();
+        prop_assert_eq!(actual, expected);
+
+        let expected: Vec<_> = keys.range(..=end).rev().cloned().collect();
+        let actual: Vec<_> = map.range(..=end).rev().map(|(k, _)| k).collect();
+        prop_assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn iterate_from_both_ends(keys in pset(any::<u16>(), 0..1000), directions in pvec(any::<bool>(), 0..1000)) {
+        let mut map = BTreeMap::new(make_memory());
+        for k in keys.iter() {
+            map.insert(*k, ());
+        }
+
+        let mut expected = keys.iter().cloned();
+        let mut actual = map.iter().map(|(k, _)| k);
+        for forward in directions {
+            if forward {
+                prop_assert_eq!(actual.next(), expected.next());
+            } else {
+                prop_assert_eq!(actual.next_back(), expected.next_back());
+            }
+        }
+    }
+
+    #[test]
+    fn entry(ops in pvec((0..100u8, any::<u8>(), any::<bool>()), 0..1000)) {
+        let mut map = BTreeMap::new(make_memory());
+        let mut std_map = std::collections::BTreeMap::new();
+
+        for (key, value, modify) in ops {
+            if modify {
+                map.entry(key).and_modify(|v| *v ^= value).or_insert(value);
+                std_map.entry(key).and_modify(|v| *v ^= value).or_insert(value);
+            } else {
+                prop_assert_eq!(map.entry(key).or_insert(value), *std_map.entry(key).or_insert(value));
+            }
+        }
+
+        prop_assert_eq!(map.len(), std_map.len() as u64);
+        prop_assert_eq!(map.iter().collect::<Vec<_>>(), std_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn from_sorted_iter(keys in pset(any::<u32>(), 0..3000), removes in pvec(any::<u32>(), 0..500)) {
+        let mut map = BTreeMap::from_sorted_iter(make_memory(), keys.iter().map(|k| (*k, *k))).unwrap();
+        let mut std_map: std::collections::BTreeMap<_, _> = keys.iter().map(|k| (*k, *k)).collect();
+        prop_assert_eq!(map.len(), std_map.len() as u64);
+
+        // Remove existing and missing keys to exercise rebalancing of the loaded nodes.
+        let keys: Vec<_> = keys.into_iter().collect();
+        for r in removes {
+            let key = if keys.is_empty() || r % 2 == 0 { r } else { keys[r as usize % keys.len()] };
+            prop_assert_eq!(map.remove(&key), std_map.remove(&key));
+        }
+
+        prop_assert_eq!(map.iter().collect::<Vec<_>>(), std_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn remove_range(
+        keys in pset(any::<u16>(), 0..3000),
+        ranges in pvec((any::<u16>(), 0..3000u16), 1..10)
+    ) {
+        let mut map = BTreeMap::new(make_memory());
+        let mut std_map = std::collections::BTreeMap::new();
+        for key in keys {
+            map.insert(key, key);
+            std_map.insert(key, key);
+        }
+
+        for (start, len) in ranges {
+            let range = start..start.saturating_add(len);
+            let removed: Vec<_> = std_map.range(range.clone()).map(|(k, _)| *k).collect();
+            for key in removed.iter() {
+                std_map.remove(key);
+            }
+
+            prop_assert_eq!(map.remove_range(range), removed.len() as u64);
+            prop_assert_eq!(map.len(), std_map.len() as u64);
+        }
+
+        prop_assert_eq!(map.iter().collect::<Vec<_>>(), std_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn split_off_and_append(
+        keys in pset(any::<u16>(), 0..3000),
+        other_keys in pset(any::<u16>(), 0..3000),
+        split_key in any::<u16>()
+    ) {
+        let mut map = BTreeMap::new(make_memory());
+        let mut std_map = std::collections::BTreeMap::new();
+        for key in keys {
+            map.insert(key, key);
+            std_map.insert(key, key);
+        }
+
+        let mut other = map.split_off(&split_key, make_memory());
+        let mut std_other = std_map.split_off(&split_key);
+        prop_assert_eq!(map.len(), std_map.len() as u64);
+        prop_assert_eq!(other.iter().collect::<Vec<_>>(), std_other.clone().into_iter().collect::<Vec<_>>());
+
+        // Append the split off entries back, along with other entries.
+        let mut extra = BTreeMap::new(make_memory());
+        for key in other_keys {
+            extra.insert(key, key.wrapping_add(1));
+            std_other.insert(key, key.wrapping_add(1));
+        }
+        other.append(&mut extra);
+        map.append(&mut other);
+        std_map.append(&mut std_other);
+
+        prop_assert!(other.is_empty());
+        prop_assert_eq!(map.len(), std_map.len() as u64);
+        prop_assert_eq!(map.iter().collect::<Vec<_>>(), std_map.into_iter().collect::<Vec<_>>());
+    }
 }
@@ -0,0 +1,538 @@
+//! Removal of ranges of entries.
+//!
+//! Rather than removing the entries one at a time, which rebalances the tree after
+//! every removal, the subtrees that lie entirely inside the range are deallocated as a
+//! whole, and the tree is rebalanced once along the path where entries were removed.
+//!
+//! Removing entries from a node can leave it with fewer entries than required, and
+//! possibly with no entries at all. Such nodes are only ever found along a single path,
+//! where a node with no entries is followed by its only child. These nodes are kept in
+//! memory in a "chain" until they can be merged with, or borrow entries from, a sibling.
+use super::{
+    node::{Node, NodeType},
+    BTreeMap,
+};
+use crate::{types::NULL, Address, Memory, Storable};
+use std::ops::{Bound, RangeBounds};
+
+// The lower and upper bounds (both exclusive) of the keys in a subtree, where `None`
+// means that the subtree is unbounded.
+type Span<K> = (Option<K>, Option<K>);
+
+impl<K, V, M> BTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Removes all the entries whose keys are in the given range, and returns the
+    /// number of entries removed.
+    ///
+    /// Subtrees whose keys all lie in the range are deallocated without being
+    /// rebalanced entry by entry, making the removal of large ranges cheap.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// for i in 0..100 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// // Remove all the entries with keys below 90.
+    /// assert_eq!(map.remove_range(..90), 90);
+    /// assert_eq!(map.len(), 10);
+    /// assert_eq!(map.first_key_value(), Some((90, 90)));
+    /// ```
+    pub fn remove_range(&mut self, key_range: impl RangeBounds<K>) -> u64 {
+        let end = key_range.end_bound().cloned();
+        let mut range = (key_range.start_bound().cloned(), end.clone());
+
+        if self.range(range.clone()).next().is_none() {
+            // There's nothing to remove.
+            return 0;
+        }
+
+        let length = self.length;
+        while self.root_addr != NULL {
+            let mut separator = None;
+            let root = self.load_node(self.root_addr);
+            let chain = self.remove_range_helper(root, &mut range, &mut separator, (None, None));
+            self.set_root_from_chain(chain);
+
+            let separator = match separator {
+                Some(separator) => separator,
+                None => break,
+            };
+
+            // An entry in the range was kept to separate the two paths in the tree where
+            // entries were removed. Remove it, along with the rest of the range, if any.
+            self.remove(&separator);
+            if range.1 == end {
+                break;
+            }
+            range = (Bound::Excluded(separator), end.clone());
+        }
+
+        self.update_counts();
+        self.save();
+        length - self.length
+    }
+
+    /// Retains only the entries for which the predicate returns `true`.
+    ///
+    /// The entries are visited in ascending order of their keys. Consecutive entries
+    /// that are not retained are removed together as in [`BTreeMap::remove_range`].
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&mut self, mut f: F) {
+        // The first and last keys of every run of consecutive entries to remove.
+        let mut runs: Vec<(K, K)> = vec![];
+        let mut in_run = false;
+        for (key, value) in self.iter() {
+            if f(&key, &value) {
+                in_run = false;
+            } else if in_run {
+                runs.last_mut().expect("a run must exist").1 = key;
+            } else {
+                runs.push((key.clone(), key));
+                in_run = true;
+            }
+        }
+
+        for (first, last) in runs {
+            self.remove_range(first..=last);
+        }
+    }
+
+    /// Removes all the entries whose keys are in the given range, and returns them in
+    /// ascending order of their keys.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// for i in 0..10 {
+    ///     map.insert(i, i * 10);
+    /// }
+    ///
+    /// assert_eq!(map.drain_range(3..5), vec![(3, 30), (4, 40)]);
+    /// assert_eq!(map.len(), 8);
+    /// ```
+    pub fn drain_range(&mut self, key_range: impl RangeBounds<K>) -> Vec<(K, V)> {
+        let range = (
+            key_range.start_bound().cloned(),
+            key_range.end_bound().cloned(),
+        );
+
+        let entries = self.range(range.clone()).collect();
+        self.remove_range(range);
+        entries
+    }
+
+    // Removes the entries in `range` from the subtree rooted at `node`, where `span`
+    // bounds the keys of the subtree.
+    //
+    // Returns the chain of nodes starting at `node`, which are yet to be saved. All the
+    // other nodes in the subtree are valid and saved.
+    //
+    // Rebalancing the tree along two paths, one for each end of the range, is avoided by
+    // keeping an entry of the range as the `separator` of the two paths, and cutting the
+    // range short to end (or start) at that entry. The separator and the rest of the
+    // range are left to be removed afterwards.
+    fn remove_range_helper(
+        &mut self,
+        mut node: Node<K>,
+        range: &mut (Bound<K>, Bound<K>),
+        separator: &mut Option<K>,
+        (lo, hi): Span<K>,
+    ) -> Vec<Node<K>> {
+        // The entries in the range are those at indices `start..end`.
+        let mut start = node.partition_point(|key| is_before_range(key, range));
+        let mut end = node
+            .partition_point(|key| !is_after_range(key, range))
+            .max(start);
+
+        if node.node_type() == NodeType::Leaf {
+            for _ in start..end {
+                node.remove_entry(start, self.memory());
+            }
+            self.length -= (end - start) as u64;
+            return vec![node];
+        }
+
+        let span_of = |node: &Node<K>, idx: usize| -> Span<K> {
+            (
+                if idx == 0 {
+                    lo.clone()
+                } else {
+                    Some(node.key(idx - 1).clone())
+                },
+                if idx == node.entries_len() {
+                    hi.clone()
+                } else {
+                    Some(node.key(idx).clone())
+                },
+            )
+        };
+
+        if end > start
+            && !contains_span(range, &span_of(&node, start))
+            && !contains_span(range, &span_of(&node, end))
+        {
+            // Both ends of the range are inside children of this node, or the range starts
+            // at the first entry in it. Keep an entry to separate the children at either end.
+            if matches!(&range.0, Bound::Included(key) if key == node.key(start)) {
+                range.0 = Bound::Excluded(node.key(start).clone());
+                *separator = Some(node.key(start).clone());
+                start += 1;
+            } else {
+                range.1 = Bound::Excluded(node.key(end - 1).clone());
+                *separator = Some(node.key(end - 1).clone());
+                end -= 1;
+            }
+        }
+
+        // Every child between the first and last entries of the range is inside the range,
+        // and possibly the children at either end too. Deallocate them, except for one
+        // child in which to continue the removal.
+        let retained = if contains_span(range, &span_of(&node, start)) {
+            end
+        } else {
+            start
+        };
+        let retained_span = span_of(&node, retained);
+
+        for idx in (start..=end).rev() {
+            if idx != retained {
+                let child = node.remove_child(idx);
+                self.deallocate_subtree(child);
+            }
+        }
+        for _ in start..end {
+            node.remove_entry(start, self.memory());
+        }
+        self.length -= (end - start) as u64;
+
+        let child = self.load_node(node.child(start));
+        let child_chain = self.remove_range_helper(child, range, separator, retained_span);
+
+        if node.entries_len() == 0 {
+            // The child has no siblings to rebalance with.
+            let mut chain = vec![node];
+            chain.extend(child_chain);
+            return chain;
+        }
+
+        self.rebalance_child(&mut node, start, child_chain);
+        vec![node]
+    }
+
+    // Rebalances the child at index `idx` of `node`, given the child's chain, so that
+    // all the nodes in the chain are valid and saved. The node itself is not saved, and
+    // may lose an entry.
+    //
+    // PRECONDITION:
+    //   * `node` has at least one entry.
+    //   * the siblings of the child are valid.
+    pub(super) fn rebalance_child(
+        &mut self,
+        node: &mut Node<K>,
+        idx: usize,
+        mut chain: Vec<Node<K>>,
+    ) {
+        let mut child = chain.remove(0);
+        if !child.is_underfull() {
+            self.save_node(&mut child);
+            return;
+        }
+
+        // The index of the child's only child, if it's also in the chain.
+        let mut grandchild_idx = 0;
+
+        if idx > 0 {
+            let mut left_sibling = self.load_node(node.child(idx - 1));
+            if child.can_merge_with(&left_sibling) {
+                // Merge the child into its left sibling.
+                let median = node.remove_entry(idx - 1, self.memory());
+                node.remove_child(idx);
+
+                grandchild_idx = left_sibling.children_len();
+                left_sibling.push_entry(median);
+                while child.entries_len() > 0 {
+                    left_sibling.push_entry(child.remove_entry(0, self.memory()));
+                }
+                while child.children_len() > 0 {
+                    left_sibling.push_child(child.remove_child(0));
+                }
+                self.deallocate_node(child);
+                child = left_sibling;
+            } else {
+                // Move entries from the left sibling, which has plenty, into the child.
+                while child.at_minimum() {
+                    let entry = left_sibling.pop_entry(self.memory()).unwrap();
+                    let median = node.swap_entry(idx - 1, entry, self.memory());
+                    child.insert_entry(0, median);
+                    if let Some(last_child) = left_sibling.pop_child() {
+                        child.insert_child(0, last_child);
+                        grandchild_idx += 1;
+                    }
+                }
+                self.save_node(&mut left_sibling);
+            }
+        } else {
+            let mut right_sibling = self.load_node(node.child(idx + 1));
+            if child.can_merge_with(&right_sibling) {
+                // Merge the right sibling into the child.
+                let median = node.remove_entry(idx, self.memory());
+                node.remove_child(idx + 1);
+
+                child.push_entry(median);
+                while right_sibling.entries_len() > 0 {
+                    child.push_entry(right_sibling.remove_entry(0, self.memory()));
+                }
+                while right_sibling.children_len() > 0 {
+                    child.push_child(right_sibling.remove_child(0));
+                }
+                self.deallocate_node(right_sibling);
+            } else {
+                // Move entries from the right sibling, which has plenty, into the child.
+                while child.at_minimum() {
+                    let entry = right_sibling.remove_entry(0, self.memory());
+                    let median = node.swap_entry(idx, entry, self.memory());
+                    child.push_entry(median);
+                    if right_sibling.node_type() == NodeType::Internal {
+                        child.push_child(right_sibling.remove_child(0));
+                    }
+                }
+                self.save_node(&mut right_sibling);
+            }
+        }
+
+        if !chain.is_empty() {
+            // The child had no entries. Its only child can now be rebalanced with the
+            // children that were moved next to it.
+            self.rebalance_child(&mut child, grandchild_idx, chain);
+        }
+        self.save_node(&mut child);
+    }
+
+    // Sets the root of the tree given the chain of nodes starting at the root.
+    fn set_root_from_chain(&mut self, chain: Vec<Node<K>>) {
+        for mut node in chain {
+            if node.entries_len() == 0 {
+                // The node is either an empty leaf or has a single child, which becomes
+                // the new root.
+                self.root_addr = match node.node_type() {
+                    NodeType::Leaf => NULL,
+                    NodeType::Internal => node.child(0),
+                };
+                self.deallocate_node(node);
+            } else {
+                self.save_node(&mut node);
+                self.root_addr = node.address();
+            }
+        }
+    }
+
+    // Deallocates the subtree rooted at the given address.
+    fn deallocate_subtree(&mut self, address: Address) {
+        let node = self.load_node(address);
+        if node.node_type() == NodeType::Internal {
+            for idx in 0..node.children_len() {
+                self.deallocate_subtree(node.child(idx));
+            }
+        }
+        self.length -= node.entries_len() as u64;
+        self.deallocate_node(node);
+    }
+}
+
+// Returns true if the key comes before all the keys in the range.
+fn is_before_range<K: Ord>(key: &K, range: &(Bound<K>, Bound<K>)) -> bool {
+    match &range.0 {
+        Bound::Included(start) => key < start,
+        Bound::Excluded(start) => key <= start,
+        Bound::Unbounded => false,
+    }
+}
+
+// Returns true if the key comes after all the keys in the range.
+fn is_after_range<K: Ord>(key: &K, range: &(Bound<K>, Bound<K>)) -> bool {
+    match &range.1 {
+        Bound::Included(end) => key > end,
+        Bound::Excluded(end) => key >= end,
+        Bound::Unbounded => false,
+    }
+}
+
+// Returns true if all the keys within the span are in the range.
+fn contains_span<K: Ord>(range: &(Bound<K>, Bound<K>), (lo, hi): &Span<K>) -> bool {
+    let contains_lo = match (&range.0, lo) {
+        (Bound::Unbounded, _) => true,
+        (Bound::Included(start) | Bound::Excluded(start), Some(lo)) => lo >= start,
+        (_, None) => false,
+    };
+    let contains_hi = match (&range.1, hi) {
+        (Bound::Unbounded, _) => true,
+        (Bound::Included(end) | Bound::Excluded(end), Some(hi)) => hi <= end,
+        (_, None) => false,
+    };
+    contains_lo && contains_hi
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+        Rc::new(RefCell::new(Vec::new()))
+    }
+
+    // Checks that all the non-root nodes have enough entries and that all the leaves are
+    // at the same depth. Returns the depth of the leaves.
+    fn check_structure<K: Storable + Ord + Clone, V: Storable, M: Memory>(
+        map: &BTreeMap<K, V, M>,
+        address: Address,
+    ) -> usize {
+        let node = map.load_node(address);
+        assert!(node.entries_len() > 0);
+        assert!(address == map.root_addr || !node.is_underfull());
+        match node.node_type() {
+            NodeType::Leaf => 0,
+            NodeType::Internal => {
+                let depths: Vec<_> = (0..node.children_len())
+                    .map(|i| check_structure(map, node.child(i)))
+                    .collect();
+                assert!(depths.iter().all(|d| *d == depths[0]));
+                depths[0] + 1
+            }
+        }
+    }
+
+    fn check_map(map: &BTreeMap<u64, u64, Rc<RefCell<Vec<u8>>>>, expected: &[u64]) {
+        assert_eq!(map.len(), expected.len() as u64);
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            expected.iter().map(|k| (*k, *k)).collect::<Vec<_>>()
+        );
+        if map.root_addr != NULL {
+            check_structure(map, map.root_addr);
+        }
+    }
+
+    #[test]
+    fn remove_range() {
+        let n = 2000;
+        let ranges = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Unbounded, Bound::Excluded(1000)),
+            (Bound::Included(1000), Bound::Unbounded),
+            (Bound::Included(10), Bound::Included(1990)),
+            (Bound::Excluded(10), Bound::Excluded(11)),
+            (Bound::Included(500), Bound::Excluded(1500)),
+            (Bound::Included(0), Bound::Included(0)),
+            (Bound::Included(1999), Bound::Included(5000)),
+            (Bound::Excluded(1), Bound::Excluded(1999)),
+            (Bound::Included(3000), Bound::Unbounded),
+            (Bound::Included(700), Bound::Excluded(600)),
+        ];
+
+        for range in ranges {
+            let mut map = BTreeMap::new(make_memory());
+            for i in 0..n {
+                map.insert(i, i);
+            }
+
+            let expected: Vec<_> = (0..n).filter(|k| !range.contains(k)).collect();
+            assert_eq!(map.remove_range(range), n - expected.len() as u64);
+            check_map(&map, &expected);
+        }
+    }
+
+    #[test]
+    fn remove_range_deallocates_nodes() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..2000u64 {
+            map.insert(i, i);
+        }
+
+        map.remove_range(..);
+        assert!(map.is_empty());
+        assert_eq!(map.root_addr, NULL);
+        assert_eq!(map.allocator.num_allocated_chunks(), 0);
+
+        // The map remains usable.
+        map.insert(1, 1);
+        check_map(&map, &[1]);
+    }
+
+    #[test]
+    fn remove_range_repeatedly() {
+        let mut map = BTreeMap::new(make_memory());
+        let mut expected: Vec<u64> = (0..3000).collect();
+        for i in 0..3000u64 {
+            map.insert(i, i);
+        }
+
+        // Remove ranges of various sizes until the map is empty.
+        let mut len = 1;
+        while !expected.is_empty() {
+            let start = expected[(len * 7919) % expected.len()];
+            let range = start..start + len as u64;
+            expected.retain(|k| !range.contains(k));
+            map.remove_range(range);
+            check_map(&map, &expected);
+            len = len * 3 % 1000 + 1;
+        }
+    }
+
+    #[test]
+    fn remove_range_unbounded_entries() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..500u64 {
+            map.insert(format!("{i:05}"), vec![i as u8; i as usize]);
+        }
+
+        assert_eq!(
+            map.remove_range(String::from("00100")..String::from("00400")),
+            300
+        );
+        assert_eq!(map.len(), 200);
+        check_structure(&map, map.root_addr);
+        assert_eq!(map.get(&String::from("00099")), Some(vec![99; 99]));
+        assert_eq!(map.get(&String::from("00100")), None);
+        assert_eq!(map.get(&String::from("00400")), Some(vec![144; 400]));
+    }
+
+    #[test]
+    fn retain() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..1000u64 {
+            map.insert(i, i);
+        }
+
+        map.retain(|k, _| k % 100 < 10 || k % 3 == 0);
+        let expected: Vec<_> = (0..1000).filter(|k| k % 100 < 10 || k % 3 == 0).collect();
+        check_map(&map, &expected);
+
+        map.retain(|_, _| false);
+        check_map(&map, &[]);
+    }
+
+    #[test]
+    fn drain_range() {
+        let mut map = BTreeMap::new(make_memory());
+        for i in 0..1000u64 {
+            map.insert(i, i);
+        }
+
+        assert_eq!(
+            map.drain_range(100..200),
+            (100..200).map(|i| (i, i)).collect::<Vec<_>>()
+        );
+        assert_eq!(map.drain_range(100..200), vec![]);
+        let expected: Vec<_> = (0..100).chain(200..1000).collect();
+        check_map(&map, &expected);
+    }
+}
@@ -0,0 +1,367 @@
+//! Splitting a map into two, and appending one map to another.
+//!
+//! Nodes can't be moved between memories, so the entries that move from one map to
+//! another are always copied. Rather than inserting them one at a time, they're bulk
+//! loaded into a tree of their own in the destination memory.
+//!
+//! When appending, if the keys of the two maps don't overlap, the bulk loaded tree is
+//! then joined with the tree of the destination map: the root of the shorter tree is
+//! attached, along with a separator, to the facing spine of the taller tree, splitting
+//! full nodes on the way down as when inserting. The attached root is then merged with,
+//! or takes entries from, its sibling as when removing.
+use super::{
+    node::{Entry as NodeEntry, Node, NodeType},
+    BTreeMap,
+};
+use crate::{types::NULL, Address, Memory, Storable};
+
+impl<K, V, M> BTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Moves all the entries with keys greater than or equal to `key` into a new map
+    /// in the given memory, and returns the new map.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut map: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// for i in 0..10 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let other = map.split_off(&6, DefaultMemoryImpl::default());
+    /// assert_eq!(map.len(), 6);
+    /// assert_eq!(other.iter().collect::<Vec<_>>(), vec![(6, 6), (7, 7), (8, 8), (9, 9)]);
+    /// ```
+    pub fn split_off<M2: Memory>(&mut self, key: &K, memory: M2) -> BTreeMap<K, V, M2> {
+        let other = BTreeMap::from_sorted_iter(memory, self.range(key.clone()..))
+            .expect("the entries of a map are sorted");
+        self.remove_range(key.clone()..);
+        other
+    }
+
+    /// Moves all the entries of `other` into this map, leaving `other` empty.
+    ///
+    /// If a key is present in both maps, the value from `other` is kept.
+    ///
+    /// This is fastest when all the keys of `other` are either smaller or greater than
+    /// the keys of this map, e.g. when merging maps previously split with
+    /// [`BTreeMap::split_off`].
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+    ///
+    /// let mut a: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// let mut b: BTreeMap<u64, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+    /// a.insert(1, 1);
+    /// b.insert(2, 2);
+    ///
+    /// a.append(&mut b);
+    /// assert_eq!(a.iter().collect::<Vec<_>>(), vec![(1, 1), (2, 2)]);
+    /// assert!(b.is_empty());
+    /// ```
+    pub fn append<M2: Memory>(&mut self, other: &mut BTreeMap<K, V, M2>) {
+        let (other_first, other_last) = match (other.first_key_value(), other.last_key_value()) {
+            (Some((first, _)), Some((last, _))) => (first, last),
+            _ => return,
+        };
+
+        match (self.first_key_value(), self.last_key_value()) {
+            (None, None) => {
+                self.root_addr = self.bulk_build(Self::encoded_entries(other.iter()));
+            }
+            (Some(_), Some((last, _))) if last < other_first => {
+                // The keys of `other` are all greater. Its first entry separates the trees.
+                let mut entries = Self::encoded_entries(other.iter());
+                let separator = entries.next().unwrap();
+                let right = self.bulk_build(entries);
+                self.join(self.root_addr, separator, right);
+            }
+            (Some((first, _)), Some(_)) if other_last < first => {
+                // The keys of `other` are all smaller. Its last entry separates the trees.
+                let separator = Self::encoded_entries(other.range(other_last.clone()..))
+                    .next()
+                    .unwrap();
+                let left = self.bulk_build(Self::encoded_entries(other.range(..other_last)));
+                self.join(left, separator, self.root_addr);
+            }
+            _ => {
+                // The keys overlap. Insert the entries one at a time.
+                for (key, value) in other.iter() {
+                    self.insert(key, value);
+                }
+            }
+        }
+
+        self.update_counts();
+        self.save();
+        other.remove_range(..);
+    }
+
+    // Encodes the values of the given entries.
+    fn encoded_entries<I>(entries: I) -> impl Iterator<Item = NodeEntry<K>>
+    where
+        I: Iterator<Item = (K, V)>,
+    {
+        entries.map(|(key, value)| (key, Self::encode_value(&value)))
+    }
+
+    // Joins the trees rooted at `left` and `right` with the separator, and sets the
+    // root of the map to the joined tree. The keys of the `left` tree must be smaller
+    // than the separator's key, which in turn must be smaller than the keys of the
+    // `right` tree. Either tree may be empty.
+    fn join(&mut self, left: Address, separator: NodeEntry<K>, right: Address) {
+        if left == NULL || right == NULL {
+            self.root_addr = if left == NULL { right } else { left };
+            self.insert_encoded(separator.0, separator.1);
+            return;
+        }
+        self.length += 1;
+
+        let left_height = self.height(left);
+        let right_height = self.height(right);
+
+        if left_height == right_height {
+            let left = self.load_node(left);
+            let right = self.load_node(right);
+            if left.can_merge_with(&right) {
+                // The roots fit in a single node, which becomes the root.
+                self.root_addr = self.merge(right, left, separator).address();
+                return;
+            }
+
+            // The roots become the children of a new root. At most one of them can be
+            // underfull, as they can't be merged.
+            let mut root = self.allocate_node(NodeType::Internal);
+            root.push_entry(separator);
+            root.push_child(left.address());
+            root.push_child(right.address());
+            if left.is_underfull() {
+                self.rebalance_child(&mut root, 0, vec![left]);
+            } else {
+                self.rebalance_child(&mut root, 1, vec![right]);
+            }
+            self.save_node(&mut root);
+            self.root_addr = root.address();
+            return;
+        }
+
+        // Attach the root of the shorter tree to the facing spine of the taller tree.
+        let left_is_taller = left_height > right_height;
+        let (taller, shorter, mut height, shorter_height) = if left_is_taller {
+            (left, right, left_height, right_height)
+        } else {
+            (right, left, right_height, left_height)
+        };
+        let spine_idx = |node: &Node<K>| {
+            if left_is_taller {
+                node.children_len() - 1
+            } else {
+                0
+            }
+        };
+
+        self.root_addr = taller;
+        let mut node = self.load_node(taller);
+        if node.is_full() {
+            // The root is full. Split it, as when inserting.
+            let mut new_root = self.allocate_node(NodeType::Internal);
+            new_root.push_child(taller);
+            self.root_addr = new_root.address();
+            self.split_child(&mut new_root, 0);
+            node = new_root;
+            height += 1;
+        }
+
+        // Descend to the node at the level above the shorter tree's root, splitting full
+        // nodes on the way so that it has room for the separator.
+        while height > shorter_height + 1 {
+            let mut child = self.load_node(node.child(spine_idx(&node)));
+            if child.is_full() {
+                let idx = spine_idx(&node);
+                self.split_child(&mut node, idx);
+                child = self.load_node(node.child(spine_idx(&node)));
+            }
+            node = child;
+            height -= 1;
+        }
+
+        let shorter = self.load_node(shorter);
+        let idx = if left_is_taller {
+            node.push_entry(separator);
+            node.push_child(shorter.address());
+            node.children_len() - 1
+        } else {
+            node.insert_entry(0, separator);
+            node.insert_child(0, shorter.address());
+            0
+        };
+        self.rebalance_child(&mut node, idx, vec![shorter]);
+        self.save_node(&mut node);
+    }
+
+    // Returns the height of the tree rooted at the given address, where a leaf has a
+    // height of zero.
+    fn height(&self, address: Address) -> usize {
+        let mut node = self.load_node(address);
+        let mut height = 0;
+        while node.node_type() == NodeType::Internal {
+            node = self.load_node(node.child(0));
+            height += 1;
+        }
+        height
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+        Rc::new(RefCell::new(Vec::new()))
+    }
+
+    // Checks that all the non-root nodes have enough entries and that all the leaves are
+    // at the same depth. Returns the depth of the leaves.
+    fn check_structure<K: Storable + Ord + Clone, V: Storable, M: Memory>(
+        map: &BTreeMap<K, V, M>,
+        address: Address,
+    ) -> usize {
+        let node = map.load_node(address);
+        assert!(address == map.root_addr || !node.is_underfull());
+        match node.node_type() {
+            NodeType::Leaf => 0,
+            NodeType::Internal => {
+                assert_eq!(node.children_len(), node.entries_len() + 1);
+                let depths: Vec<_> = (0..node.children_len())
+                    .map(|i| check_structure(map, node.child(i)))
+                    .collect();
+                assert!(depths.iter().all(|d| *d == depths[0]));
+                depths[0] + 1
+            }
+        }
+    }
+
+    fn check_map(map: &BTreeMap<u64, u64, Rc<RefCell<Vec<u8>>>>, keys: Vec<u64>) {
+        assert_eq!(map.len(), keys.len() as u64);
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            keys.into_iter().map(|i| (i, i + 1)).collect::<Vec<_>>()
+        );
+        if map.root_addr != NULL {
+            check_structure(map, map.root_addr);
+        }
+    }
+
+    fn make_map(keys: impl Iterator<Item = u64>) -> BTreeMap<u64, u64, Rc<RefCell<Vec<u8>>>> {
+        let mut map = BTreeMap::new(make_memory());
+        for i in keys {
+            map.insert(i, i + 1);
+        }
+        map
+    }
+
+    #[test]
+    fn split_off() {
+        for n in [0, 1, 10, 11, 12, 100, 1000] {
+            for key in [0, 1, n / 3, n / 2, n - n.min(1), n, n + 1] {
+                let mut map = make_map(0..n);
+                let other = map.split_off(&key, make_memory());
+
+                check_map(&map, (0..key.min(n)).collect());
+                check_map(&other, (key.min(n)..n).collect());
+            }
+        }
+    }
+
+    #[test]
+    fn split_off_deallocates_moved_nodes() {
+        let mut map = make_map(0..1000);
+        let mut other = map.split_off(&0, make_memory());
+        assert_eq!(map.allocator.num_allocated_chunks(), 0);
+
+        let map = other.split_off(&1000, map.into_memory());
+        assert!(map.is_empty());
+        check_map(&other, (0..1000).collect());
+    }
+
+    #[test]
+    fn append_disjoint_maps() {
+        let sizes = [0, 1, 2, 5, 10, 11, 12, 50, 100, 133, 1000, 3000];
+        for left in sizes {
+            for right in sizes {
+                let keys: Vec<u64> = (0..left + right).collect();
+
+                // Append the greater keys.
+                let mut map = make_map(0..left);
+                let mut other = make_map(left..left + right);
+                map.append(&mut other);
+                check_map(&map, keys.clone());
+                check_map(&other, vec![]);
+                assert_eq!(other.allocator.num_allocated_chunks(), 0);
+
+                // Append the smaller keys.
+                let mut map = make_map(left..left + right);
+                let mut other = make_map(0..left);
+                map.append(&mut other);
+                check_map(&map, keys.clone());
+                check_map(&other, vec![]);
+            }
+        }
+    }
+
+    #[test]
+    fn append_overlapping_maps() {
+        let mut map = make_map((0..1000).step_by(2));
+        let mut other = BTreeMap::new(make_memory());
+        for i in 500..1500 {
+            other.insert(i, i + 1);
+        }
+        map.insert(500, 0);
+
+        map.append(&mut other);
+        check_map(
+            &map,
+            (0..500).step_by(2).chain(500..1500).collect::<Vec<_>>(),
+        );
+        assert!(other.is_empty());
+    }
+
+    #[test]
+    fn append_unbounded_entries() {
+        let mut map = BTreeMap::new(make_memory());
+        let mut other = BTreeMap::new(make_memory());
+        for i in 0..500u64 {
+            map.insert(format!("{i:05}"), vec![i as u8; i as usize]);
+            other.insert(format!("{:05}", i + 500), vec![i as u8; 5000]);
+        }
+
+        map.append(&mut other);
+        check_structure(&map, map.root_addr);
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.get(&String::from("00750")), Some(vec![250; 5000]));
+        assert_eq!(other.allocator.num_allocated_chunks(), 0);
+    }
+
+    #[test]
+    fn split_off_and_append_v1_maps() {
+        let mut map = BTreeMap::new_v1(make_memory());
+        for i in 0..1000u64 {
+            map.insert(i, i + 1);
+        }
+
+        let mut other = map.split_off(&500, make_memory());
+        check_map(&other, (500..1000).collect());
+
+        map.append(&mut other);
+        check_map(&map, (0..1000).collect());
+
+        let map: BTreeMap<u64, u64, _> = BTreeMap::init_v1(map.into_memory());
+        check_map(&map, (0..1000).collect());
+    }
+}
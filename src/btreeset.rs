@@ -0,0 +1,351 @@
+//! An ordered set of keys based on a [`BTreeMap`].
+//!
+//! The set is stored as a map whose values are always empty. The nodes of such a map
+//! are keys-only: they store no bytes for the values, not even their sizes.
+use crate::btreemap::{self, BTreeMap, KeyPrefix};
+use crate::storable::Bound;
+use crate::{Memory, Storable};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::ops::RangeBounds;
+
+#[cfg(test)]
+mod tests;
+
+// The value type of the underlying map. Unlike `()`, it's fixed in size, so the map
+// doesn't store its values.
+struct Unit;
+
+impl Storable for Unit {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&[])
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        assert!(bytes.is_empty());
+        Unit
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 0,
+        is_fixed_size: true,
+    };
+}
+
+/// An ordered set of keys in stable memory.
+///
+/// ```
+/// use ic_stable_structures::{BTreeSet, DefaultMemoryImpl};
+///
+/// let mut set: BTreeSet<u64, _> = BTreeSet::init(DefaultMemoryImpl::default());
+/// assert!(set.insert(1));
+/// assert!(!set.insert(1));
+/// assert!(set.contains(&1));
+/// assert!(set.remove(&1));
+/// assert!(set.is_empty());
+/// ```
+pub struct BTreeSet<K, M>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    map: BTreeMap<K, Unit, M>,
+}
+
+impl<K, M> BTreeSet<K, M>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    /// Initializes a `BTreeSet`.
+    ///
+    /// If the memory provided already contains a `BTreeSet`, then that
+    /// set is loaded. Otherwise, a new `BTreeSet` instance is created.
+    pub fn init(memory: M) -> Self {
+        Self {
+            map: BTreeMap::init(memory),
+        }
+    }
+
+    /// Creates a new instance of a `BTreeSet`, overwriting any data structures
+    /// the memory might have contained.
+    pub fn new(memory: M) -> Self {
+        Self {
+            map: BTreeMap::new(memory),
+        }
+    }
+
+    /// Loads the set from memory.
+    pub fn load(memory: M) -> Self {
+        Self {
+            map: BTreeMap::load(memory),
+        }
+    }
+
+    /// Adds a key to the set.
+    ///
+    /// Returns `true` if the key wasn't already in the set.
+    pub fn insert(&mut self, key: K) -> bool {
+        self.map.insert(key, Unit).is_none()
+    }
+
+    /// Returns `true` if the key is in the set.
+    pub fn contains(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Removes a key from the set.
+    ///
+    /// Returns `true` if the key was in the set.
+    pub fn remove(&mut self, key: &K) -> bool {
+        self.map.remove(key).is_some()
+    }
+
+    /// Returns `true` if the set contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of keys in the set.
+    pub fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    /// Returns the underlying memory.
+    pub fn into_memory(self) -> M {
+        self.map.into_memory()
+    }
+
+    /// Removes all the keys from the set.
+    pub fn clear(self) -> Self {
+        Self {
+            map: self.map.clear(),
+        }
+    }
+
+    /// Returns the smallest key in the set, if any.
+    pub fn first(&self) -> Option<K> {
+        self.map.first_key_value().map(|(key, _)| key)
+    }
+
+    /// Returns the largest key in the set, if any.
+    pub fn last(&self) -> Option<K> {
+        self.map.last_key_value().map(|(key, _)| key)
+    }
+
+    /// Returns an iterator over the keys of the set, in ascending order.
+    pub fn iter(&self) -> Iter<K, M> {
+        Iter(self.map.iter())
+    }
+
+    /// Returns an iterator over the keys of the set that belong to the specified range.
+    pub fn range(&self, key_range: impl RangeBounds<K>) -> Iter<K, M> {
+        Iter(self.map.range(key_range))
+    }
+
+    /// Returns an iterator over the keys of the set that start with the given prefix.
+    ///
+    /// See [`BTreeMap::prefix_iter`] for more details.
+    pub fn prefix_iter<P: KeyPrefix<K> + ?Sized>(&self, prefix: &P) -> Iter<K, M> {
+        Iter(self.map.prefix_iter(prefix))
+    }
+
+    /// Returns an iterator over the keys that are in `self` or `other`, in ascending
+    /// order and without duplicates.
+    ///
+    /// ```
+    /// use ic_stable_structures::{BTreeSet, DefaultMemoryImpl};
+    ///
+    /// let mut a: BTreeSet<u64, _> = BTreeSet::init(DefaultMemoryImpl::default());
+    /// let mut b: BTreeSet<u64, _> = BTreeSet::init(DefaultMemoryImpl::default());
+    /// a.insert(1);
+    /// a.insert(2);
+    /// b.insert(2);
+    /// b.insert(3);
+    ///
+    /// assert_eq!(a.union(&b).collect::<Vec<_>>(), vec![1, 2, 3]);
+    /// assert_eq!(a.intersection(&b).collect::<Vec<_>>(), vec![2]);
+    /// assert_eq!(a.difference(&b).collect::<Vec<_>>(), vec![1]);
+    /// ```
+    pub fn union<'a, M2: Memory>(&'a self, other: &'a BTreeSet<K, M2>) -> Union<'a, K, M, M2> {
+        Union {
+            a: self.iter().peekable(),
+            b: other.iter().peekable(),
+        }
+    }
+
+    /// Returns an iterator over the keys that are in both `self` and `other`, in
+    /// ascending order.
+    pub fn intersection<'a, M2: Memory>(
+        &'a self,
+        other: &'a BTreeSet<K, M2>,
+    ) -> Intersection<'a, K, M, M2> {
+        Intersection {
+            a: self.iter().peekable(),
+            b: other.iter().peekable(),
+        }
+    }
+
+    /// Returns an iterator over the keys that are in `self` but not in `other`, in
+    /// ascending order.
+    pub fn difference<'a, M2: Memory>(
+        &'a self,
+        other: &'a BTreeSet<K, M2>,
+    ) -> Difference<'a, K, M, M2> {
+        Difference {
+            a: self.iter().peekable(),
+            b: other.iter().peekable(),
+        }
+    }
+}
+
+/// An iterator over the keys of a [`BTreeSet`].
+pub struct Iter<'a, K, M>(btreemap::Iter<'a, K, Unit, M>)
+where
+    K: Storable + Ord + Clone,
+    M: Memory;
+
+impl<K, M> Iterator for Iter<'_, K, M>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _)| key)
+    }
+}
+
+impl<K, M> DoubleEndedIterator for Iter<'_, K, M>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(key, _)| key)
+    }
+}
+
+/// An iterator over the union of two [`BTreeSet`]s, returned by [`BTreeSet::union`].
+pub struct Union<'a, K, M, M2>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+    M2: Memory,
+{
+    a: Peekable<Iter<'a, K, M>>,
+    b: Peekable<Iter<'a, K, M2>>,
+}
+
+impl<K, M, M2> Iterator for Union<'_, K, M, M2>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+    M2: Memory,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ordering = match (self.a.peek(), self.b.peek()) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, _) => Ordering::Greater,
+        };
+
+        match ordering {
+            Ordering::Less => self.a.next(),
+            Ordering::Greater => self.b.next(),
+            Ordering::Equal => {
+                self.b.next();
+                self.a.next()
+            }
+        }
+    }
+}
+
+/// An iterator over the intersection of two [`BTreeSet`]s, returned by
+/// [`BTreeSet::intersection`].
+pub struct Intersection<'a, K, M, M2>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+    M2: Memory,
+{
+    a: Peekable<Iter<'a, K, M>>,
+    b: Peekable<Iter<'a, K, M2>>,
+}
+
+impl<K, M, M2> Iterator for Intersection<'_, K, M, M2>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+    M2: Memory,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ordering = match (self.a.peek(), self.b.peek()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                _ => return None,
+            };
+
+            match ordering {
+                Ordering::Less => {
+                    self.a.next();
+                }
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    self.b.next();
+                    return self.a.next();
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the difference of two [`BTreeSet`]s, returned by
+/// [`BTreeSet::difference`].
+pub struct Difference<'a, K, M, M2>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+    M2: Memory,
+{
+    a: Peekable<Iter<'a, K, M>>,
+    b: Peekable<Iter<'a, K, M2>>,
+}
+
+impl<K, M, M2> Iterator for Difference<'_, K, M, M2>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+    M2: Memory,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ordering = match (self.a.peek(), self.b.peek()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                (Some(_), None) => Ordering::Less,
+                (None, _) => return None,
+            };
+
+            match ordering {
+                Ordering::Less => return self.a.next(),
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    self.a.next();
+                    self.b.next();
+                }
+            }
+        }
+    }
+}
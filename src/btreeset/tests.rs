@@ -0,0 +1,140 @@
+use super::*;
+use proptest::collection::btree_set as pset;
+use proptest::collection::vec as pvec;
+use proptest::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+    Rc::new(RefCell::new(Vec::new()))
+}
+
+#[derive(Debug, Clone)]
+enum Operation {
+    Insert(u16),
+    Remove(u16),
+    Contains(u16),
+}
+
+fn arb_op() -> impl Strategy<Value = Operation> {
+    prop_oneof![
+        3 => any::<u16>().prop_map(Operation::Insert),
+        1 => any::<u16>().prop_map(Operation::Remove),
+        1 => any::<u16>().prop_map(Operation::Contains),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn model(ops in pvec(arb_op(), 0..3000)) {
+        let mut set = BTreeSet::new(make_memory());
+        let mut std_set = std::collections::BTreeSet::new();
+
+        for op in ops {
+            match op {
+                Operation::Insert(key) => prop_assert_eq!(set.insert(key), std_set.insert(key)),
+                Operation::Remove(key) => prop_assert_eq!(set.remove(&key), std_set.remove(&key)),
+                Operation::Contains(key) => {
+                    prop_assert_eq!(set.contains(&key), std_set.contains(&key))
+                }
+            }
+        }
+
+        prop_assert_eq!(set.len(), std_set.len() as u64);
+        prop_assert_eq!(set.first(), std_set.first().copied());
+        prop_assert_eq!(set.last(), std_set.last().copied());
+        prop_assert_eq!(set.iter().collect::<Vec<_>>(), std_set.iter().copied().collect::<Vec<_>>());
+        prop_assert_eq!(
+            set.iter().rev().collect::<Vec<_>>(),
+            std_set.iter().rev().copied().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn set_operations(a in pset(0..2000u32, 0..1000), b in pset(0..2000u32, 0..1000)) {
+        let mut set_a = BTreeSet::new(make_memory());
+        let mut set_b = BTreeSet::new(make_memory());
+        for key in a.iter() {
+            set_a.insert(*key);
+        }
+        for key in b.iter() {
+            set_b.insert(*key);
+        }
+
+        prop_assert_eq!(set_a.union(&set_b).collect::<Vec<_>>(), a.union(&b).copied().collect::<Vec<_>>());
+        prop_assert_eq!(
+            set_a.intersection(&set_b).collect::<Vec<_>>(),
+            a.intersection(&b).copied().collect::<Vec<_>>()
+        );
+        prop_assert_eq!(
+            set_a.difference(&set_b).collect::<Vec<_>>(),
+            a.difference(&b).copied().collect::<Vec<_>>()
+        );
+        prop_assert_eq!(
+            set_b.difference(&set_a).collect::<Vec<_>>(),
+            b.difference(&a).copied().collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn range() {
+    let mut set = BTreeSet::new(make_memory());
+    for i in 0..100u64 {
+        set.insert(i * 2);
+    }
+
+    assert_eq!(
+        set.range(10..20).collect::<Vec<_>>(),
+        vec![10, 12, 14, 16, 18]
+    );
+    assert_eq!(set.range(195..).collect::<Vec<_>>(), vec![196, 198]);
+    assert_eq!(set.range(..=2).collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(set.range(1000..).next(), None);
+}
+
+#[test]
+fn init_loads_existing_set() {
+    let mut set = BTreeSet::init(make_memory());
+    for i in 0..1000u64 {
+        set.insert(i);
+    }
+
+    let mut set: BTreeSet<u64, _> = BTreeSet::init(set.into_memory());
+    assert_eq!(set.len(), 1000);
+    assert!(set.contains(&999));
+    assert!(set.remove(&999));
+
+    let set: BTreeSet<u64, _> = BTreeSet::load(set.into_memory());
+    assert_eq!(set.iter().collect::<Vec<_>>(), (0..999).collect::<Vec<_>>());
+
+    let set = set.clear();
+    assert!(set.is_empty());
+}
+
+#[test]
+fn nodes_store_keys_only() {
+    let mut set = BTreeSet::new(make_memory());
+    for i in 0..1000u64 {
+        set.insert(i);
+    }
+
+    // Read the root's address from the map's header, then the root's layout version.
+    let memory = set.into_memory();
+    let root = crate::read_u64(&memory, crate::types::Address::from(12));
+    let mut version = [0];
+    memory.read(root + 3, &mut version);
+    assert_eq!(version[0], 3);
+}
+
+#[test]
+fn unbounded_keys() {
+    let mut set = BTreeSet::new(make_memory());
+    for i in 0..500u64 {
+        set.insert(format!("{i:0width$}", width = i as usize));
+    }
+
+    assert_eq!(set.len(), 500);
+    assert!(set.contains(&format!("{:010}", 10)));
+    assert!(!set.contains(&String::from("10")));
+}
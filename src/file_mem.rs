@@ -1,4 +1,4 @@
-use crate::{Memory, WASM_PAGE_SIZE};
+use crate::{Memory, ShrinkableMemory, WASM_PAGE_SIZE};
 use std::cell::RefCell;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -54,6 +54,19 @@ impl Memory for FileMemory {
     }
 }
 
+impl ShrinkableMemory for FileMemory {
+    fn truncate(&self, pages: u64) -> u64 {
+        let previous_size = self.size();
+        if pages < previous_size {
+            self.0
+                .borrow()
+                .set_len(pages * WASM_PAGE_SIZE)
+                .expect("truncate must succeed");
+        }
+        previous_size
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -93,4 +106,31 @@ mod test {
             assert_eq!(buf.as_slice(), vec_mem.borrow().as_slice());
         });
     }
+
+    #[test]
+    fn truncate_discards_trailing_pages() {
+        let vec_mem = make_vec_memory();
+        let file_mem = make_file_memory();
+
+        for memory in [&vec_mem as &dyn ShrinkableMemory, &file_mem] {
+            assert_eq!(memory.grow(3), 0);
+            memory.write(0, &[1, 2, 3]);
+            memory.write(2 * WASM_PAGE_SIZE, &[4, 5, 6]);
+
+            // Truncating to a larger size does nothing.
+            assert_eq!(memory.truncate(5), 3);
+            assert_eq!(memory.size(), 3);
+
+            assert_eq!(memory.truncate(1), 3);
+            assert_eq!(memory.size(), 1);
+            let mut bytes = vec![0; 3];
+            memory.read(0, &mut bytes);
+            assert_eq!(bytes, vec![1, 2, 3]);
+
+            // The pages grown again contain zeroes.
+            assert_eq!(memory.grow(2), 1);
+            memory.read(2 * WASM_PAGE_SIZE, &mut bytes);
+            assert_eq!(bytes, vec![0, 0, 0]);
+        }
+    }
 }
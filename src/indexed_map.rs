@@ -0,0 +1,471 @@
+//! A [`BTreeMap`] with secondary indexes that are kept consistent with it.
+//!
+//! Each index is stored as a [`BTreeSet`] of `(index key, primary key)` pairs in its
+//! own memory, e.g. a [`VirtualMemory`](crate::memory_manager::VirtualMemory). The index
+//! key of an entry is computed by the index's extractor.
+//!
+//! # Consistency
+//!
+//! Inserting or removing an entry updates the primary map and all the indexes. Every
+//! check that can fail (running the extractors and checking the sizes of the keys and
+//! values) is done before any of the maps is modified, so a panic leaves them all
+//! unchanged. A failure while modifying the maps, such as running out of memory, traps,
+//! in which case the canister's state is rolled back.
+//!
+//! Extractors must be deterministic, as an entry's index keys are computed again when
+//! the entry is removed.
+//!
+//! # Index layout
+//!
+//! The first page of an index's memory holds its header, and the set is stored in the
+//! pages after it.
+//!
+//! ```text
+//! ---------------------------------------- <- Address 0
+//! Magic "IDX"                 ↕ 3 bytes
+//! ----------------------------------------
+//! Layout version              ↕ 1 byte
+//! ----------------------------------------
+//! Index ID                    ↕ 4 bytes
+//! ----------------------------------------
+//! Status                      ↕ 1 byte
+//! ----------------------------------------
+//! Cursor length = N           ↕ 4 bytes
+//! ----------------------------------------
+//! Cursor                      ↕ N bytes
+//! ---------------------------------------- <- Address 65536
+//! The set of the index
+//! ```
+//!
+//! The status is one of `STATUS_BUILT`, `STATUS_REBUILDING` (no entry of the primary map
+//! is indexed yet) and `STATUS_REBUILDING_AFTER_CURSOR` (the entries up to the primary
+//! key encoded in the cursor are indexed).
+use crate::btreemap::BTreeMap;
+use crate::btreeset::BTreeSet;
+use crate::types::Address;
+use crate::{read_u32, write, write_u32, Memory, RestrictedMemory, Storable, MAX_PAGES};
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::ops::Bound;
+
+const MAGIC: &[u8; 3] = b"IDX";
+const LAYOUT_VERSION: u8 = 1;
+const STATUS_BUILT: u8 = 0;
+const STATUS_REBUILDING: u8 = 1;
+const STATUS_REBUILDING_AFTER_CURSOR: u8 = 2;
+
+const ID_OFFSET: u64 = 4;
+const STATUS_OFFSET: u64 = 8;
+const CURSOR_OFFSET: u64 = 9;
+
+// The maximum size of an encoded primary key stored as the cursor of a rebuild.
+const MAX_CURSOR_SIZE: u64 = crate::WASM_PAGE_SIZE - CURSOR_OFFSET - 4;
+
+#[cfg(test)]
+mod tests;
+
+/// A handle to an index of an [`IndexedMap`], returned by [`IndexedMap::add_index`].
+pub struct IndexId<IK> {
+    idx: usize,
+    _phantom: PhantomData<fn() -> IK>,
+}
+
+impl<IK> Clone for IndexId<IK> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<IK> Copy for IndexId<IK> {}
+
+// The operations of an index that don't depend on the type of its keys. `PM` is the
+// memory of the primary map.
+trait Index<K: Storable + Ord + Clone, V: Storable, PM: Memory> {
+    // Panics if the entry can't be added to the index.
+    fn check(&self, key: &K, value: &V);
+
+    fn insert(&mut self, key: &K, value: &V);
+
+    fn remove(&mut self, key: &K, value: &V);
+
+    // Returns the primary keys of the entries with the given encoded index key.
+    fn get(&self, index_key: &[u8]) -> Vec<K>;
+
+    fn is_built(&self) -> bool;
+
+    // Indexes up to `budget` entries of the primary map. Returns true if the index is built.
+    fn rebuild_step(&mut self, primary: &BTreeMap<K, V, PM>, budget: u64) -> bool;
+}
+
+// The progress of building an index.
+enum Status<K> {
+    Built,
+    // The entries of the primary map up to the given key, if any, are indexed.
+    Rebuilding(Option<K>),
+}
+
+struct IndexImpl<IK, K, V, M, F>
+where
+    IK: Storable + Ord + Clone,
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    // The memory of the index, whose first page holds the header.
+    memory: M,
+    set: BTreeSet<(IK, K), RestrictedMemory<M>>,
+    status: Status<K>,
+    extractor: F,
+    _phantom: PhantomData<fn(&V)>,
+}
+
+impl<IK, K, V, M, F> IndexImpl<IK, K, V, M, F>
+where
+    IK: Storable + Ord + Clone,
+    K: Storable + Ord + Clone,
+    M: Memory + Clone,
+    F: Fn(&K, &V) -> IK,
+{
+    // Loads the index with the given ID from memory, or creates it if the memory is empty.
+    fn init(memory: M, id: u32, extractor: F, primary_len: u64) -> Self {
+        let is_new = memory.size() == 0;
+        let status = if is_new {
+            write(&memory, 0, MAGIC);
+            write(&memory, 3, &[LAYOUT_VERSION]);
+            write_u32(&memory, Address::from(ID_OFFSET), id);
+            if primary_len == 0 {
+                Status::Built
+            } else {
+                Status::Rebuilding(None)
+            }
+        } else {
+            Self::load_status(&memory, id)
+        };
+
+        let mut index = Self {
+            set: BTreeSet::init(RestrictedMemory::new(memory.clone(), 1..MAX_PAGES)),
+            memory,
+            status,
+            extractor,
+            _phantom: PhantomData,
+        };
+
+        // An index with a different number of entries than the primary map is out of sync.
+        if matches!(index.status, Status::Built) && index.set.len() != primary_len {
+            index.set = index.set.clear();
+            index.status = Status::Rebuilding(None);
+            index.save_status();
+        } else if is_new {
+            index.save_status();
+        }
+        index
+    }
+
+    fn load_status(memory: &M, id: u32) -> Status<K> {
+        let mut header = [0; CURSOR_OFFSET as usize];
+        memory.read(0, &mut header);
+        assert_eq!(&header[0..3], MAGIC, "The memory doesn't contain an index.");
+        assert_eq!(
+            header[3], LAYOUT_VERSION,
+            "Unsupported index layout version."
+        );
+        let stored_id = read_u32(memory, Address::from(ID_OFFSET));
+        assert_eq!(
+            stored_id, id,
+            "The memory contains the index with ID {stored_id}, not {id}."
+        );
+
+        match header[STATUS_OFFSET as usize] {
+            STATUS_BUILT => Status::Built,
+            STATUS_REBUILDING => Status::Rebuilding(None),
+            STATUS_REBUILDING_AFTER_CURSOR => {
+                let len = read_u32(memory, Address::from(CURSOR_OFFSET));
+                let mut cursor = vec![0; len as usize];
+                memory.read(CURSOR_OFFSET + 4, &mut cursor);
+                Status::Rebuilding(Some(K::from_bytes(Cow::Owned(cursor))))
+            }
+            status => panic!("Unknown index status {status}."),
+        }
+    }
+
+    fn save_status(&self) {
+        match &self.status {
+            Status::Built => write(&self.memory, STATUS_OFFSET, &[STATUS_BUILT]),
+            Status::Rebuilding(None) => write(&self.memory, STATUS_OFFSET, &[STATUS_REBUILDING]),
+            Status::Rebuilding(Some(cursor)) => {
+                let cursor = cursor.to_bytes();
+                assert!(
+                    cursor.len() as u64 <= MAX_CURSOR_SIZE,
+                    "The keys of a map whose index is being rebuilt must be at most \
+                     {MAX_CURSOR_SIZE} bytes."
+                );
+                write_u32(
+                    &self.memory,
+                    Address::from(CURSOR_OFFSET),
+                    cursor.len() as u32,
+                );
+                write(&self.memory, CURSOR_OFFSET + 4, &cursor);
+                write(
+                    &self.memory,
+                    STATUS_OFFSET,
+                    &[STATUS_REBUILDING_AFTER_CURSOR],
+                );
+            }
+        }
+    }
+
+    // Returns true if the entry with the given key is indexed already, in which case it
+    // must be kept up to date. The other entries are indexed when the rebuild reaches them.
+    fn is_indexed(&self, key: &K) -> bool {
+        match &self.status {
+            Status::Built => true,
+            Status::Rebuilding(Some(cursor)) => key <= cursor,
+            Status::Rebuilding(None) => false,
+        }
+    }
+}
+
+impl<IK, K, V, M, PM, F> Index<K, V, PM> for IndexImpl<IK, K, V, M, F>
+where
+    IK: Storable + Ord + Clone,
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory + Clone,
+    PM: Memory,
+    F: Fn(&K, &V) -> IK,
+{
+    fn check(&self, key: &K, value: &V) {
+        let index_key = (self.extractor)(key, value);
+        BTreeMap::<(IK, K), (), M>::assert_key_size(&(index_key, key.clone()));
+    }
+
+    fn insert(&mut self, key: &K, value: &V) {
+        if self.is_indexed(key) {
+            self.set.insert(((self.extractor)(key, value), key.clone()));
+        }
+    }
+
+    fn remove(&mut self, key: &K, value: &V) {
+        if self.is_indexed(key) {
+            self.set
+                .remove(&((self.extractor)(key, value), key.clone()));
+        }
+    }
+
+    fn get(&self, index_key: &[u8]) -> Vec<K> {
+        assert!(
+            matches!(self.status, Status::Built),
+            "The index is being rebuilt. See `IndexedMap::rebuild_index_step`."
+        );
+        let index_key = IK::from_bytes(Cow::Borrowed(index_key));
+        self.set
+            .prefix_iter(&index_key)
+            .map(|(_, key)| key)
+            .collect()
+    }
+
+    fn is_built(&self) -> bool {
+        matches!(self.status, Status::Built)
+    }
+
+    fn rebuild_step(&mut self, primary: &BTreeMap<K, V, PM>, budget: u64) -> bool {
+        let start = match &self.status {
+            Status::Built => return true,
+            Status::Rebuilding(None) => Bound::Unbounded,
+            Status::Rebuilding(Some(cursor)) => Bound::Excluded(cursor.clone()),
+        };
+
+        let mut entries = primary.range((start, Bound::Unbounded));
+        let mut last_key = None;
+        for _ in 0..budget {
+            match entries.next() {
+                Some((key, value)) => {
+                    self.set
+                        .insert(((self.extractor)(&key, &value), key.clone()));
+                    last_key = Some(key);
+                }
+                None => {
+                    self.status = Status::Built;
+                    self.save_status();
+                    return true;
+                }
+            }
+        }
+
+        if last_key.is_some() {
+            self.status = Status::Rebuilding(last_key);
+            self.save_status();
+        }
+        false
+    }
+}
+
+/// A [`BTreeMap`] with secondary indexes, which are updated along with the map.
+///
+/// Index keys don't need to be unique: many entries can have the same index key.
+///
+/// ```
+/// use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+/// use ic_stable_structures::{DefaultMemoryImpl, IndexedMap};
+///
+/// let mem_mgr = MemoryManager::init(DefaultMemoryImpl::default());
+///
+/// // Token ids mapped to their owners, indexed by owner.
+/// let mut tokens: IndexedMap<u64, u64, _> = IndexedMap::init(mem_mgr.get(MemoryId::new(0)));
+/// let by_owner = tokens.add_index(mem_mgr.get(MemoryId::new(1)), 0, |_, owner| *owner);
+///
+/// tokens.insert(1, 100);
+/// tokens.insert(2, 100);
+/// tokens.insert(3, 200);
+/// assert_eq!(tokens.get_by_index(by_owner, &100), vec![1, 2]);
+///
+/// // Transfer token 2.
+/// tokens.insert(2, 200);
+/// assert_eq!(tokens.get_by_index(by_owner, &100), vec![1]);
+/// assert_eq!(tokens.get_by_index(by_owner, &200), vec![2, 3]);
+/// ```
+pub struct IndexedMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    primary: BTreeMap<K, V, M>,
+    indexes: Vec<Box<dyn Index<K, V, M>>>,
+}
+
+impl<K, V, M> IndexedMap<K, V, M>
+where
+    K: Storable + Ord + Clone + 'static,
+    V: Storable + 'static,
+    M: Memory,
+{
+    /// Initializes an `IndexedMap` with the primary map in the given memory.
+    ///
+    /// If the memory provided already contains a map, then that map is loaded.
+    /// Otherwise, a new map is created. The indexes are then added with
+    /// [`IndexedMap::add_index`].
+    pub fn init(memory: M) -> Self {
+        Self {
+            primary: BTreeMap::init(memory),
+            indexes: vec![],
+        }
+    }
+
+    /// Adds the index with the given ID in the given memory, where the index key of each
+    /// entry is computed by `extractor`, and returns a handle to query it.
+    ///
+    /// If the memory is empty, a new index is created. Otherwise, the memory must
+    /// contain the index with the same ID, which is loaded.
+    ///
+    /// An index that is new or out of sync with the primary map (i.e. it has a
+    /// different number of entries) needs to be rebuilt with
+    /// [`IndexedMap::rebuild_index_step`] before it can be queried. No entry is
+    /// indexed by this method, so it does a bounded amount of work.
+    ///
+    /// Indexes must be added in the same order, and with the same IDs and extractors,
+    /// every time the map is initialized. To change the extractor of an index, add it
+    /// in a new memory with a new ID.
+    ///
+    /// PRECONDITION: the index isn't stored in the memory of another index.
+    pub fn add_index<IK, M2, F>(&mut self, memory: M2, id: u32, extractor: F) -> IndexId<IK>
+    where
+        IK: Storable + Ord + Clone + 'static,
+        M2: Memory + Clone + 'static,
+        F: Fn(&K, &V) -> IK + 'static,
+    {
+        self.indexes.push(Box::new(IndexImpl::init(
+            memory,
+            id,
+            extractor,
+            self.primary.len(),
+        )));
+
+        IndexId {
+            idx: self.indexes.len() - 1,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Inserts an entry into the map and updates the indexes.
+    ///
+    /// The previous value of the key, if present, is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        // Check everything that can fail before modifying any of the maps.
+        BTreeMap::<K, V, M>::assert_key_size(&key);
+        let encoded_value = BTreeMap::<K, V, M>::encode_value(&value);
+        for index in self.indexes.iter() {
+            index.check(&key, &value);
+        }
+
+        let previous_value = self
+            .primary
+            .insert_encoded(key.clone(), encoded_value)
+            .map(|value| V::from_bytes(Cow::Owned(value)));
+
+        for index in self.indexes.iter_mut() {
+            if let Some(previous_value) = &previous_value {
+                index.remove(&key, previous_value);
+            }
+            index.insert(&key, &value);
+        }
+
+        previous_value
+    }
+
+    /// Removes a key from the map and the indexes, returning the previous value of
+    /// the key if present.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.primary.get(key)?;
+        for index in self.indexes.iter_mut() {
+            index.remove(key, &value);
+        }
+        self.primary.remove(key)
+    }
+
+    /// Returns the value associated with the given key if it exists.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.primary.get(key)
+    }
+
+    /// Returns `true` if the key exists in the map, `false` otherwise.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.primary.contains_key(key)
+    }
+
+    /// Returns the keys of the entries with the given index key, sorted by key.
+    ///
+    /// PRECONDITION: the index is built (see [`IndexedMap::is_index_built`]).
+    pub fn get_by_index<IK: Storable>(&self, index: IndexId<IK>, index_key: &IK) -> Vec<K> {
+        self.indexes[index.idx].get(&index_key.to_bytes())
+    }
+
+    /// Returns `true` if the index is built, i.e. it can be queried.
+    pub fn is_index_built<IK>(&self, index: IndexId<IK>) -> bool {
+        self.indexes[index.idx].is_built()
+    }
+
+    /// Indexes up to `budget` entries of the primary map in an index that is being
+    /// rebuilt. This method can be called repeatedly (e.g. in a timer) to rebuild the
+    /// index with a bounded amount of work per call, and progress is persisted across
+    /// calls and upgrades. The map can be modified between the calls.
+    ///
+    /// Returns `true` if the index is built, `false` otherwise.
+    pub fn rebuild_index_step<IK>(&mut self, index: IndexId<IK>, budget: u64) -> bool {
+        self.indexes[index.idx].rebuild_step(&self.primary, budget)
+    }
+
+    /// Returns the primary map, e.g. for iterating over its entries.
+    pub fn primary(&self) -> &BTreeMap<K, V, M> {
+        &self.primary
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.primary.is_empty()
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> u64 {
+        self.primary.len()
+    }
+}
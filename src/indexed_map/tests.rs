@@ -0,0 +1,198 @@
+use super::*;
+use crate::memory_manager::{MemoryId, MemoryManager};
+use crate::storable::Blob;
+use proptest::collection::vec as pvec;
+use proptest::prelude::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as StdBTreeMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+
+fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+    Rc::new(RefCell::new(Vec::new()))
+}
+
+// Checks that the index contains exactly the entries of the model.
+fn check_index<V: Storable + 'static>(
+    map: &IndexedMap<u32, V, Rc<RefCell<Vec<u8>>>>,
+    index: IndexId<u8>,
+    model: &StdBTreeMap<u32, V>,
+    extractor: impl Fn(&V) -> u8,
+) {
+    for index_key in 0..=u8::MAX {
+        let expected: Vec<_> = model
+            .iter()
+            .filter(|(_, value)| extractor(value) == index_key)
+            .map(|(key, _)| *key)
+            .collect();
+        assert_eq!(map.get_by_index(index, &index_key), expected);
+    }
+}
+
+proptest! {
+    #[test]
+    fn indexes_are_consistent(ops in pvec((0..200u32, any::<u16>(), any::<bool>()), 0..1000)) {
+        let mut map = IndexedMap::init(make_memory());
+        let low = map.add_index(make_memory(), 0, |_, value: &u16| *value as u8);
+        let high = map.add_index(make_memory(), 1, |_, value: &u16| (*value >> 8) as u8);
+        let mut model = StdBTreeMap::new();
+
+        for (key, value, insert) in ops {
+            if insert {
+                prop_assert_eq!(map.insert(key, value), model.insert(key, value));
+            } else {
+                prop_assert_eq!(map.remove(&key), model.remove(&key));
+            }
+        }
+
+        prop_assert_eq!(map.len(), model.len() as u64);
+        check_index(&map, low, &model, |value| *value as u8);
+        check_index(&map, high, &model, |value| (*value >> 8) as u8);
+    }
+}
+
+#[test]
+fn indexes_are_reloaded_from_virtual_memories() {
+    let mem_mgr = MemoryManager::init(make_memory());
+    let mut map = IndexedMap::init(mem_mgr.get(MemoryId::new(0)));
+    map.add_index(mem_mgr.get(MemoryId::new(1)), 0, |key: &u64, _: &u64| {
+        key % 10
+    });
+    for i in 0..1000u64 {
+        map.insert(i, i);
+    }
+
+    let mut map: IndexedMap<u64, u64, _> = IndexedMap::init(mem_mgr.get(MemoryId::new(0)));
+    let index = map.add_index(mem_mgr.get(MemoryId::new(1)), 0, |key: &u64, _: &u64| {
+        key % 10
+    });
+    assert!(map.is_index_built(index));
+    assert_eq!(
+        map.get_by_index(index, &3),
+        (3..1000).step_by(10).collect::<Vec<_>>()
+    );
+    assert_eq!(map.remove(&3), Some(3));
+    assert_eq!(map.get_by_index(index, &3)[0], 13);
+}
+
+proptest! {
+    #[test]
+    fn indexes_are_built_in_steps(
+        entries in pvec((0..200u32, any::<u16>()), 0..200),
+        ops in pvec((0..200u32, any::<u16>(), any::<bool>()), 0..200),
+        budget in 1..20u64,
+    ) {
+        let mem = make_memory();
+        let index_mem = make_memory();
+        let mut map = IndexedMap::init(mem.clone());
+        let mut model = StdBTreeMap::new();
+        for (key, value) in entries {
+            map.insert(key, value);
+            model.insert(key, value);
+        }
+
+        // The index is built from the existing entries, while the map is being modified.
+        let mut index = map.add_index(index_mem.clone(), 0, |_, value: &u16| *value as u8);
+        prop_assert_eq!(map.is_index_built(index), model.is_empty());
+        for (key, value, insert) in ops {
+            if insert {
+                prop_assert_eq!(map.insert(key, value), model.insert(key, value));
+            } else {
+                prop_assert_eq!(map.remove(&key), model.remove(&key));
+            }
+            map.rebuild_index_step(index, budget);
+
+            // Progress is preserved when the map is reloaded.
+            map = IndexedMap::init(mem.clone());
+            index = map.add_index(index_mem.clone(), 0, |_, value: &u16| *value as u8);
+        }
+
+        while !map.rebuild_index_step(index, budget) {}
+        prop_assert!(map.is_index_built(index));
+        check_index(&map, index, &model, |value| *value as u8);
+    }
+}
+
+#[test]
+fn indexes_are_rebuilt_when_out_of_sync() {
+    let mem = make_memory();
+    let index_mem = make_memory();
+    let mut map = IndexedMap::init(mem.clone());
+    let index = map.add_index(index_mem.clone(), 0, |_, value: &u32| *value as u8 % 3);
+    for i in 0..100u32 {
+        map.insert(i, i * 2);
+    }
+    assert!(map.is_index_built(index));
+
+    // Entries inserted without the index make it out of sync.
+    let mut map = IndexedMap::init(mem.clone());
+    map.insert(100u32, 200u32);
+
+    let mut map = IndexedMap::init(mem);
+    let index = map.add_index(index_mem, 0, |_, value: &u32| *value as u8 % 3);
+    assert!(!map.is_index_built(index));
+    assert!(!map.rebuild_index_step(index, 100));
+    assert!(map.rebuild_index_step(index, 100));
+    let model: StdBTreeMap<_, _> = (0..101).map(|i| (i, i * 2)).collect();
+    check_index(&map, index, &model, |value| *value as u8 % 3);
+}
+
+#[test]
+#[should_panic(expected = "The index is being rebuilt")]
+fn querying_index_being_rebuilt_panics() {
+    let mut map = IndexedMap::init(make_memory());
+    map.insert(1u32, 2u32);
+    let index = map.add_index(make_memory(), 0, |_, value: &u32| *value);
+    map.get_by_index(index, &2);
+}
+
+#[test]
+#[should_panic(expected = "The memory contains the index with ID 0, not 1.")]
+fn loading_index_with_another_id_panics() {
+    let index_mem = make_memory();
+    let mut map: IndexedMap<u32, u32, _> = IndexedMap::init(make_memory());
+    map.add_index(index_mem.clone(), 0, |_, value: &u32| *value);
+    map.add_index(index_mem, 1, |_, value: &u32| *value);
+}
+
+#[test]
+#[should_panic(expected = "The memory doesn't contain an index.")]
+fn loading_index_from_memory_of_another_structure_panics() {
+    let mem = make_memory();
+    let mut other: BTreeMap<u32, u32, _> = BTreeMap::init(mem.clone());
+    other.insert(1, 2);
+
+    let mut map: IndexedMap<u32, u32, _> = IndexedMap::init(make_memory());
+    map.add_index(mem, 0, |_, value: &u32| *value);
+}
+
+#[test]
+fn failed_insert_leaves_maps_unchanged() {
+    let mut map: IndexedMap<u32, Vec<u8>, _> = IndexedMap::init(make_memory());
+    let first_byte = map.add_index(make_memory(), 0, |_, value: &Vec<u8>| value[0]);
+    let prefix = map.add_index(make_memory(), 1, |_, value: &Vec<u8>| {
+        Blob::<4>::try_from(&value[..value.len().min(5)]).unwrap()
+    });
+    map.insert(1, vec![1, 2]);
+
+    // The second extractor panics on values longer than 4 bytes.
+    let result = catch_unwind(AssertUnwindSafe(|| map.insert(1, vec![2, 3, 4, 5, 6])));
+    assert!(result.is_err());
+    assert_eq!(map.get(&1), Some(vec![1, 2]));
+    assert_eq!(map.get_by_index(first_byte, &1), vec![1]);
+    assert_eq!(map.get_by_index(first_byte, &2), Vec::<u32>::new());
+
+    // The first extractor panics on empty values.
+    let result = catch_unwind(AssertUnwindSafe(|| map.insert(2, vec![])));
+    assert!(result.is_err());
+    assert!(!map.contains_key(&2));
+    assert_eq!(map.len(), 1);
+
+    map.insert(1, vec![2, 3]);
+    assert_eq!(map.get_by_index(first_byte, &2), vec![1]);
+    assert_eq!(
+        map.get_by_index(prefix, &Blob::try_from(&[2, 3][..]).unwrap()),
+        vec![1]
+    );
+    assert!(map.get_by_index(first_byte, &1).is_empty());
+}
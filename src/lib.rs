@@ -1,15 +1,18 @@
 #![doc = include_str!("../README.md")]
 mod base_vec;
 pub mod btreemap;
+pub mod btreeset;
 pub mod cell;
 pub use cell::{Cell as StableCell, Cell};
 pub mod file_mem;
 #[cfg(target_arch = "wasm32")]
 mod ic0_memory; // Memory API for canisters.
+pub mod indexed_map;
 pub mod log;
 pub use log::{Log as StableLog, Log};
 pub mod memory_manager;
 pub mod min_heap;
+pub mod multimap;
 pub mod reader;
 pub mod storable;
 #[cfg(test)]
@@ -17,13 +20,16 @@ mod tests;
 mod types;
 pub mod vec;
 pub use min_heap::{MinHeap, MinHeap as StableMinHeap};
+pub use multimap::{Multimap, Multimap as StableMultimap};
 pub use vec::{Vec as StableVec, Vec};
 pub mod vec_mem;
 pub mod writer;
 pub use btreemap::{BTreeMap, BTreeMap as StableBTreeMap};
+pub use btreeset::{BTreeSet, BTreeSet as StableBTreeSet};
 pub use file_mem::FileMemory;
 #[cfg(target_arch = "wasm32")]
 pub use ic0_memory::Ic0StableMemory;
+pub use indexed_map::{IndexedMap, IndexedMap as StableIndexedMap};
 use std::error;
 use std::fmt::{Display, Formatter};
 pub use storable::Storable;
@@ -60,6 +66,17 @@ pub trait Memory {
     fn write(&self, offset: u64, src: &[u8]);
 }
 
+/// A [`Memory`] that can also shrink, giving the space of its trailing pages back.
+pub trait ShrinkableMemory: Memory {
+    /// Shrinks the memory to the given number of pages, discarding the
+    /// contents of the pages beyond it. Does nothing if the memory is
+    /// not larger than `pages`. Returns the previous size of the memory
+    /// (in pages).
+    ///
+    /// Growing the memory again yields pages containing zeroes.
+    fn truncate(&self, pages: u64) -> u64;
+}
+
 // A helper function that reads a single 32bit integer encoded as
 // little-endian from the specified memory at the specified offset.
 fn read_u32<M: Memory>(m: &M, addr: Address) -> u32 {
@@ -41,29 +41,62 @@
 //! assert_eq!(bytes, vec![4, 5, 6]);
 //! ```
 use crate::{
-    read_struct,
+    read_struct, read_u32,
     types::{Address, Bytes},
-    write, write_struct, Memory, WASM_PAGE_SIZE,
+    write, write_struct, write_u32, Memory, ShrinkableMemory, WASM_PAGE_SIZE,
 };
 use std::cell::RefCell;
 use std::cmp::min;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::rc::Rc;
 
 const MAGIC: &[u8; 3] = b"MGR";
-const LAYOUT_VERSION: u8 = 1;
+const LAYOUT_VERSION_1: u8 = 1;
+const LAYOUT_VERSION_2: u8 = 2;
+const LAYOUT_VERSION_3: u8 = 3;
 
-// The maximum number of memories that can be created.
-const MAX_NUM_MEMORIES: u8 = 255;
+// The maximum number of memories that can be created in the V1 and V2 layouts.
+const MAX_NUM_MEMORIES: u16 = 255;
 
-// The maximum number of buckets the memory manager can handle.
+// The maximum number of buckets the memory manager can handle in the V1 and V2 layouts.
 // With a bucket size of 128 pages this can support up to 256GiB of memory.
 const MAX_NUM_BUCKETS: u64 = 32768;
 
+// The maximum number of memories that can be created in the V3 layout. The two largest ids are
+// used internally for the metadata memory and for marking buckets as unallocated.
+const MAX_NUM_MEMORIES_V3: u16 = u16::MAX - 1;
+
+// The maximum number of buckets the memory manager can handle in the V3 layout.
+const MAX_NUM_BUCKETS_V3: u64 = u32::MAX as u64;
+
 const BUCKET_SIZE_IN_PAGES: u64 = 128;
 
 // A value used internally to indicate that a bucket is unallocated.
-const UNALLOCATED_BUCKET_MARKER: u8 = MAX_NUM_MEMORIES;
+const UNALLOCATED_BUCKET_MARKER: u8 = MAX_NUM_MEMORIES as u8;
+const UNALLOCATED_BUCKET_MARKER_V3: u16 = u16::MAX;
+
+// A value used internally in the sub-memories of the V3 layout to indicate that a memory has
+// been used directly by the top-level memory manager.
+const USED_MEMORY_MARKER_V3: u16 = u16::MAX;
+
+// The memory storing the memory sizes and the bucket allocations in the V3 layout.
+const METADATA_MEMORY_ID: MemoryId = MemoryId(MAX_NUM_MEMORIES_V3);
+
+// The offset of the sub-memories in the metadata memory, which come after the size of every
+// memory.
+const SUB_MEMORIES_OFFSET_V3: u64 = (u16::MAX as u64 + 1) * 8;
+
+// The offset of the bucket allocations in the metadata memory, which come after the
+// sub-memories.
+const BUCKET_ALLOCATIONS_OFFSET_V3: u64 = SUB_MEMORIES_OFFSET_V3 + (u16::MAX as u64 + 1) * 4;
+
+// The size of the allocation of a bucket in the V3 layout: the memory that owns the bucket
+// followed by the bucket's position among the buckets of that memory.
+const BUCKET_ALLOCATION_SIZE_V3: u64 = 6;
+
+// The maximum number of metadata buckets, whose ids are stored in the first page.
+const MAX_NUM_METADATA_BUCKETS: u64 =
+    (WASM_PAGE_SIZE - core::mem::size_of::<HeaderV3>() as u64) / 4;
 
 // The offset where buckets are in memory.
 const BUCKETS_OFFSET_IN_PAGES: u64 = 1;
@@ -74,8 +107,9 @@ const HEADER_RESERVED_BYTES: usize = 32;
 
 /// A memory manager simulates multiple memories within a single memory.
 ///
-/// The memory manager can return up to 255 unique instances of [`VirtualMemory`], and each can be
-/// used independently and can grow up to the bounds of the underlying memory.
+/// The memory manager can return up to 255 unique instances of [`VirtualMemory`], or up to 65534
+/// in the V3 layout, and each can be used independently and can grow up to the bounds of the
+/// underlying memory.
 ///
 /// By default, the memory manager divides the memory into "buckets" of 128 pages. Each
 /// [`VirtualMemory`] is internally represented as a list of buckets. Buckets of different memories
@@ -85,6 +119,11 @@ const HEADER_RESERVED_BYTES: usize = 32;
 /// Because a [`VirtualMemory`] is a list of buckets, this implies that internally it grows one
 /// bucket at a time.
 ///
+/// A memory that is no longer needed can be released with [`MemoryManager::free`], which returns
+/// its buckets to a free pool, and a memory can release its trailing buckets by shrinking with
+/// [`ShrinkableMemory::truncate`](crate::ShrinkableMemory::truncate). Growing a memory reuses the
+/// free buckets before allocating new ones at the end of the underlying memory.
+///
 /// The first page of the memory is reserved for the memory manager's own state. The layout for
 /// this state is as follows:
 ///
@@ -128,8 +167,107 @@ const HEADER_RESERVED_BYTES: usize = 32;
 /// -------------------------------------------------- <- Page ((MAX_NUM_BUCKETS - 1) * N + 1)
 /// Bucket MAX_NUM_BUCKETS                ↕ N pages
 /// ```
+///
+/// # V2 layout
+///
+/// The V2 layout is identical to the V1 layout, except that buckets can be freed. In V1, every
+/// bucket below the number of allocated buckets is owned by a memory, and the buckets of a memory
+/// are in increasing order. In V2, such a bucket can also be marked as unallocated, in which case
+/// it's in the free pool and will be reused by the next memory that grows, wherever its other
+/// buckets are.
+///
+/// The order of the buckets of each memory is therefore stored explicitly, in a bucket of its
+/// own, the order bucket, whose id is stored in the first two bytes of the reserved space:
+///
+/// ```text
+/// -------------------------------------------------- <- Address of the order bucket
+/// Position of bucket 1                  ↕ 2 bytes       (among the buckets of its memory)
+/// --------------------------------------------------
+/// Position of bucket 2                  ↕ 2 bytes
+/// --------------------------------------------------
+/// ...
+/// --------------------------------------------------
+/// Position of bucket `MAX_NUM_BUCKETS`  ↕ 2 bytes
+/// ```
+///
+/// Memory managers keep the V1 layout, which older versions of this library can load, until a
+/// bucket is freed for the first time. The first bucket to be freed becomes the order bucket.
+///
+/// # V3 layout
+///
+/// The V1 and V2 layouts store the size of each memory and the owner of each bucket in the first
+/// page, which limits them to 255 memories and `MAX_NUM_BUCKETS` buckets. The V3 layout lifts
+/// these limits by storing them in a memory of their own, the metadata memory, which is made of
+/// buckets like any other memory and grows as more buckets are allocated. Only the ids of the
+/// metadata buckets are stored in the first page.
+///
+/// ```text
+/// -------------------------------------------------- <- Address 0
+/// Magic "MGR"                           ↕ 3 bytes
+/// --------------------------------------------------
+/// Layout version                        ↕ 1 byte
+/// --------------------------------------------------
+/// Bucket size (in pages) = N            ↕ 2 bytes
+/// --------------------------------------------------
+/// Number of allocated buckets           ↕ 4 bytes
+/// --------------------------------------------------
+/// Number of metadata buckets = M        ↕ 4 bytes
+/// --------------------------------------------------
+/// Reserved space                        ↕ 32 bytes
+/// --------------------------------------------------
+/// Metadata bucket 1                     ↕ 4 bytes       (the id of the bucket)
+/// --------------------------------------------------
+/// ...
+/// --------------------------------------------------
+/// Metadata bucket M                     ↕ 4 bytes
+/// -------------------------------------------------- <- Buckets (Page 1)
+/// Bucket 1                              ↕ N pages
+/// --------------------------------------------------
+/// ...
+/// ```
+///
+/// The metadata memory is laid out as follows:
+///
+/// ```text
+/// -------------------------------------------------- <- Address 0
+/// Size of memory 0 (in pages)           ↕ 8 bytes
+/// --------------------------------------------------
+/// ...
+/// --------------------------------------------------
+/// Size of memory 65535 (in pages)       ↕ 8 bytes
+/// -------------------------------------------------- <- Sub-memories
+/// Memory 0                              ↕ 4 bytes       (see below)
+/// --------------------------------------------------
+/// ...
+/// --------------------------------------------------
+/// Memory 65535                          ↕ 4 bytes
+/// -------------------------------------------------- <- Bucket allocations
+/// Bucket 1                              ↕ 6 bytes       (see below)
+/// --------------------------------------------------
+/// ...
+/// --------------------------------------------------
+/// Bucket (number of allocated buckets)  ↕ 6 bytes
+/// ```
+///
+/// The allocation of a bucket is the id of the memory that owns it, or `0xFFFF` if the bucket is
+/// free, followed by the position of the bucket among the buckets of that memory as a `u32`.
+///
+/// A memory that belongs to a sub-manager (see [`MemoryManager::sub_manager`]) stores the id of
+/// the memory of the sub-manager plus one in its first two bytes, followed by its id within the
+/// sub-manager. A memory that has grown while used directly stores `0xFFFF` in its first two
+/// bytes, so that it's never given to a sub-manager, even once it's freed. The first two bytes of
+/// other memories are zero.
+///
+/// The metadata memory takes a bucket of its own as soon as a memory grows, and its tables are
+/// read whenever the memory manager is loaded, so new memory managers use the V1 layout unless
+/// they are created with [`MemoryManager::init_v3`]. Memory managers in the V1 and V2 layouts
+/// keep their layout, along with its limits, until they are upgraded with
+/// [`MemoryManager::upgrade_layout`].
 pub struct MemoryManager<M: Memory> {
     inner: Rc<RefCell<MemoryManagerInner<M>>>,
+
+    // The memory this is the sub-manager of, or `None` for a top-level memory manager.
+    parent: Option<MemoryId>,
 }
 
 impl<M: Memory> MemoryManager<M> {
@@ -138,6 +276,15 @@ impl<M: Memory> MemoryManager<M> {
         Self::init_with_bucket_size(memory, BUCKET_SIZE_IN_PAGES as u16)
     }
 
+    /// Initializes a `MemoryManager` in the V3 layout with the given memory, which supports up
+    /// to 65534 memories and sub-managers. A memory manager in the V1 or V2 layout that is
+    /// already in the memory is upgraded with [`MemoryManager::upgrade_layout`].
+    pub fn init_v3(memory: M) -> Self {
+        let mem_mgr = Self::init(memory);
+        mem_mgr.upgrade_layout();
+        mem_mgr
+    }
+
     /// Initializes a `MemoryManager` with the given memory and bucket size in pages.
     pub fn init_with_bucket_size(memory: M, bucket_size_in_pages: u16) -> Self {
         Self {
@@ -145,16 +292,106 @@ impl<M: Memory> MemoryManager<M> {
                 memory,
                 bucket_size_in_pages,
             ))),
+            parent: None,
         }
     }
 
     /// Returns the memory associated with the given ID.
+    ///
+    /// PRECONDITION: the ID is below 255 if the memory manager uses the V1 or V2 layout.
     pub fn get(&self, id: MemoryId) -> VirtualMemory<M> {
         VirtualMemory {
-            id,
+            id: self.inner.borrow_mut().resolve(self.parent, id),
             memory_manager: self.inner.clone(),
+            runs: RefCell::new(None),
+        }
+    }
+
+    /// Returns a memory manager with its own namespace of memories, associated with the given ID.
+    ///
+    /// The memories of a sub-manager are stored directly in the buckets of the top-level memory
+    /// manager, so they are as fast as its own memories and don't need a header of their own.
+    /// Sub-managers can be nested. The memories of all the sub-managers share the 65534 memories
+    /// of the top-level memory manager, and are assigned the largest IDs that have never been
+    /// used directly. Memories that are used directly without ever growing are only remembered
+    /// until the memory manager is reloaded. The IDs of sub-manager memories can no longer be
+    /// used directly.
+    ///
+    /// The memory associated with the given ID can still be used alongside the sub-manager.
+    ///
+    /// PRECONDITION: the memory manager uses the V3 layout.
+    ///
+    /// ```
+    /// use ic_stable_structures::{DefaultMemoryImpl, Memory};
+    /// use ic_stable_structures::memory_manager::{MemoryManager, MemoryId};
+    ///
+    /// let mem_mgr = MemoryManager::init_v3(DefaultMemoryImpl::default());
+    /// let tenant_0 = mem_mgr.sub_manager(MemoryId::new(0));
+    /// let tenant_1 = mem_mgr.sub_manager(MemoryId::new(1));
+    ///
+    /// // Each tenant has its own memory 0.
+    /// tenant_0.get(MemoryId::new(0)).grow(1);
+    /// tenant_0.get(MemoryId::new(0)).write(0, &[1, 2, 3]);
+    /// assert_eq!(tenant_1.get(MemoryId::new(0)).size(), 0);
+    /// ```
+    pub fn sub_manager(&self, id: MemoryId) -> MemoryManager<M> {
+        assert_eq!(
+            self.inner.borrow().version,
+            LAYOUT_VERSION_3,
+            "sub-managers require the V3 layout, see `MemoryManager::init_v3`"
+        );
+        MemoryManager {
+            parent: Some(self.inner.borrow_mut().resolve(self.parent, id)),
+            inner: self.inner.clone(),
         }
     }
+
+    /// Frees the memory associated with the given ID, returning its buckets to the free pool.
+    ///
+    /// PRECONDITION: the ID is below 255 if the memory manager uses the V1 or V2 layout.
+    ///
+    /// The memory's size becomes zero and its contents are lost. The memory can still be used
+    /// afterwards, in which case it grows again like a new memory.
+    ///
+    /// The freed buckets are reused by the memories that grow next, except for the first bucket
+    /// ever freed by a memory manager in the V1 layout, which then switches to the V2 layout and
+    /// stores the order of the buckets of each memory in that bucket.
+    ///
+    /// ```
+    /// use ic_stable_structures::{DefaultMemoryImpl, Memory};
+    /// use ic_stable_structures::memory_manager::{MemoryManager, MemoryId};
+    ///
+    /// let mem_mgr = MemoryManager::init(DefaultMemoryImpl::default());
+    /// let memory_0 = mem_mgr.get(MemoryId::new(0));
+    /// memory_0.grow(1);
+    /// memory_0.write(0, &[1, 2, 3]);
+    ///
+    /// mem_mgr.free(MemoryId::new(0));
+    /// assert_eq!(memory_0.size(), 0);
+    ///
+    /// // The memory grows again like a new memory.
+    /// memory_0.grow(1);
+    /// let mut bytes = [0; 3];
+    /// memory_0.read(0, &mut bytes);
+    /// assert_eq!(bytes, [0, 0, 0]);
+    /// ```
+    pub fn free(&self, id: MemoryId) {
+        let mut inner = self.inner.borrow_mut();
+        let id = inner.resolve(self.parent, id);
+        inner.free(id)
+    }
+
+    /// Upgrades a memory manager in the V1 or V2 layout to the V3 layout, which supports up to
+    /// 65534 memories and lifts the limit on the number of buckets. Does nothing if the memory
+    /// manager already uses the V3 layout.
+    ///
+    /// The buckets of the memories stay where they are, so the upgrade only allocates the
+    /// buckets of the metadata memory and copies the memory sizes and bucket allocations into
+    /// them. Once upgraded, the memory manager can no longer be loaded by versions of this
+    /// library that don't support the V3 layout.
+    pub fn upgrade_layout(&self) {
+        self.inner.borrow_mut().upgrade_layout()
+    }
 }
 
 #[repr(C, packed)]
@@ -169,8 +406,12 @@ struct Header {
     // The size of a bucket in Wasm pages.
     bucket_size_in_pages: u16,
 
+    // The bucket storing the position of each bucket among the buckets of its memory in the V2
+    // layout. Zero in the V1 layout.
+    order_bucket: u16,
+
     // Reserved bytes for future extensions
-    _reserved: [u8; HEADER_RESERVED_BYTES],
+    _reserved: [u8; HEADER_RESERVED_BYTES - 2],
 
     // The size of each individual memory that can be created by the memory manager.
     memory_sizes_in_pages: [u64; MAX_NUM_MEMORIES as usize],
@@ -182,10 +423,55 @@ impl Header {
     }
 }
 
+#[repr(C, packed)]
+struct HeaderV3 {
+    magic: [u8; 3],
+
+    version: u8,
+
+    // The size of a bucket in Wasm pages.
+    bucket_size_in_pages: u16,
+
+    // The number of buckets allocated by the memory manager.
+    num_allocated_buckets: u32,
+
+    // The number of buckets allocated to the metadata memory, whose ids follow the header.
+    num_metadata_buckets: u32,
+
+    // Reserved bytes for future extensions
+    _reserved: [u8; HEADER_RESERVED_BYTES],
+}
+
+impl HeaderV3 {
+    fn size() -> Bytes {
+        Bytes::new(core::mem::size_of::<Self>() as u64)
+    }
+}
+
 #[derive(Clone)]
 pub struct VirtualMemory<M: Memory> {
     id: MemoryId,
     memory_manager: Rc<RefCell<MemoryManagerInner<M>>>,
+
+    // The runs of buckets of the memory, cached along with the generation of the memory manager
+    // they were read at so that accesses don't need to look them up.
+    runs: RefCell<Option<(u64, Rc<Vec<BucketRun>>)>>,
+}
+
+impl<M: Memory> VirtualMemory<M> {
+    // Returns the runs of buckets of the memory, reading them from the memory manager only if
+    // its buckets changed since they were cached.
+    fn runs(&self, memory_manager: &MemoryManagerInner<M>) -> Rc<Vec<BucketRun>> {
+        let mut cache = self.runs.borrow_mut();
+        match &*cache {
+            Some((generation, runs)) if *generation == memory_manager.generation => runs.clone(),
+            _ => {
+                let runs = memory_manager.memory_runs(self.id);
+                *cache = Some((memory_manager.generation, runs.clone()));
+                runs
+            }
+        }
+    }
 }
 
 impl<M: Memory> Memory for VirtualMemory<M> {
@@ -198,11 +484,23 @@ impl<M: Memory> Memory for VirtualMemory<M> {
     }
 
     fn read(&self, offset: u64, dst: &mut [u8]) {
-        self.memory_manager.borrow().read(self.id, offset, dst)
+        let memory_manager = self.memory_manager.borrow();
+        let runs = self.runs(&memory_manager);
+        memory_manager.read_runs(self.id, &runs, offset, dst)
     }
 
     fn write(&self, offset: u64, src: &[u8]) {
-        self.memory_manager.borrow().write(self.id, offset, src)
+        let memory_manager = self.memory_manager.borrow();
+        let runs = self.runs(&memory_manager);
+        memory_manager.write_runs(self.id, &runs, offset, src)
+    }
+}
+
+/// Truncating a [`VirtualMemory`] returns the buckets it no longer needs to the free pool of
+/// the memory manager.
+impl<M: Memory> ShrinkableMemory for VirtualMemory<M> {
+    fn truncate(&self, pages: u64) -> u64 {
+        self.memory_manager.borrow_mut().truncate(self.id, pages)
     }
 }
 
@@ -210,16 +508,47 @@ impl<M: Memory> Memory for VirtualMemory<M> {
 struct MemoryManagerInner<M: Memory> {
     memory: M,
 
+    // The version of the layout the memory manager is saved in.
+    version: u8,
+
     // The number of buckets that have been allocated.
-    allocated_buckets: u16,
+    allocated_buckets: u32,
 
     bucket_size_in_pages: u16,
 
-    // An array storing the size (in pages) of each of the managed memories.
-    memory_sizes_in_pages: [u64; MAX_NUM_MEMORIES as usize],
+    // The size (in pages) of each of the managed memories, indexed by memory id.
+    memory_sizes_in_pages: Vec<u64>,
 
     // A map mapping each managed memory to the bucket ids that are allocated to it.
     memory_buckets: BTreeMap<MemoryId, Vec<BucketId>>,
+
+    // The buckets of each managed memory, with the buckets that are adjacent in the underlying
+    // memory coalesced into runs.
+    memory_runs: BTreeMap<MemoryId, Rc<Vec<BucketRun>>>,
+
+    // Incremented whenever the buckets of a memory change, which invalidates the runs cached by
+    // the `VirtualMemory`s.
+    generation: u64,
+
+    // The buckets that have been allocated and then freed, which are reused before allocating
+    // new buckets.
+    free_buckets: BTreeSet<BucketId>,
+
+    // The bucket storing the position of each bucket among the buckets of its memory in the V2
+    // layout.
+    order_bucket: Option<BucketId>,
+
+    // A map mapping the memory of each sub-manager and the id of a memory within it to the
+    // memory that stores it.
+    sub_memories: BTreeMap<(MemoryId, MemoryId), MemoryId>,
+
+    // The memories that store the memories of sub-managers.
+    sub_memory_ids: BTreeSet<MemoryId>,
+
+    // The memories that have been used directly, which can't store the memories of
+    // sub-managers. The V3 layout saves them along with the sub-memories once they grow, so that
+    // getting a memory doesn't write to the underlying memory.
+    used_ids: BTreeSet<MemoryId>,
 }
 
 impl<M: Memory> MemoryManagerInner<M> {
@@ -244,9 +573,17 @@ impl<M: Memory> MemoryManagerInner<M> {
     fn new(memory: M, bucket_size_in_pages: u16) -> Self {
         let mem_mgr = Self {
             memory,
+            version: LAYOUT_VERSION_1,
             allocated_buckets: 0,
-            memory_sizes_in_pages: [0; MAX_NUM_MEMORIES as usize],
+            memory_sizes_in_pages: vec![0; MAX_NUM_MEMORIES as usize],
             memory_buckets: BTreeMap::new(),
+            memory_runs: BTreeMap::new(),
+            generation: 0,
+            free_buckets: BTreeSet::new(),
+            order_bucket: None,
+            sub_memories: BTreeMap::new(),
+            sub_memory_ids: BTreeSet::new(),
+            used_ids: BTreeSet::new(),
             bucket_size_in_pages,
         };
 
@@ -263,51 +600,374 @@ impl<M: Memory> MemoryManagerInner<M> {
     }
 
     fn load(memory: M) -> Self {
+        let mut magic = [0; 3];
+        memory.read(0, &mut magic);
+        assert_eq!(&magic, MAGIC, "Bad magic.");
+
+        let mut version = [0];
+        memory.read(magic.len() as u64, &mut version);
+        match version[0] {
+            LAYOUT_VERSION_1 | LAYOUT_VERSION_2 => Self::load_v2(memory),
+            LAYOUT_VERSION_3 => Self::load_v3(memory),
+            _ => panic!("Unsupported version."),
+        }
+    }
+
+    // Loads a memory manager in the V1 or V2 layout.
+    fn load_v2(memory: M) -> Self {
         // Read the header from memory.
         let header: Header = read_struct(Address::from(0), &memory);
-        assert_eq!(&header.magic, MAGIC, "Bad magic.");
-        assert_eq!(header.version, LAYOUT_VERSION, "Unsupported version.");
 
         let mut buckets = vec![0; MAX_NUM_BUCKETS as usize];
         memory.read(bucket_allocations_address(BucketId(0)).get(), &mut buckets);
 
+        // The V1 layout has no order bucket, as the buckets of a memory are in increasing order.
+        let order_bucket =
+            (header.version == LAYOUT_VERSION_2).then_some(BucketId(header.order_bucket as u32));
+        let mut positions = vec![0; header.num_allocated_buckets as usize * 2];
+        if let Some(order_bucket) = order_bucket {
+            let bucket_size_in_bytes =
+                Bytes::from(header.bucket_size_in_pages as u64 * WASM_PAGE_SIZE);
+            memory.read(
+                bucket_address(order_bucket, bucket_size_in_bytes).get(),
+                &mut positions,
+            );
+        }
+
         let mut memory_buckets = BTreeMap::new();
+        let mut free_buckets = BTreeSet::new();
         for (bucket_idx, memory) in buckets.into_iter().enumerate() {
+            let bucket_id = BucketId(bucket_idx as u32);
             if memory != UNALLOCATED_BUCKET_MARKER {
+                let position =
+                    u16::from_le_bytes([positions[bucket_idx * 2], positions[bucket_idx * 2 + 1]]);
                 memory_buckets
-                    .entry(MemoryId(memory))
+                    .entry(MemoryId(memory as u16))
                     .or_insert_with(Vec::new)
-                    .push(BucketId(bucket_idx as u16));
+                    .push((position as u32, bucket_id));
+            } else if bucket_id.0 < header.num_allocated_buckets as u32
+                && Some(bucket_id) != order_bucket
+            {
+                // An unallocated bucket that was allocated before has been freed.
+                free_buckets.insert(bucket_id);
             }
         }
 
-        Self {
+        let mut mem_mgr = Self {
+            memory,
+            version: header.version,
+            allocated_buckets: header.num_allocated_buckets as u32,
+            bucket_size_in_pages: header.bucket_size_in_pages,
+            memory_sizes_in_pages: { header.memory_sizes_in_pages }.to_vec(),
+            memory_buckets: sort_by_position(memory_buckets),
+            memory_runs: BTreeMap::new(),
+            generation: 0,
+            free_buckets,
+            order_bucket,
+            sub_memories: BTreeMap::new(),
+            sub_memory_ids: BTreeSet::new(),
+            used_ids: BTreeSet::new(),
+        };
+        mem_mgr.update_all_runs();
+        mem_mgr
+    }
+
+    // Loads a memory manager in the V3 layout.
+    fn load_v3(memory: M) -> Self {
+        let header: HeaderV3 = read_struct(Address::from(0), &memory);
+        let metadata_buckets: Vec<_> = (0..header.num_metadata_buckets as u64)
+            .map(|i| BucketId(read_u32(&memory, metadata_bucket_address(i))))
+            .collect();
+
+        let mut mem_mgr = Self {
             memory,
+            version: LAYOUT_VERSION_3,
             allocated_buckets: header.num_allocated_buckets,
             bucket_size_in_pages: header.bucket_size_in_pages,
-            memory_sizes_in_pages: header.memory_sizes_in_pages,
-            memory_buckets,
+            memory_sizes_in_pages: vec![0; MAX_NUM_MEMORIES_V3 as usize + 1],
+            memory_buckets: BTreeMap::new(),
+            memory_runs: BTreeMap::new(),
+            generation: 0,
+            free_buckets: BTreeSet::new(),
+            order_bucket: None,
+            sub_memories: BTreeMap::new(),
+            sub_memory_ids: BTreeSet::new(),
+            used_ids: BTreeSet::new(),
+        };
+
+        if metadata_buckets.is_empty() {
+            // No bucket has been allocated yet.
+            return mem_mgr;
+        }
+
+        // Load the metadata memory, which stores everything else.
+        mem_mgr.memory_sizes_in_pages[METADATA_MEMORY_ID.0 as usize] =
+            metadata_buckets.len() as u64 * mem_mgr.bucket_size_in_pages as u64;
+        mem_mgr
+            .memory_buckets
+            .insert(METADATA_MEMORY_ID, metadata_buckets);
+        mem_mgr.update_runs(METADATA_MEMORY_ID);
+
+        let mut memory_sizes = vec![0; MAX_NUM_MEMORIES_V3 as usize * 8];
+        mem_mgr.read(METADATA_MEMORY_ID, 0, &mut memory_sizes);
+        for (id, size) in memory_sizes.chunks_exact(8).enumerate() {
+            mem_mgr.memory_sizes_in_pages[id] = u64::from_le_bytes(size.try_into().unwrap());
+        }
+
+        let mut sub_memories = vec![0; MAX_NUM_MEMORIES_V3 as usize * 4];
+        mem_mgr.read(
+            METADATA_MEMORY_ID,
+            SUB_MEMORIES_OFFSET_V3,
+            &mut sub_memories,
+        );
+        for (id, sub_memory) in sub_memories.chunks_exact(4).enumerate() {
+            match u16::from_le_bytes([sub_memory[0], sub_memory[1]]) {
+                0 => {}
+                USED_MEMORY_MARKER_V3 => {
+                    mem_mgr.used_ids.insert(MemoryId(id as u16));
+                }
+                parent => {
+                    let local_id = u16::from_le_bytes([sub_memory[2], sub_memory[3]]);
+                    mem_mgr.add_sub_memory(
+                        MemoryId(parent - 1),
+                        MemoryId(local_id),
+                        MemoryId(id as u16),
+                    );
+                }
+            }
+        }
+
+        let mut buckets =
+            vec![0; (mem_mgr.allocated_buckets as u64 * BUCKET_ALLOCATION_SIZE_V3) as usize];
+        mem_mgr.read(
+            METADATA_MEMORY_ID,
+            BUCKET_ALLOCATIONS_OFFSET_V3,
+            &mut buckets,
+        );
+        let mut memory_buckets: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (bucket_idx, allocation) in buckets
+            .chunks_exact(BUCKET_ALLOCATION_SIZE_V3 as usize)
+            .enumerate()
+        {
+            let bucket_id = BucketId(bucket_idx as u32);
+            match u16::from_le_bytes([allocation[0], allocation[1]]) {
+                UNALLOCATED_BUCKET_MARKER_V3 => {
+                    mem_mgr.free_buckets.insert(bucket_id);
+                }
+                // The metadata buckets are loaded from the header.
+                id if id == METADATA_MEMORY_ID.0 => {}
+                id => {
+                    let position = u32::from_le_bytes(allocation[2..6].try_into().unwrap());
+                    memory_buckets
+                        .entry(MemoryId(id))
+                        .or_default()
+                        .push((position, bucket_id));
+                }
+            }
         }
+        mem_mgr
+            .memory_buckets
+            .extend(sort_by_position(memory_buckets));
+
+        mem_mgr.update_all_runs();
+        mem_mgr
     }
 
     fn save_header(&self) {
+        if self.version == LAYOUT_VERSION_3 {
+            let header = HeaderV3 {
+                magic: *MAGIC,
+                version: LAYOUT_VERSION_3,
+                bucket_size_in_pages: self.bucket_size_in_pages,
+                num_allocated_buckets: self.allocated_buckets,
+                num_metadata_buckets: self.memory_buckets_len(METADATA_MEMORY_ID) as u32,
+                _reserved: [0; HEADER_RESERVED_BYTES],
+            };
+
+            write_struct(&header, Address::from(0), &self.memory);
+            return;
+        }
+
         let header = Header {
             magic: *MAGIC,
-            version: LAYOUT_VERSION,
-            num_allocated_buckets: self.allocated_buckets,
+            version: self.version,
+            num_allocated_buckets: self.allocated_buckets as u16,
             bucket_size_in_pages: self.bucket_size_in_pages,
-            _reserved: [0; HEADER_RESERVED_BYTES],
-            memory_sizes_in_pages: self.memory_sizes_in_pages,
+            order_bucket: self.order_bucket.map_or(0, |bucket_id| bucket_id.0 as u16),
+            _reserved: [0; HEADER_RESERVED_BYTES - 2],
+            memory_sizes_in_pages: self.memory_sizes_in_pages[..MAX_NUM_MEMORIES as usize]
+                .try_into()
+                .unwrap(),
         };
 
         write_struct(&header, Address::from(0), &self.memory);
     }
 
+    // Saves the size of a memory. The V1 and V2 layouts store it in the header.
+    fn save_memory_size(&self, id: MemoryId) {
+        // Without a metadata memory, no bucket has been allocated and all the memories are empty.
+        if self.version == LAYOUT_VERSION_3 && self.memory_size(METADATA_MEMORY_ID) > 0 {
+            self.write(
+                METADATA_MEMORY_ID,
+                id.0 as u64 * 8,
+                &self.memory_size(id).to_le_bytes(),
+            );
+        }
+    }
+
+    // Saves the memory that owns the given bucket along with the bucket's position among the
+    // buckets of that memory, or `None` if the bucket is unallocated.
+    fn save_bucket_owner(&self, bucket_id: BucketId, owner: Option<(MemoryId, usize)>) {
+        if self.version == LAYOUT_VERSION_3 {
+            let (owner, position) = owner
+                .map_or((UNALLOCATED_BUCKET_MARKER_V3, 0), |(id, position)| {
+                    (id.0, position as u32)
+                });
+            let mut allocation = [0; BUCKET_ALLOCATION_SIZE_V3 as usize];
+            allocation[..2].copy_from_slice(&owner.to_le_bytes());
+            allocation[2..].copy_from_slice(&position.to_le_bytes());
+            self.write(
+                METADATA_MEMORY_ID,
+                BUCKET_ALLOCATIONS_OFFSET_V3 + bucket_id.0 as u64 * BUCKET_ALLOCATION_SIZE_V3,
+                &allocation,
+            );
+            return;
+        }
+
+        write(
+            &self.memory,
+            bucket_allocations_address(bucket_id).get(),
+            &[owner.map_or(UNALLOCATED_BUCKET_MARKER, |(id, _)| id.0 as u8)],
+        );
+        if let (Some(order_bucket), Some((_, position))) = (self.order_bucket, owner) {
+            write(
+                &self.memory,
+                bucket_address(order_bucket, self.bucket_size_in_bytes()).get()
+                    + bucket_id.0 as u64 * 2,
+                &(position as u16).to_le_bytes(),
+            );
+        }
+    }
+
+    // Returns the memory that stores the memory with the given id in the sub-manager of
+    // `parent`, or in the top-level memory manager if `parent` is `None`.
+    fn resolve(&mut self, parent: Option<MemoryId>, id: MemoryId) -> MemoryId {
+        assert!(
+            id.0 < self.max_num_memories(),
+            "{id:?} requires the V3 layout, see `MemoryManager::init_v3`"
+        );
+
+        let parent = match parent {
+            Some(parent) => parent,
+            None => {
+                assert!(
+                    !self.sub_memory_ids.contains(&id),
+                    "{id:?} is used by a sub-manager"
+                );
+                self.used_ids.insert(id);
+                return id;
+            }
+        };
+
+        if let Some(sub_memory_id) = self.sub_memories.get(&(parent, id)) {
+            return *sub_memory_id;
+        }
+
+        // Assign the largest memory that isn't in use.
+        let sub_memory_id = (0..MAX_NUM_MEMORIES_V3)
+            .rev()
+            .map(MemoryId)
+            .find(|id| {
+                !self.used_ids.contains(id)
+                    && !self.sub_memory_ids.contains(id)
+                    && !self.memory_buckets.contains_key(id)
+                    && self
+                        .sub_memories
+                        .range((*id, MemoryId(0))..=(*id, MemoryId(u16::MAX)))
+                        .next()
+                        .is_none()
+            })
+            .expect("all the memories are in use");
+        self.add_sub_memory(parent, id, sub_memory_id);
+        self.save_sub_memory(sub_memory_id, parent.0 + 1, id);
+
+        sub_memory_id
+    }
+
+    // Saves the entry of a memory in the sub-memories of the V3 layout, made of the given
+    // marker, which is either the id of the memory of its sub-manager plus one or
+    // `USED_MEMORY_MARKER_V3`, followed by its id within the sub-manager.
+    fn save_sub_memory(&mut self, id: MemoryId, marker: u16, local_id: MemoryId) {
+        let mut sub_memory = [0; 4];
+        sub_memory[..2].copy_from_slice(&marker.to_le_bytes());
+        sub_memory[2..].copy_from_slice(&local_id.0.to_le_bytes());
+        self.grow_metadata();
+        self.write(
+            METADATA_MEMORY_ID,
+            SUB_MEMORIES_OFFSET_V3 + id.0 as u64 * 4,
+            &sub_memory,
+        );
+        self.save_header();
+    }
+
+    fn add_sub_memory(&mut self, parent: MemoryId, id: MemoryId, sub_memory_id: MemoryId) {
+        self.sub_memories.insert((parent, id), sub_memory_id);
+        self.sub_memory_ids.insert(sub_memory_id);
+    }
+
+    // Returns the maximum number of memories the layout supports.
+    fn max_num_memories(&self) -> u16 {
+        if self.version == LAYOUT_VERSION_3 {
+            MAX_NUM_MEMORIES_V3
+        } else {
+            MAX_NUM_MEMORIES
+        }
+    }
+
+    // Returns the maximum number of buckets the layout supports.
+    fn max_num_buckets(&self) -> u64 {
+        if self.version == LAYOUT_VERSION_3 {
+            MAX_NUM_BUCKETS_V3
+        } else {
+            MAX_NUM_BUCKETS
+        }
+    }
+
     // Returns the size of a memory (in pages).
     fn memory_size(&self, id: MemoryId) -> u64 {
         self.memory_sizes_in_pages[id.0 as usize]
     }
 
+    // Returns the runs of buckets of a memory.
+    fn memory_runs(&self, id: MemoryId) -> Rc<Vec<BucketRun>> {
+        self.memory_runs.get(&id).cloned().unwrap_or_default()
+    }
+
+    // Coalesces the buckets of a memory into runs, after its buckets changed.
+    fn update_runs(&mut self, id: MemoryId) {
+        match self.memory_buckets.get(&id) {
+            Some(buckets) => {
+                self.memory_runs.insert(id, Rc::new(bucket_runs(buckets)));
+            }
+            None => {
+                self.memory_runs.remove(&id);
+            }
+        }
+        self.generation += 1;
+    }
+
+    fn update_all_runs(&mut self) {
+        let ids: Vec<_> = self.memory_buckets.keys().copied().collect();
+        for id in ids {
+            self.update_runs(id);
+        }
+    }
+
+    // Returns the number of buckets allocated to a memory.
+    fn memory_buckets_len(&self, id: MemoryId) -> usize {
+        self.memory_buckets.get(&id).map_or(0, Vec::len)
+    }
+
     // Grows the memory with the given id by the given number of pages.
     fn grow(&mut self, id: MemoryId, pages: u64) -> i64 {
         // Compute how many additional buckets are needed.
@@ -317,28 +977,78 @@ impl<M: Memory> MemoryManagerInner<M> {
         let required_buckets = self.num_buckets_needed(new_size);
         let new_buckets_needed = required_buckets - current_buckets;
 
-        if new_buckets_needed + self.allocated_buckets as u64 > MAX_NUM_BUCKETS {
+        let new_buckets = match self.allocate_buckets(id, new_buckets_needed) {
+            Some(new_buckets) => new_buckets,
             // Exceeded the memory that can be managed.
-            return -1;
+            None => return -1,
+        };
+
+        // Update the memory with the new size.
+        self.memory_sizes_in_pages[id.0 as usize] = new_size;
+
+        // Write in stable store that the new buckets belong to the memory with the provided
+        // `id`, making room for it in the metadata memory first.
+        self.grow_metadata();
+        let first_position = self.memory_buckets_len(id) - new_buckets.len();
+        for (i, new_bucket_id) in new_buckets.into_iter().enumerate() {
+            self.save_bucket_owner(new_bucket_id, Some((id, first_position + i)));
+        }
+
+        // A memory used directly is only recorded as such once it grows.
+        if old_size == 0
+            && new_size > 0
+            && self.version == LAYOUT_VERSION_3
+            && self.used_ids.contains(&id)
+        {
+            self.save_sub_memory(id, USED_MEMORY_MARKER_V3, MemoryId(0));
+        }
+
+        // Update the header and return the old size.
+        self.save_memory_size(id);
+        self.save_header();
+        old_size as i64
+    }
+
+    // Allocates the given number of buckets to a memory and grows the underlying memory to fit
+    // them. Returns the new buckets, or `None` if the layout has no room for them.
+    //
+    // The owners of the new buckets are not saved.
+    fn allocate_buckets(&mut self, id: MemoryId, num_buckets: u64) -> Option<Vec<BucketId>> {
+        if num_buckets
+            > self.free_buckets.len() as u64 + self.max_num_buckets()
+                - self.allocated_buckets as u64
+        {
+            return None;
         }
 
-        // Allocate new buckets as needed.
-        for _ in 0..new_buckets_needed {
-            let new_bucket_id = BucketId(self.allocated_buckets);
+        // Pick the buckets to add to the memory, reusing the free buckets before allocating new
+        // ones.
+        let num_reused_buckets = min(num_buckets, self.free_buckets.len() as u64);
+        let mut new_buckets: Vec<_> = self
+            .free_buckets
+            .iter()
+            .take(num_reused_buckets as usize)
+            .copied()
+            .collect();
+        let num_allocated_buckets =
+            self.allocated_buckets as u64 + num_buckets - num_reused_buckets;
+        new_buckets.extend((self.allocated_buckets..num_allocated_buckets as u32).map(BucketId));
+
+        // Allocate the buckets.
+        for new_bucket_id in new_buckets.iter() {
+            if self.free_buckets.remove(new_bucket_id) {
+                // A reused bucket still holds the contents of the memory that freed it.
+                self.zero_bucket(*new_bucket_id);
+            }
 
             self.memory_buckets
                 .entry(id)
                 .or_insert_with(Vec::new)
-                .push(new_bucket_id);
-
-            // Write in stable store that this bucket belongs to the memory with the provided `id`.
-            write(
-                &self.memory,
-                bucket_allocations_address(new_bucket_id).get(),
-                &[id.0],
-            );
-
-            self.allocated_buckets += 1;
+                .push(*new_bucket_id);
+        }
+        self.allocated_buckets = num_allocated_buckets as u32;
+        if !new_buckets.is_empty() {
+            self.update_runs(id);
         }
 
         // Grow the underlying memory if necessary.
@@ -352,61 +1062,235 @@ impl<M: Memory> MemoryManagerInner<M> {
             }
         }
 
-        // Update the memory with the new size.
-        self.memory_sizes_in_pages[id.0 as usize] = new_size;
-
-        // Update the header and return the old size.
-        self.save_header();
-        old_size as i64
+        Some(new_buckets)
     }
 
-    fn write(&self, id: MemoryId, offset: u64, src: &[u8]) {
-        if (offset + src.len() as u64) > self.memory_size(id) * WASM_PAGE_SIZE {
-            panic!("{id:?}: write out of bounds");
+    // Grows the metadata memory of the V3 layout until it has room for the owners of all the
+    // allocated buckets, including its own.
+    fn grow_metadata(&mut self) {
+        if self.version != LAYOUT_VERSION_3
+            || (self.allocated_buckets == 0
+                && self.sub_memories.is_empty()
+                && self.used_ids.is_empty())
+        {
+            return;
         }
 
-        let mut bytes_written = 0;
-        for Segment { address, length } in self.bucket_iter(id, offset, src.len()) {
-            self.memory.write(
-                address.get(),
-                &src[bytes_written as usize..(bytes_written + length.get()) as usize],
+        let mut new_buckets = vec![];
+        while self.memory_size(METADATA_MEMORY_ID) * WASM_PAGE_SIZE
+            < BUCKET_ALLOCATIONS_OFFSET_V3
+                + self.allocated_buckets as u64 * BUCKET_ALLOCATION_SIZE_V3
+        {
+            let num_metadata_buckets = self.memory_buckets_len(METADATA_MEMORY_ID) as u64;
+            assert!(
+                num_metadata_buckets < MAX_NUM_METADATA_BUCKETS,
+                "the memory manager's metadata is full"
             );
+            let new_bucket_id = self
+                .allocate_buckets(METADATA_MEMORY_ID, 1)
+                .expect("no room for the memory manager's metadata")[0];
+            self.memory_sizes_in_pages[METADATA_MEMORY_ID.0 as usize] +=
+                self.bucket_size_in_pages as u64;
+            write_u32(
+                &self.memory,
+                metadata_bucket_address(num_metadata_buckets),
+                new_bucket_id.0,
+            );
+            new_buckets.push((new_bucket_id, num_metadata_buckets as usize));
+        }
 
-            bytes_written += length.get();
+        for (new_bucket_id, position) in new_buckets {
+            self.save_bucket_owner(new_bucket_id, Some((METADATA_MEMORY_ID, position)));
         }
     }
 
-    fn read(&self, id: MemoryId, offset: u64, dst: &mut [u8]) {
-        if (offset + dst.len() as u64) > self.memory_size(id) * WASM_PAGE_SIZE {
-            panic!("{id:?}: read out of bounds");
+    // Upgrades the memory manager to the V3 layout.
+    fn upgrade_layout(&mut self) {
+        if self.version == LAYOUT_VERSION_3 {
+            return;
         }
 
-        let mut bytes_read = 0;
-        for Segment { address, length } in self.bucket_iter(id, offset, dst.len()) {
-            self.memory.read(
-                address.get(),
-                &mut dst[bytes_read as usize..(bytes_read + length.get()) as usize],
-            );
+        self.version = LAYOUT_VERSION_3;
+        self.memory_sizes_in_pages
+            .resize(MAX_NUM_MEMORIES_V3 as usize + 1, 0);
+        // The metadata memory stores the positions of the buckets, so the order bucket of the
+        // V2 layout is no longer needed.
+        if let Some(order_bucket) = self.order_bucket.take() {
+            self.free_buckets.insert(order_bucket);
+        }
+        self.grow_metadata();
 
-            bytes_read += length.get();
+        // Copy the memory sizes and bucket allocations into the metadata memory.
+        for id in 0..MAX_NUM_MEMORIES {
+            self.save_memory_size(MemoryId(id));
+        }
+        for (id, buckets) in self.memory_buckets.iter() {
+            for (position, bucket_id) in buckets.iter().enumerate() {
+                self.save_bucket_owner(*bucket_id, Some((*id, position)));
+            }
         }
+        for bucket_id in self.free_buckets.iter() {
+            self.save_bucket_owner(*bucket_id, None);
+        }
+        for id in self.used_ids.clone() {
+            self.save_sub_memory(id, USED_MEMORY_MARKER_V3, MemoryId(0));
+        }
+
+        self.save_header();
     }
 
-    // Initializes a [`BucketIterator`].
-    fn bucket_iter(&self, id: MemoryId, offset: u64, length: usize) -> BucketIterator {
-        // Get the buckets allocated to the given memory id.
-        let buckets = match self.memory_buckets.get(&id) {
-            Some(s) => s.as_slice(),
+    // Frees the memory with the given id, returning its buckets to the free pool.
+    fn free(&mut self, id: MemoryId) {
+        self.truncate(id, 0);
+    }
+
+    // Shrinks the memory with the given id to the given number of pages, returning the buckets
+    // that are no longer needed to the free pool.
+    fn truncate(&mut self, id: MemoryId, pages: u64) -> u64 {
+        let old_size = self.memory_size(id);
+        if pages >= old_size {
+            return old_size;
+        }
+
+        // The discarded pages of the last remaining bucket must read as zeros if the memory
+        // grows again.
+        let remaining_buckets = self.num_buckets_needed(pages);
+        let end_of_remaining_buckets = min(
+            old_size,
+            remaining_buckets * self.bucket_size_in_pages as u64,
+        );
+        let zeros = vec![0; WASM_PAGE_SIZE as usize];
+        for page in pages..end_of_remaining_buckets {
+            self.write(id, page * WASM_PAGE_SIZE, &zeros);
+        }
+
+        let buckets = self.memory_buckets.entry(id).or_default();
+        let mut released_buckets = buckets.split_off(remaining_buckets as usize);
+        if buckets.is_empty() {
+            self.memory_buckets.remove(&id);
+        }
+        self.update_runs(id);
+
+        // Only the V2 layout allows free buckets in the bucket allocations. Since the buckets of
+        // a memory can then be in any order, their positions are stored in one of the released
+        // buckets.
+        if !released_buckets.is_empty() && self.version == LAYOUT_VERSION_1 {
+            let order_bucket = released_buckets.remove(0);
+            self.save_bucket_owner(order_bucket, None);
+            self.version = LAYOUT_VERSION_2;
+            self.order_bucket = Some(order_bucket);
+            self.save_bucket_positions();
+        }
+        for bucket_id in released_buckets {
+            self.save_bucket_owner(bucket_id, None);
+            self.free_buckets.insert(bucket_id);
+        }
+
+        self.memory_sizes_in_pages[id.0 as usize] = pages;
+        self.save_memory_size(id);
+        self.save_header();
+        old_size
+    }
+
+    // Saves the position of every allocated bucket among the buckets of its memory in the order
+    // bucket of the V2 layout.
+    fn save_bucket_positions(&self) {
+        let order_bucket = self
+            .order_bucket
+            .expect("the V2 layout has an order bucket");
+        let mut positions = vec![0; self.allocated_buckets as usize * 2];
+        for buckets in self.memory_buckets.values() {
+            for (position, bucket_id) in buckets.iter().enumerate() {
+                let idx = bucket_id.0 as usize * 2;
+                positions[idx..idx + 2].copy_from_slice(&(position as u16).to_le_bytes());
+            }
+        }
+        write(
+            &self.memory,
+            bucket_address(order_bucket, self.bucket_size_in_bytes()).get(),
+            &positions,
+        );
+    }
+
+    // Overwrites the contents of the given bucket with zeros.
+    fn zero_bucket(&self, bucket_id: BucketId) {
+        let zeros = vec![0; WASM_PAGE_SIZE as usize];
+        let bucket_address = bucket_address(bucket_id, self.bucket_size_in_bytes());
+        for page in 0..self.bucket_size_in_pages as u64 {
+            self.memory
+                .write(bucket_address.get() + page * WASM_PAGE_SIZE, &zeros);
+        }
+    }
+
+    fn write(&self, id: MemoryId, offset: u64, src: &[u8]) {
+        self.write_runs(id, self.runs(id), offset, src)
+    }
+
+    // Writes to the memory with the given id, whose runs of buckets are given.
+    fn write_runs(&self, id: MemoryId, runs: &[BucketRun], offset: u64, src: &[u8]) {
+        if (offset + src.len() as u64) > self.memory_size(id) * WASM_PAGE_SIZE {
+            panic!("{id:?}: write out of bounds");
+        }
+
+        let mut bytes_written = 0;
+        for Segment { address, length } in self.bucket_iter(runs, offset, src.len()) {
+            self.memory.write(
+                address.get(),
+                &src[bytes_written as usize..(bytes_written + length.get()) as usize],
+            );
+
+            bytes_written += length.get();
+        }
+    }
+
+    fn read(&self, id: MemoryId, offset: u64, dst: &mut [u8]) {
+        self.read_runs(id, self.runs(id), offset, dst)
+    }
+
+    // Reads from the memory with the given id, whose runs of buckets are given.
+    fn read_runs(&self, id: MemoryId, runs: &[BucketRun], offset: u64, dst: &mut [u8]) {
+        if (offset + dst.len() as u64) > self.memory_size(id) * WASM_PAGE_SIZE {
+            panic!("{id:?}: read out of bounds");
+        }
+
+        let mut bytes_read = 0;
+        for Segment { address, length } in self.bucket_iter(runs, offset, dst.len()) {
+            self.memory.read(
+                address.get(),
+                &mut dst[bytes_read as usize..(bytes_read + length.get()) as usize],
+            );
+
+            bytes_read += length.get();
+        }
+    }
+
+    // Returns the runs of buckets of a memory without copying them.
+    fn runs(&self, id: MemoryId) -> &[BucketRun] {
+        match self.memory_runs.get(&id) {
+            Some(runs) => runs.as_slice(),
             None => &[],
-        };
+        }
+    }
+
+    // Initializes a [`BucketIterator`].
+    fn bucket_iter<'a>(
+        &self,
+        runs: &'a [BucketRun],
+        offset: u64,
+        length: usize,
+    ) -> BucketIterator<'a> {
+        // Skip the runs that end before the offset.
+        let bucket_size_in_bytes = self.bucket_size_in_bytes();
+        let first_run =
+            runs.partition_point(|run| run.end() * bucket_size_in_bytes.get() <= offset);
 
         BucketIterator {
             virtual_segment: Segment {
                 address: Address::from(offset),
                 length: Bytes::from(length as u64),
             },
-            buckets,
-            bucket_size_in_bytes: self.bucket_size_in_bytes(),
+            runs: &runs[first_run..],
+            bucket_size_in_bytes,
         }
     }
 
@@ -426,6 +1310,42 @@ struct Segment {
     length: Bytes,
 }
 
+// A run of buckets that are consecutive both in a memory and in the underlying memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BucketRun {
+    // The index of the run's first bucket among the buckets of the memory.
+    start: u64,
+
+    // The run's first bucket.
+    first_bucket: BucketId,
+
+    // The number of buckets in the run.
+    len: u64,
+}
+
+impl BucketRun {
+    // Returns the index of the bucket after the run among the buckets of the memory.
+    fn end(&self) -> u64 {
+        self.start + self.len
+    }
+}
+
+// Coalesces the buckets of a memory into runs.
+fn bucket_runs(buckets: &[BucketId]) -> Vec<BucketRun> {
+    let mut runs: Vec<BucketRun> = vec![];
+    for (idx, bucket_id) in buckets.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run) if run.first_bucket.0 as u64 + run.len == bucket_id.0 as u64 => run.len += 1,
+            _ => runs.push(BucketRun {
+                start: idx as u64,
+                first_bucket: *bucket_id,
+                len: 1,
+            }),
+        }
+    }
+    runs
+}
+
 // An iterator that maps a segment of virtual memory to segments of real memory.
 //
 // A segment in virtual memory can map to multiple segments of real memory. Here's an example:
@@ -439,19 +1359,23 @@ struct Segment {
 //
 // The [`VirtualMemory`] is internally divided into fixed-size buckets. In the memory's virtual
 // address space, all these buckets are consecutive, but in real memory this may not be the case.
+// The buckets that are consecutive in real memory too are coalesced into runs.
 //
-// A virtual segment would first be split at the bucket boundaries. The example virtual segment
-// above would be split into the following segments:
+// A virtual segment would first be split at the boundaries of the runs. If buckets 1 and 2
+// are consecutive in real memory, but not buckets 0 and 1, the example virtual segment above
+// would be split into the following segments:
 //
 //    (A, end of bucket 0)
-//    (start of bucket 1, end of bucket 1)
-//    (start of bucket 2, B)
+//    (start of bucket 1, B)
 //
 // Each of the segments above can then be translated into the real address space by looking up
-// the underlying buckets' addresses in real memory.
+// the address of the run's first bucket in real memory.
 struct BucketIterator<'a> {
     virtual_segment: Segment,
-    buckets: &'a [BucketId],
+
+    // The runs of buckets, starting with the run containing the virtual segment's address.
+    runs: &'a [BucketRun],
+
     bucket_size_in_bytes: Bytes,
 }
 
@@ -464,32 +1388,22 @@ impl Iterator for BucketIterator<'_> {
         }
 
         // Map the virtual segment's address to a real address.
-        let bucket_idx =
-            (self.virtual_segment.address.get() / self.bucket_size_in_bytes.get()) as usize;
-        let bucket_address = self.bucket_address(
-            *self
-                .buckets
-                .get(bucket_idx)
-                .expect("bucket idx out of bounds"),
-        );
-
-        let real_address = bucket_address
-            + Bytes::from(self.virtual_segment.address.get() % self.bucket_size_in_bytes.get());
-
-        // Compute how many bytes are in this real segment.
-        let bytes_in_segment = {
-            let next_bucket_address = bucket_address + self.bucket_size_in_bytes;
-
-            // Write up to either the end of the bucket, or the end of the segment.
-            min(
-                Bytes::from(next_bucket_address.get() - real_address.get()),
-                self.virtual_segment.length,
-            )
-        };
+        let run = self.runs.first().expect("bucket idx out of bounds");
+        let offset_in_run =
+            self.virtual_segment.address.get() - run.start * self.bucket_size_in_bytes.get();
+        let real_address = self.bucket_address(run.first_bucket) + Bytes::from(offset_in_run);
+
+        // Compute how many bytes are in this real segment, which goes up to either the end of
+        // the run, or the end of the segment.
+        let bytes_in_segment = min(
+            Bytes::from(run.len * self.bucket_size_in_bytes.get() - offset_in_run),
+            self.virtual_segment.length,
+        );
 
         // Update the virtual segment to exclude the portion we're about to return.
         self.virtual_segment.length -= bytes_in_segment;
         self.virtual_segment.address += bytes_in_segment;
+        self.runs = &self.runs[1..];
 
         Some(Segment {
             address: real_address,
@@ -501,36 +1415,66 @@ impl Iterator for BucketIterator<'_> {
 impl<'a> BucketIterator<'a> {
     // Returns the address of a given bucket.
     fn bucket_address(&self, id: BucketId) -> Address {
-        Address::from(BUCKETS_OFFSET_IN_BYTES) + self.bucket_size_in_bytes * Bytes::from(id.0)
+        bucket_address(id, self.bucket_size_in_bytes)
     }
 }
 
 #[derive(Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Debug)]
-pub struct MemoryId(u8);
+pub struct MemoryId(u16);
 
 impl MemoryId {
-    pub const fn new(id: u8) -> Self {
-        // Any ID can be used except the special value that's used internally to
-        // mark a bucket as unallocated.
-        assert!(id != UNALLOCATED_BUCKET_MARKER);
+    pub const fn new(id: u16) -> Self {
+        // Any ID can be used except the special values that are used internally for the
+        // metadata memory and to mark a bucket as unallocated.
+        assert!(id < MAX_NUM_MEMORIES_V3);
 
         Self(id)
     }
 }
 
 // Referring to a bucket.
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct BucketId(u16);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct BucketId(u32);
+
+// Returns the address of a given bucket in the underlying memory.
+fn bucket_address(id: BucketId, bucket_size_in_bytes: Bytes) -> Address {
+    Address::from(BUCKETS_OFFSET_IN_BYTES) + bucket_size_in_bytes * Bytes::from(id.0)
+}
+
+// Orders the buckets of each memory by their position among the buckets of the memory.
+fn sort_by_position(
+    memory_buckets: BTreeMap<MemoryId, Vec<(u32, BucketId)>>,
+) -> BTreeMap<MemoryId, Vec<BucketId>> {
+    memory_buckets
+        .into_iter()
+        .map(|(id, mut buckets)| {
+            buckets.sort();
+            (
+                id,
+                buckets
+                    .into_iter()
+                    .map(|(_, bucket_id)| bucket_id)
+                    .collect(),
+            )
+        })
+        .collect()
+}
 
 fn bucket_allocations_address(id: BucketId) -> Address {
     Address::from(0) + Header::size() + Bytes::from(id.0)
 }
 
+// Returns the address of the id of the given metadata bucket in the V3 layout.
+fn metadata_bucket_address(idx: u64) -> Address {
+    Address::from(0) + HeaderV3::size() + Bytes::from(idx * 4)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use maplit::btreemap;
+    use maplit::{btreemap, btreeset};
     use proptest::prelude::*;
+    use std::cell::Cell;
 
     const MAX_MEMORY_IN_PAGES: u64 = MAX_NUM_BUCKETS * BUCKET_SIZE_IN_PAGES;
 
@@ -538,6 +1482,16 @@ mod test {
         Rc::new(RefCell::new(Vec::new()))
     }
 
+    // Creates a memory manager in the V3 layout with the given bucket size.
+    fn init_v3_with_bucket_size<M: Memory>(
+        memory: M,
+        bucket_size_in_pages: u16,
+    ) -> MemoryManager<M> {
+        let mem_mgr = MemoryManager::init_with_bucket_size(memory, bucket_size_in_pages);
+        mem_mgr.upgrade_layout();
+        mem_mgr
+    }
+
     #[test]
     fn can_get_memory() {
         let mem_mgr = MemoryManager::init(make_memory());
@@ -737,6 +1691,112 @@ mod test {
         assert_eq!(bytes, vec![4, 5, 6]);
     }
 
+    // A memory that counts the reads issued to it.
+    #[derive(Clone, Default)]
+    struct CountingMemory {
+        memory: Rc<RefCell<Vec<u8>>>,
+        reads: Rc<Cell<usize>>,
+    }
+
+    impl Memory for CountingMemory {
+        fn size(&self) -> u64 {
+            self.memory.size()
+        }
+
+        fn grow(&self, pages: u64) -> i64 {
+            self.memory.grow(pages)
+        }
+
+        fn read(&self, offset: u64, dst: &mut [u8]) {
+            self.reads.set(self.reads.get() + 1);
+            self.memory.read(offset, dst)
+        }
+
+        fn write(&self, offset: u64, src: &[u8]) {
+            self.memory.write(offset, src)
+        }
+    }
+
+    #[test]
+    fn reads_across_adjacent_buckets_are_coalesced() {
+        let mem = CountingMemory::default();
+        let mem_mgr = MemoryManager::init(mem.clone());
+        let memory_0 = mem_mgr.get(MemoryId(0));
+        let memory_1 = mem_mgr.get(MemoryId(1));
+
+        assert_eq!(memory_0.grow(BUCKET_SIZE_IN_PAGES * 2), 0);
+        assert_eq!(memory_1.grow(1), 0);
+        assert_eq!(
+            memory_0.grow(BUCKET_SIZE_IN_PAGES),
+            BUCKET_SIZE_IN_PAGES as i64 * 2
+        );
+
+        // Bucket 2 belongs to memory 1.
+        assert_eq!(
+            *mem_mgr.inner.borrow().memory_runs(MemoryId(0)),
+            vec![
+                BucketRun {
+                    start: 0,
+                    first_bucket: BucketId(0),
+                    len: 2
+                },
+                BucketRun {
+                    start: 2,
+                    first_bucket: BucketId(3),
+                    len: 1
+                },
+            ]
+        );
+
+        let bucket_size_in_bytes = mem_mgr.inner.borrow().bucket_size_in_bytes().get() as usize;
+        let mut bytes = vec![0; bucket_size_in_bytes * 2];
+        mem.reads.set(0);
+        memory_0.read(0, &mut bytes);
+        assert_eq!(mem.reads.get(), 1);
+
+        let mut bytes = vec![0; bucket_size_in_bytes * 3];
+        mem.reads.set(0);
+        memory_0.read(0, &mut bytes);
+        assert_eq!(mem.reads.get(), 2);
+
+        let mut bytes = vec![0; 2];
+        mem.reads.set(0);
+        memory_0.read(bucket_size_in_bytes as u64 * 2 - 1, &mut bytes);
+        assert_eq!(mem.reads.get(), 2);
+    }
+
+    #[test]
+    fn cached_runs_are_updated_when_buckets_change() {
+        let mem_mgr = MemoryManager::init(make_memory());
+        let memory_0 = mem_mgr.get(MemoryId(0));
+        let memory_0_copy = mem_mgr.get(MemoryId(0));
+        let memory_1 = mem_mgr.get(MemoryId(1));
+        let bucket_size_in_bytes = mem_mgr.inner.borrow().bucket_size_in_bytes().get();
+
+        assert_eq!(memory_0.grow(BUCKET_SIZE_IN_PAGES), 0);
+        memory_0.write(0, &[1, 2, 3]);
+
+        // Growing the memory through another handle is visible to the first one.
+        assert_eq!(
+            memory_0_copy.grow(BUCKET_SIZE_IN_PAGES),
+            BUCKET_SIZE_IN_PAGES as i64
+        );
+        memory_0.write(bucket_size_in_bytes, &[4, 5, 6]);
+        let mut bytes = vec![0; 3];
+        memory_0_copy.read(bucket_size_in_bytes, &mut bytes);
+        assert_eq!(bytes, vec![4, 5, 6]);
+
+        // The freed buckets are reused by other memories.
+        mem_mgr.free(MemoryId(0));
+        assert_eq!(memory_1.grow(BUCKET_SIZE_IN_PAGES), 0);
+        memory_1.write(0, &[7, 8, 9]);
+        assert_eq!(memory_0.grow(1), 0);
+        memory_0.read(0, &mut bytes);
+        assert_eq!(bytes, vec![0, 0, 0]);
+        memory_1.read(0, &mut bytes);
+        assert_eq!(bytes, vec![7, 8, 9]);
+    }
+
     #[test]
     #[should_panic]
     fn reading_out_of_bounds_should_panic() {
@@ -853,4 +1913,508 @@ mod test {
         memory_1.read(0, &mut buf);
         assert_eq!(buf, vec![2; 1000]);
     }
+
+    #[test]
+    fn freed_buckets_are_reused() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init(mem.clone());
+        let memory_0 = mem_mgr.get(MemoryId(0));
+        let memory_1 = mem_mgr.get(MemoryId(1));
+
+        assert_eq!(memory_0.grow(BUCKET_SIZE_IN_PAGES * 3), 0);
+        assert_eq!(memory_1.grow(1), 0);
+        memory_0.write(BUCKET_SIZE_IN_PAGES * WASM_PAGE_SIZE, &[1, 2, 3]);
+        assert_eq!(mem.size(), 1 + 4 * BUCKET_SIZE_IN_PAGES);
+
+        // The first bucket to be freed stores the positions of the buckets.
+        mem_mgr.free(MemoryId(0));
+        assert_eq!(memory_0.size(), 0);
+        assert_eq!(mem_mgr.inner.borrow().order_bucket, Some(BucketId(0)));
+        assert_eq!(
+            mem_mgr.inner.borrow().free_buckets,
+            btreeset! { BucketId(1), BucketId(2) }
+        );
+
+        // Growing a memory reuses the free buckets before allocating new ones.
+        let memory_2 = mem_mgr.get(MemoryId(2));
+        assert_eq!(memory_2.grow(BUCKET_SIZE_IN_PAGES * 3), 0);
+        assert_eq!(
+            mem_mgr.inner.borrow().memory_buckets,
+            btreemap! {
+                MemoryId(1) => vec![BucketId(3)],
+                MemoryId(2) => vec![BucketId(1), BucketId(2), BucketId(4)],
+            }
+        );
+        assert!(mem_mgr.inner.borrow().free_buckets.is_empty());
+        assert_eq!(mem.size(), 1 + 5 * BUCKET_SIZE_IN_PAGES);
+
+        // The reused buckets don't keep the contents of the freed memory.
+        let mut bytes = vec![0; 3];
+        memory_2.read(0, &mut bytes);
+        assert_eq!(bytes, vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn free_buckets_are_reused_in_any_order() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init(mem.clone());
+        let memory_0 = mem_mgr.get(MemoryId(0));
+        let memory_1 = mem_mgr.get(MemoryId(1));
+
+        assert_eq!(memory_0.grow(BUCKET_SIZE_IN_PAGES * 10), 0);
+        assert_eq!(memory_1.grow(BUCKET_SIZE_IN_PAGES), 0);
+        mem_mgr.free(MemoryId(0));
+        assert_eq!(mem.size(), 1 + 11 * BUCKET_SIZE_IN_PAGES);
+
+        // The free buckets come before the last bucket of memory 1, and are reused anyway.
+        assert_eq!(
+            memory_1.grow(BUCKET_SIZE_IN_PAGES * 5),
+            BUCKET_SIZE_IN_PAGES as i64
+        );
+        assert_eq!(mem.size(), 1 + 11 * BUCKET_SIZE_IN_PAGES);
+        let expected_buckets = vec![
+            BucketId(10),
+            BucketId(1),
+            BucketId(2),
+            BucketId(3),
+            BucketId(4),
+            BucketId(5),
+        ];
+        assert_eq!(
+            mem_mgr.inner.borrow().memory_buckets[&MemoryId(1)],
+            expected_buckets
+        );
+        for i in 0..6 {
+            memory_1.write(i * BUCKET_SIZE_IN_PAGES * WASM_PAGE_SIZE, &[i as u8; 3]);
+        }
+
+        // The order of the buckets is restored when the memory manager is loaded, in the V2
+        // layout as well as in the V3 layout.
+        for _ in 0..2 {
+            let mem_mgr = MemoryManager::init(mem.clone());
+            assert_eq!(
+                mem_mgr.inner.borrow().memory_buckets[&MemoryId(1)],
+                expected_buckets
+            );
+            let memory_1 = mem_mgr.get(MemoryId(1));
+            for i in 0..6 {
+                let mut bytes = vec![0; 3];
+                memory_1.read(i * BUCKET_SIZE_IN_PAGES * WASM_PAGE_SIZE, &mut bytes);
+                assert_eq!(bytes, vec![i as u8; 3]);
+            }
+            mem_mgr.upgrade_layout();
+        }
+    }
+
+    #[test]
+    fn free_pool_is_restored_from_memory() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init(mem.clone());
+        let memories: Vec<_> = (0..4).map(|id| mem_mgr.get(MemoryId(id))).collect();
+        for (i, memory) in memories.iter().enumerate() {
+            assert_eq!(memory.grow(1), 0);
+            memory.write(0, &[i as u8; 3]);
+        }
+        mem_mgr.free(MemoryId(1));
+        mem_mgr.free(MemoryId(3));
+
+        let mem_mgr = MemoryManager::init(mem);
+        assert_eq!(mem_mgr.inner.borrow().allocated_buckets, 4);
+        assert_eq!(mem_mgr.inner.borrow().order_bucket, Some(BucketId(1)));
+        assert_eq!(
+            mem_mgr.inner.borrow().free_buckets,
+            btreeset! { BucketId(3) }
+        );
+        assert_eq!(mem_mgr.get(MemoryId(1)).size(), 0);
+        assert_eq!(mem_mgr.get(MemoryId(3)).size(), 0);
+
+        let mut bytes = vec![0; 3];
+        mem_mgr.get(MemoryId(2)).read(0, &mut bytes);
+        assert_eq!(bytes, vec![2; 3]);
+    }
+
+    #[test]
+    fn truncate_releases_trailing_buckets() {
+        let mem_mgr = MemoryManager::init(make_memory());
+        let memory_0 = mem_mgr.get(MemoryId(0));
+        let memory_1 = mem_mgr.get(MemoryId(1));
+
+        assert_eq!(memory_0.grow(BUCKET_SIZE_IN_PAGES * 3), 0);
+        memory_0.write(0, &[1, 2, 3]);
+        memory_0.write(WASM_PAGE_SIZE, &[4, 5, 6]);
+
+        // Truncating to a larger size does nothing.
+        assert_eq!(
+            memory_0.truncate(BUCKET_SIZE_IN_PAGES * 4),
+            BUCKET_SIZE_IN_PAGES * 3
+        );
+
+        assert_eq!(memory_0.truncate(1), BUCKET_SIZE_IN_PAGES * 3);
+        assert_eq!(memory_0.size(), 1);
+        assert_eq!(
+            mem_mgr.inner.borrow().memory_buckets,
+            btreemap! {
+                MemoryId(0) => vec![BucketId(0)],
+            }
+        );
+        assert_eq!(mem_mgr.inner.borrow().order_bucket, Some(BucketId(1)));
+        assert_eq!(
+            mem_mgr.inner.borrow().free_buckets,
+            btreeset! { BucketId(2) }
+        );
+
+        let mut bytes = vec![0; 3];
+        memory_0.read(0, &mut bytes);
+        assert_eq!(bytes, vec![1, 2, 3]);
+
+        // The discarded pages of the remaining bucket contain zeroes when grown again.
+        assert_eq!(memory_0.grow(1), 1);
+        memory_0.read(WASM_PAGE_SIZE, &mut bytes);
+        assert_eq!(bytes, vec![0, 0, 0]);
+
+        // The released buckets are reused.
+        assert_eq!(memory_1.grow(BUCKET_SIZE_IN_PAGES * 2), 0);
+        assert!(mem_mgr.inner.borrow().free_buckets.is_empty());
+
+        // Truncating to zero releases all the buckets.
+        assert_eq!(memory_0.truncate(0), 2);
+        assert_eq!(
+            mem_mgr.inner.borrow().memory_buckets,
+            btreemap! {
+                MemoryId(1) => vec![BucketId(2), BucketId(3)],
+            }
+        );
+    }
+
+    #[test]
+    fn can_use_memory_ids_beyond_255() {
+        let mem = make_memory();
+        let mem_mgr = init_v3_with_bucket_size(mem.clone(), 1);
+        let ids = [0, 254, 255, 1000, MAX_NUM_MEMORIES_V3 - 1];
+        for (i, id) in ids.iter().enumerate() {
+            let memory = mem_mgr.get(MemoryId::new(*id));
+            assert_eq!(memory.grow(i as u64 + 1), 0);
+            memory.write(0, &id.to_le_bytes());
+        }
+
+        let mem_mgr = MemoryManager::init(mem);
+        for (i, id) in ids.iter().enumerate() {
+            let memory = mem_mgr.get(MemoryId::new(*id));
+            assert_eq!(memory.size(), i as u64 + 1);
+            let mut bytes = [0; 2];
+            memory.read(0, &mut bytes);
+            assert_eq!(u16::from_le_bytes(bytes), *id);
+        }
+    }
+
+    #[test]
+    fn metadata_memory_grows_with_the_number_of_buckets() {
+        // With buckets of one page, each metadata bucket holds the allocations of 10922 buckets,
+        // after the twelve buckets holding the memory sizes and sub-memories.
+        let mem = make_memory();
+        let mem_mgr = init_v3_with_bucket_size(mem.clone(), 1);
+        let memory_0 = mem_mgr.get(MemoryId(0));
+        let memory_1 = mem_mgr.get(MemoryId(1));
+        assert_eq!(memory_0.grow(40_000), 0);
+        assert_eq!(memory_1.grow(1), 0);
+        memory_0.write(39_999 * WASM_PAGE_SIZE, &[1, 2, 3]);
+        memory_1.write(0, &[4, 5, 6]);
+
+        assert_eq!(mem_mgr.inner.borrow().allocated_buckets, 40_017);
+        assert_eq!(
+            mem_mgr
+                .inner
+                .borrow()
+                .memory_buckets_len(METADATA_MEMORY_ID),
+            16
+        );
+
+        // The limit of the V2 layout doesn't apply.
+        assert!(mem_mgr.inner.borrow().allocated_buckets as u64 > MAX_NUM_BUCKETS);
+
+        let mem_mgr = MemoryManager::init(mem);
+        let mut bytes = vec![0; 3];
+        mem_mgr
+            .get(MemoryId(0))
+            .read(39_999 * WASM_PAGE_SIZE, &mut bytes);
+        assert_eq!(bytes, vec![1, 2, 3]);
+        mem_mgr.get(MemoryId(1)).read(0, &mut bytes);
+        assert_eq!(bytes, vec![4, 5, 6]);
+        assert_eq!(mem_mgr.get(MemoryId(0)).size(), 40_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "MemoryId(255) requires the V3 layout")]
+    fn v2_layout_is_limited_to_255_memories() {
+        MemoryManager::init(make_memory()).get(MemoryId::new(255));
+    }
+
+    #[test]
+    #[should_panic(expected = "MemoryId(300) requires the V3 layout")]
+    fn v2_layout_cant_free_memories_beyond_255() {
+        MemoryManager::init(make_memory()).free(MemoryId::new(300));
+    }
+
+    #[test]
+    fn can_upgrade_to_v3_layout() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init(mem.clone());
+        let memories: Vec<_> = (0..4).map(|id| mem_mgr.get(MemoryId(id))).collect();
+        for (i, memory) in memories.iter().enumerate() {
+            assert_eq!(memory.grow(i as u64 + 1), 0);
+            memory.write(0, &[i as u8; 3]);
+        }
+        mem_mgr.free(MemoryId(1));
+        let memory_buckets = mem_mgr.inner.borrow().memory_buckets.clone();
+
+        mem_mgr.upgrade_layout();
+
+        // The buckets of the memories stay where they are, and the metadata memory takes the
+        // free bucket.
+        let mut expected_buckets = memory_buckets;
+        expected_buckets.insert(METADATA_MEMORY_ID, vec![BucketId(1)]);
+        assert_eq!(mem_mgr.inner.borrow().memory_buckets, expected_buckets);
+
+        let mem_mgr = MemoryManager::init(mem.clone());
+        assert_eq!(mem_mgr.inner.borrow().version, LAYOUT_VERSION_3);
+        assert_eq!(mem_mgr.inner.borrow().memory_buckets, expected_buckets);
+        for (i, id) in [0, 2, 3].into_iter().enumerate() {
+            let memory = mem_mgr.get(MemoryId(id));
+            assert_eq!(memory.size(), id as u64 + 1);
+            let mut bytes = vec![0; 3];
+            memory.read(0, &mut bytes);
+            assert_eq!(bytes, vec![id as u8; 3], "memory {i}");
+        }
+        assert_eq!(mem_mgr.get(MemoryId(1)).size(), 0);
+
+        // Memory ids beyond the limit of the V2 layout can now be used.
+        let memory_1000 = mem_mgr.get(MemoryId(1000));
+        assert_eq!(memory_1000.grow(1), 0);
+        let mem_mgr = MemoryManager::init(mem);
+        assert_eq!(mem_mgr.get(MemoryId(1000)).size(), 1);
+    }
+
+    #[test]
+    fn upgrading_an_empty_memory_manager() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init(mem.clone());
+        mem_mgr.upgrade_layout();
+
+        let mem_mgr = MemoryManager::init(mem);
+        assert_eq!(mem_mgr.inner.borrow().version, LAYOUT_VERSION_3);
+        assert_eq!(mem_mgr.get(MemoryId(300)).grow(1), 0);
+        assert_eq!(mem_mgr.inner.borrow().allocated_buckets, 2);
+    }
+
+    #[test]
+    fn init_v3_upgrades_existing_memory_managers() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init(mem.clone());
+        assert_eq!(mem_mgr.get(MemoryId(0)).grow(1), 0);
+
+        let mem_mgr = MemoryManager::init_v3(mem.clone());
+        assert_eq!(mem_mgr.inner.borrow().version, LAYOUT_VERSION_3);
+        assert_eq!(mem_mgr.get(MemoryId(0)).size(), 1);
+
+        let mem_mgr = MemoryManager::init(mem);
+        assert_eq!(mem_mgr.inner.borrow().version, LAYOUT_VERSION_3);
+        assert_eq!(mem_mgr.get(MemoryId(0)).size(), 1);
+    }
+
+    #[test]
+    fn sub_managers_have_their_own_namespaces() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init_v3(mem.clone());
+        let tenant_0 = mem_mgr.sub_manager(MemoryId(0));
+        let tenant_1 = mem_mgr.sub_manager(MemoryId(1));
+
+        for (i, memory) in [
+            mem_mgr.get(MemoryId(0)),
+            tenant_0.get(MemoryId(0)),
+            tenant_1.get(MemoryId(0)),
+            tenant_1.get(MemoryId(1)),
+        ]
+        .iter()
+        .enumerate()
+        {
+            assert_eq!(memory.grow(1), 0);
+            memory.write(0, &[i as u8; 3]);
+        }
+
+        // The memories of the sub-managers are assigned the largest ids.
+        assert_eq!(
+            mem_mgr.inner.borrow().sub_memories,
+            btreemap! {
+                (MemoryId(0), MemoryId(0)) => MemoryId(MAX_NUM_MEMORIES_V3 - 1),
+                (MemoryId(1), MemoryId(0)) => MemoryId(MAX_NUM_MEMORIES_V3 - 2),
+                (MemoryId(1), MemoryId(1)) => MemoryId(MAX_NUM_MEMORIES_V3 - 3),
+            }
+        );
+
+        let mem_mgr = MemoryManager::init(mem);
+        let tenant_0 = mem_mgr.sub_manager(MemoryId(0));
+        let tenant_1 = mem_mgr.sub_manager(MemoryId(1));
+        for (i, memory) in [
+            mem_mgr.get(MemoryId(0)),
+            tenant_0.get(MemoryId(0)),
+            tenant_1.get(MemoryId(0)),
+            tenant_1.get(MemoryId(1)),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let mut bytes = vec![0; 3];
+            memory.read(0, &mut bytes);
+            assert_eq!(bytes, vec![i as u8; 3]);
+        }
+        assert_eq!(tenant_0.get(MemoryId(1)).size(), 0);
+    }
+
+    #[test]
+    fn nested_sub_managers_are_flattened() {
+        let mem_mgr = init_v3_with_bucket_size(make_memory(), 1);
+        let nested = mem_mgr
+            .sub_manager(MemoryId(3))
+            .sub_manager(MemoryId(4))
+            .sub_manager(MemoryId(5));
+        let memory = nested.get(MemoryId(6));
+        assert_eq!(memory.grow(3), 0);
+        memory.write(WASM_PAGE_SIZE - 1, &[1, 2, 3]);
+
+        // The memory is stored directly in buckets of the top-level memory manager, without
+        // intermediate memories.
+        let inner = mem_mgr.inner.borrow();
+        assert_eq!(inner.memory_buckets_len(memory.id), 3);
+        assert_eq!(inner.sub_memories.len(), 3);
+        for id in inner.sub_memory_ids.iter() {
+            assert_eq!(inner.memory_buckets.contains_key(id), *id == memory.id);
+        }
+        assert!(!inner.memory_buckets.contains_key(&MemoryId(3)));
+
+        let mut bytes = vec![0; 3];
+        memory.read(WASM_PAGE_SIZE - 1, &mut bytes);
+        assert_eq!(bytes, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn sub_managers_skip_memories_in_use() {
+        let mem_mgr = MemoryManager::init_v3(make_memory());
+        let largest_id = MemoryId(MAX_NUM_MEMORIES_V3 - 1);
+        mem_mgr.get(largest_id);
+
+        let tenant = mem_mgr.sub_manager(MemoryId(0));
+        assert_eq!(
+            tenant.get(MemoryId(0)).id,
+            MemoryId(MAX_NUM_MEMORIES_V3 - 2)
+        );
+    }
+
+    #[test]
+    fn memories_used_directly_are_not_given_to_sub_managers_after_reload() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init_v3(mem.clone());
+        let ids = [
+            MemoryId(0),
+            MemoryId(MAX_NUM_MEMORIES_V3 - 2),
+            MemoryId(MAX_NUM_MEMORIES_V3 - 1),
+        ];
+        for id in ids {
+            assert_eq!(mem_mgr.get(id).grow(1), 0);
+        }
+
+        // The sub-manager is used first after the reload, when none of the memories used
+        // before has been used again.
+        let mem_mgr = MemoryManager::init(mem);
+        let tenant = mem_mgr.sub_manager(MemoryId(1));
+        assert_eq!(
+            tenant.get(MemoryId(0)).id,
+            MemoryId(MAX_NUM_MEMORIES_V3 - 3)
+        );
+        for id in ids {
+            assert_eq!(mem_mgr.get(id).size(), 1);
+        }
+    }
+
+    #[test]
+    fn getting_a_memory_does_not_write_to_the_underlying_memory() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init_v3(mem.clone());
+        let contents = mem.borrow().clone();
+        let memory = mem_mgr.get(MemoryId(5));
+        assert_eq!(*mem.borrow(), contents);
+
+        // The memory is recorded as used once it grows.
+        assert_eq!(memory.grow(1), 0);
+        let mem_mgr = MemoryManager::init(mem);
+        assert!(mem_mgr.inner.borrow().used_ids.contains(&MemoryId(5)));
+    }
+
+    #[test]
+    #[should_panic(expected = "is used by a sub-manager")]
+    fn memories_of_sub_managers_cant_be_used_directly() {
+        let mem_mgr = MemoryManager::init_v3(make_memory());
+        let tenant = mem_mgr.sub_manager(MemoryId(0));
+        let id = tenant.get(MemoryId(0)).id;
+        mem_mgr.get(id);
+    }
+
+    #[test]
+    fn can_free_memories_of_sub_managers() {
+        let mem_mgr = MemoryManager::init_v3(make_memory());
+        let tenant = mem_mgr.sub_manager(MemoryId(0));
+        let memory = tenant.get(MemoryId(0));
+        assert_eq!(memory.grow(1), 0);
+
+        tenant.free(MemoryId(0));
+        assert_eq!(memory.size(), 0);
+        // Bucket 0 was allocated to the metadata memory to store the sub-memory.
+        assert_eq!(
+            mem_mgr.inner.borrow().free_buckets,
+            btreeset! { BucketId(1) }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "sub-managers require the V3 layout")]
+    fn sub_managers_require_v3_layout() {
+        MemoryManager::init(make_memory()).sub_manager(MemoryId(0));
+    }
+
+    #[test]
+    fn can_load_v1_layout() {
+        let mem = make_memory();
+        let mem_mgr = MemoryManager::init(mem.clone());
+        let memory_0 = mem_mgr.get(MemoryId(0));
+        assert_eq!(memory_0.grow(1), 0);
+        memory_0.write(0, &[1, 2, 3]);
+
+        // New memory managers use the V1 layout.
+        let header: Header = read_struct(Address::from(0), &mem);
+        assert_eq!(header.version, LAYOUT_VERSION_1);
+
+        let mem_mgr = MemoryManager::init(mem.clone());
+        let memory_0 = mem_mgr.get(MemoryId(0));
+        let mut bytes = vec![0; 3];
+        memory_0.read(0, &mut bytes);
+        assert_eq!(bytes, vec![1, 2, 3]);
+
+        // The V1 layout is kept as long as no bucket is freed.
+        assert_eq!(memory_0.grow(1), 1);
+        assert_eq!(memory_0.truncate(1), 2);
+        let header: Header = read_struct(Address::from(0), &mem);
+        assert_eq!(header.version, LAYOUT_VERSION_1);
+
+        // Freeing a bucket switches to the V2 layout.
+        assert_eq!(memory_0.grow(BUCKET_SIZE_IN_PAGES), 1);
+        assert_eq!(memory_0.truncate(1), BUCKET_SIZE_IN_PAGES + 1);
+        // The freed bucket stores the positions of the buckets.
+        let header: Header = read_struct(Address::from(0), &mem);
+        assert_eq!(header.version, LAYOUT_VERSION_2);
+        assert_eq!({ header.order_bucket }, 1);
+
+        let mem_mgr = MemoryManager::init(mem);
+        assert_eq!(mem_mgr.inner.borrow().version, LAYOUT_VERSION_2);
+        assert_eq!(mem_mgr.inner.borrow().order_bucket, Some(BucketId(1)));
+        assert!(mem_mgr.inner.borrow().free_buckets.is_empty());
+    }
 }
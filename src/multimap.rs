@@ -0,0 +1,142 @@
+//! A map with many values per key, based on a [`BTreeMap`].
+//!
+//! Each entry of the multimap is stored in the underlying map under the composite key
+//! `(K1, K2)`. Since tuples are ordered by their first element first, all the entries
+//! of a given `K1` are contiguous in the map, and are found by seeking to the prefix.
+use crate::btreemap::BTreeMap;
+use crate::{Memory, Storable};
+use std::ops::Bound;
+
+#[cfg(test)]
+mod tests;
+
+/// A map from pairs of keys to values in stable memory, where all the entries that
+/// share the first key can be iterated over or removed at once.
+///
+/// ```
+/// use ic_stable_structures::{DefaultMemoryImpl, StableMultimap};
+///
+/// // Token ids of each owner, along with the token's name.
+/// let mut tokens: StableMultimap<u64, u64, String, _> =
+///     StableMultimap::init(DefaultMemoryImpl::default());
+/// tokens.insert(1, 10, String::from("a"));
+/// tokens.insert(1, 11, String::from("b"));
+/// tokens.insert(2, 20, String::from("c"));
+///
+/// assert_eq!(tokens.get(&1, &11), Some(String::from("b")));
+/// assert_eq!(
+///     tokens.range(&1).collect::<Vec<_>>(),
+///     vec![(10, String::from("a")), (11, String::from("b"))]
+/// );
+///
+/// assert_eq!(tokens.remove_partial(&1), 2);
+/// assert_eq!(tokens.len(), 1);
+/// ```
+pub struct Multimap<K1, K2, V, M>
+where
+    K1: Storable + Ord + Clone,
+    K2: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    map: BTreeMap<(K1, K2), V, M>,
+}
+
+impl<K1, K2, V, M> Multimap<K1, K2, V, M>
+where
+    K1: Storable + Ord + Clone,
+    K2: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Initializes a `Multimap`.
+    ///
+    /// If the memory provided already contains a `Multimap`, then that
+    /// multimap is loaded. Otherwise, a new `Multimap` instance is created.
+    pub fn init(memory: M) -> Self {
+        Self {
+            map: BTreeMap::init(memory),
+        }
+    }
+
+    /// Creates a new instance of a `Multimap`, overwriting any data structures
+    /// the memory might have contained.
+    pub fn new(memory: M) -> Self {
+        Self {
+            map: BTreeMap::new(memory),
+        }
+    }
+
+    /// Loads the multimap from memory.
+    pub fn load(memory: M) -> Self {
+        Self {
+            map: BTreeMap::load(memory),
+        }
+    }
+
+    /// Inserts a value for the given pair of keys.
+    ///
+    /// The previous value of the pair of keys, if present, is returned.
+    pub fn insert(&mut self, k1: K1, k2: K2, value: V) -> Option<V> {
+        self.map.insert((k1, k2), value)
+    }
+
+    /// Returns the value for the given pair of keys, if present.
+    pub fn get(&self, k1: &K1, k2: &K2) -> Option<V> {
+        self.map.get(&(k1.clone(), k2.clone()))
+    }
+
+    /// Returns `true` if the multimap contains a value for the given pair of keys.
+    pub fn contains_key(&self, k1: &K1, k2: &K2) -> bool {
+        self.map.contains_key(&(k1.clone(), k2.clone()))
+    }
+
+    /// Removes the value for the given pair of keys, returning it if present.
+    pub fn remove(&mut self, k1: &K1, k2: &K2) -> Option<V> {
+        self.map.remove(&(k1.clone(), k2.clone()))
+    }
+
+    /// Removes all the entries with the given first key.
+    ///
+    /// Returns the number of removed entries.
+    pub fn remove_partial(&mut self, k1: &K1) -> u64 {
+        let mut entries = self.map.prefix_iter(k1);
+        let first = match entries.next() {
+            Some((key, _)) => key,
+            None => return 0,
+        };
+        let last = match entries.next_back() {
+            Some((key, _)) => key,
+            None => first.clone(),
+        };
+
+        self.map
+            .remove_range((Bound::Included(first), Bound::Included(last)))
+    }
+
+    /// Returns an iterator over the second keys and values of the entries with the
+    /// given first key, sorted by the second key.
+    pub fn range(&self, k1: &K1) -> impl DoubleEndedIterator<Item = (K2, V)> + '_ {
+        self.map.prefix_iter(k1).map(|((_, k2), value)| (k2, value))
+    }
+
+    /// Returns an iterator over all the entries of the multimap, sorted by keys.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (K1, K2, V)> + '_ {
+        self.map.iter().map(|((k1, k2), value)| (k1, k2, value))
+    }
+
+    /// Returns `true` if the multimap contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of entries in the multimap.
+    pub fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    /// Returns the underlying memory.
+    pub fn into_memory(self) -> M {
+        self.map.into_memory()
+    }
+}
@@ -0,0 +1,125 @@
+use super::*;
+use proptest::collection::vec as pvec;
+use proptest::prelude::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as StdBTreeMap;
+use std::rc::Rc;
+
+fn make_memory() -> Rc<RefCell<Vec<u8>>> {
+    Rc::new(RefCell::new(Vec::new()))
+}
+
+#[derive(Debug, Clone)]
+enum Operation {
+    Insert(u8, u16, u32),
+    Remove(u8, u16),
+    RemovePartial(u8),
+}
+
+fn arb_op() -> impl Strategy<Value = Operation> {
+    prop_oneof![
+        10 => (0..20u8, any::<u16>(), any::<u32>()).prop_map(|(k1, k2, v)| Operation::Insert(k1, k2, v)),
+        3 => (0..20u8, any::<u16>()).prop_map(|(k1, k2)| Operation::Remove(k1, k2)),
+        1 => (0..20u8).prop_map(Operation::RemovePartial),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn model(ops in pvec(arb_op(), 0..3000)) {
+        let mut multimap = Multimap::new(make_memory());
+        let mut std_map = StdBTreeMap::new();
+
+        for op in ops {
+            match op {
+                Operation::Insert(k1, k2, v) => {
+                    prop_assert_eq!(multimap.insert(k1, k2, v), std_map.insert((k1, k2), v));
+                }
+                Operation::Remove(k1, k2) => {
+                    prop_assert_eq!(multimap.remove(&k1, &k2), std_map.remove(&(k1, k2)));
+                }
+                Operation::RemovePartial(k1) => {
+                    let removed: Vec<_> = std_map.range((k1, 0)..=(k1, u16::MAX)).map(|(k, _)| *k).collect();
+                    for key in removed.iter() {
+                        std_map.remove(key);
+                    }
+                    prop_assert_eq!(multimap.remove_partial(&k1), removed.len() as u64);
+                }
+            }
+        }
+
+        prop_assert_eq!(multimap.len(), std_map.len() as u64);
+        for k1 in 0..20u8 {
+            prop_assert_eq!(
+                multimap.range(&k1).collect::<Vec<_>>(),
+                std_map.range((k1, 0)..=(k1, u16::MAX)).map(|((_, k2), v)| (*k2, *v)).collect::<Vec<_>>()
+            );
+        }
+        prop_assert_eq!(
+            multimap.iter().collect::<Vec<_>>(),
+            std_map.into_iter().map(|((k1, k2), v)| (k1, k2, v)).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn get_and_contains_key() {
+    let mut multimap = Multimap::new(make_memory());
+    multimap.insert(1u64, 2u64, 3u64);
+
+    assert_eq!(multimap.get(&1, &2), Some(3));
+    assert!(multimap.contains_key(&1, &2));
+    assert_eq!(multimap.get(&1, &3), None);
+    assert_eq!(multimap.get(&2, &2), None);
+    assert!(!multimap.contains_key(&2, &1));
+
+    assert_eq!(multimap.insert(1, 2, 4), Some(3));
+    assert_eq!(multimap.get(&1, &2), Some(4));
+}
+
+#[test]
+fn remove_partial_keeps_neighbouring_keys() {
+    let mut multimap = Multimap::new(make_memory());
+    for k1 in 0..10u64 {
+        for k2 in 0..100u64 {
+            multimap.insert(k1, k2, k1 * k2);
+        }
+    }
+
+    assert_eq!(multimap.remove_partial(&5), 100);
+    assert_eq!(multimap.remove_partial(&5), 0);
+    assert_eq!(multimap.remove_partial(&100), 0);
+    assert_eq!(multimap.len(), 900);
+    assert_eq!(multimap.range(&5).next(), None);
+    assert_eq!(multimap.range(&4).count(), 100);
+    assert_eq!(multimap.range(&6).next(), Some((0, 0)));
+    assert_eq!(multimap.range(&6).next_back(), Some((99, 6 * 99)));
+
+    // A first key with a single entry.
+    multimap.insert(20, 0, 0);
+    assert_eq!(multimap.remove_partial(&20), 1);
+    assert_eq!(multimap.len(), 900);
+}
+
+#[test]
+fn unbounded_keys() {
+    let mut multimap = Multimap::new(make_memory());
+    for owner in ["alice", "bob", "carol"] {
+        for token in 0..50u64 {
+            multimap.insert(owner.to_string(), format!("token-{token:03}"), token);
+        }
+    }
+
+    let multimap: Multimap<String, String, u64, _> = Multimap::load(multimap.into_memory());
+    assert_eq!(
+        multimap
+            .range(&String::from("bob"))
+            .take(2)
+            .collect::<Vec<_>>(),
+        vec![
+            (String::from("token-000"), 0),
+            (String::from("token-001"), 1)
+        ]
+    );
+    assert_eq!(multimap.range(&String::from("bo")).next(), None);
+}
@@ -3,6 +3,8 @@ use std::cmp::{Ordering, Reverse};
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 
+pub mod ordered;
+
 #[cfg(test)]
 mod tests;
 
@@ -330,95 +332,252 @@ where
     B: Storable,
 {
     fn to_bytes(&self) -> Cow<[u8]> {
-        match Self::BOUND {
-            Bound::Bounded { max_size, .. } => {
-                let mut bytes = vec![0; max_size as usize];
-                let a_bytes = self.0.to_bytes();
-                let b_bytes = self.1.to_bytes();
+        let a_bytes = self.0.to_bytes();
+        let b_bytes = self.1.to_bytes();
 
-                let a_bounds = bounds::<A>();
-                let b_bounds = bounds::<B>();
+        Cow::Owned(encode_tuple(
+            &[&a_bytes, &b_bytes],
+            &[&A::BOUND, &B::BOUND],
+            &Self::BOUND,
+        ))
+    }
 
-                let a_max_size = a_bounds.max_size as usize;
-                let b_max_size = b_bounds.max_size as usize;
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let elements = decode_tuple(&bytes, &[&A::BOUND, &B::BOUND], &Self::BOUND);
 
-                debug_assert!(a_bytes.len() <= a_max_size);
-                debug_assert!(b_bytes.len() <= b_max_size);
+        let a = A::from_bytes(Cow::Borrowed(elements[0]));
+        let b = B::from_bytes(Cow::Borrowed(elements[1]));
 
-                bytes[0..a_bytes.len()].copy_from_slice(a_bytes.borrow());
-                bytes[a_max_size..a_max_size + b_bytes.len()].copy_from_slice(b_bytes.borrow());
+        (a, b)
+    }
 
-                let a_size_len = bytes_to_store_size(&a_bounds) as usize;
-                let b_size_len = bytes_to_store_size(&b_bounds) as usize;
+    const BOUND: Bound = tuple_bound(&[A::BOUND, B::BOUND]);
+}
 
-                let sizes_offset: usize = a_max_size + b_max_size;
+impl<A, B, C> Storable for (A, B, C)
+where
+    A: Storable,
+    B: Storable,
+    C: Storable,
+{
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let a_bytes = self.0.to_bytes();
+        let b_bytes = self.1.to_bytes();
+        let c_bytes = self.2.to_bytes();
 
-                encode_size(
-                    &mut bytes[sizes_offset..sizes_offset + a_size_len],
-                    a_bytes.len(),
-                    &a_bounds,
-                );
-                encode_size(
-                    &mut bytes[sizes_offset + a_size_len..sizes_offset + a_size_len + b_size_len],
-                    b_bytes.len(),
-                    &b_bounds,
-                );
+        Cow::Owned(encode_tuple(
+            &[&a_bytes, &b_bytes, &c_bytes],
+            &[&A::BOUND, &B::BOUND, &C::BOUND],
+            &Self::BOUND,
+        ))
+    }
 
-                Cow::Owned(bytes)
-            }
-            _ => todo!("Serializing tuples with unbounded types is not yet supported."),
-        }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let elements = decode_tuple(&bytes, &[&A::BOUND, &B::BOUND, &C::BOUND], &Self::BOUND);
+
+        let a = A::from_bytes(Cow::Borrowed(elements[0]));
+        let b = B::from_bytes(Cow::Borrowed(elements[1]));
+        let c = C::from_bytes(Cow::Borrowed(elements[2]));
+
+        (a, b, c)
+    }
+
+    const BOUND: Bound = tuple_bound(&[A::BOUND, B::BOUND, C::BOUND]);
+}
+
+impl<A, B, C, D> Storable for (A, B, C, D)
+where
+    A: Storable,
+    B: Storable,
+    C: Storable,
+    D: Storable,
+{
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let a_bytes = self.0.to_bytes();
+        let b_bytes = self.1.to_bytes();
+        let c_bytes = self.2.to_bytes();
+        let d_bytes = self.3.to_bytes();
+
+        Cow::Owned(encode_tuple(
+            &[&a_bytes, &b_bytes, &c_bytes, &d_bytes],
+            &[&A::BOUND, &B::BOUND, &C::BOUND, &D::BOUND],
+            &Self::BOUND,
+        ))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        match Self::BOUND {
-            Bound::Bounded { max_size, .. } => {
-                assert_eq!(bytes.len(), max_size as usize);
-
-                let a_bounds = bounds::<A>();
-                let b_bounds = bounds::<B>();
-                let a_max_size = a_bounds.max_size as usize;
-                let b_max_size = b_bounds.max_size as usize;
-                let sizes_offset = a_max_size + b_max_size;
-
-                let a_size_len = bytes_to_store_size(&a_bounds) as usize;
-                let b_size_len = bytes_to_store_size(&b_bounds) as usize;
-                let a_len = decode_size(&bytes[sizes_offset..sizes_offset + a_size_len], &a_bounds);
-                let b_len = decode_size(
-                    &bytes[sizes_offset + a_size_len..sizes_offset + a_size_len + b_size_len],
-                    &b_bounds,
-                );
+        let elements = decode_tuple(
+            &bytes,
+            &[&A::BOUND, &B::BOUND, &C::BOUND, &D::BOUND],
+            &Self::BOUND,
+        );
+
+        let a = A::from_bytes(Cow::Borrowed(elements[0]));
+        let b = B::from_bytes(Cow::Borrowed(elements[1]));
+        let c = C::from_bytes(Cow::Borrowed(elements[2]));
+        let d = D::from_bytes(Cow::Borrowed(elements[3]));
+
+        (a, b, c, d)
+    }
+
+    const BOUND: Bound = tuple_bound(&[A::BOUND, B::BOUND, C::BOUND, D::BOUND]);
+}
+
+// Tuples are encoded in one of two ways, depending on whether all of their
+// elements are bounded.
+//
+// If all the elements are bounded, each element is padded to its max size,
+// and the sizes of the elements are appended at the end:
+//
+// ```text
+// | element 0 (padded) | ... | element n (padded) | size 0 | ... | size n |
+// ```
+//
+// Otherwise, each element except the last one is prefixed with its size:
+//
+// ```text
+// | size 0 | element 0 | ... | size n - 1 | element n - 1 | element n |
+// ```
+//
+// In both cases, sizes are big-endian and take as many bytes as needed to store
+// the max size of a bounded element (zero bytes if the element is fixed in size).
+// The sizes of unbounded elements take 4 bytes.
+
+/// Returns the bound of a tuple with elements of the given bounds.
+const fn tuple_bound(elements: &[Bound]) -> Bound {
+    let mut max_size = 0;
+    let mut is_fixed_size = true;
+    let mut i = 0;
+    while i < elements.len() {
+        let element_bounds = match bounds_of(&elements[i]) {
+            Some(bounds) => bounds,
+            None => return Bound::Unbounded,
+        };
+        max_size += element_bounds.max_size + bytes_to_store_size(&element_bounds);
+        is_fixed_size &= element_bounds.is_fixed_size;
+        i += 1;
+    }
+
+    Bound::Bounded {
+        max_size,
+        is_fixed_size,
+    }
+}
 
-                let a = A::from_bytes(Cow::Borrowed(&bytes[0..a_len]));
-                let b = B::from_bytes(Cow::Borrowed(&bytes[a_max_size..a_max_size + b_len]));
+// Encodes the serialized elements of a tuple.
+fn encode_tuple(elements: &[&[u8]], bounds: &[&Bound], tuple_bound: &Bound) -> Vec<u8> {
+    debug_assert_eq!(elements.len(), bounds.len());
+
+    match tuple_bound {
+        Bound::Bounded { max_size, .. } => {
+            let mut bytes = vec![0; *max_size as usize];
+
+            // Write the elements, each padded to its max size.
+            let mut offset = 0;
+            for (element, bound) in elements.iter().zip(bounds) {
+                let element_bounds = bounds_of(bound).expect("tuple must be bounded");
+                debug_assert!(element.len() <= element_bounds.max_size as usize);
+                bytes[offset..offset + element.len()].copy_from_slice(element);
+                offset += element_bounds.max_size as usize;
+            }
 
-                (a, b)
+            // Write the sizes of the elements.
+            for (element, bound) in elements.iter().zip(bounds) {
+                let element_bounds = bounds_of(bound).expect("tuple must be bounded");
+                let size_len = bytes_to_store_size(&element_bounds) as usize;
+                encode_size(
+                    &mut bytes[offset..offset + size_len],
+                    element.len(),
+                    &element_bounds,
+                );
+                offset += size_len;
             }
-            _ => todo!("Deserializing tuples with unbounded types is not yet supported."),
+
+            bytes
+        }
+        Bound::Unbounded => {
+            let mut bytes = vec![];
+            let last_idx = elements.len() - 1;
+            for (idx, (element, bound)) in elements.iter().zip(bounds).enumerate() {
+                // The last element takes up the rest of the bytes, so its size isn't stored.
+                if idx != last_idx {
+                    match bounds_of(bound) {
+                        Some(element_bounds) => {
+                            let size_len = bytes_to_store_size(&element_bounds) as usize;
+                            let offset = bytes.len();
+                            bytes.resize(offset + size_len, 0);
+                            encode_size(&mut bytes[offset..], element.len(), &element_bounds);
+                        }
+                        None => bytes.extend_from_slice(&(element.len() as u32).to_be_bytes()),
+                    }
+                }
+                bytes.extend_from_slice(element);
+            }
+
+            bytes
         }
     }
+}
 
-    const BOUND: Bound = {
-        match (A::BOUND, B::BOUND) {
-            (Bound::Bounded { .. }, Bound::Bounded { .. }) => {
-                let a_bounds = bounds::<A>();
-                let b_bounds = bounds::<B>();
+// Decodes a tuple into the serialized bytes of its elements.
+fn decode_tuple<'a>(bytes: &'a [u8], bounds: &[&Bound], tuple_bound: &Bound) -> Vec<&'a [u8]> {
+    match tuple_bound {
+        Bound::Bounded { max_size, .. } => {
+            assert_eq!(bytes.len(), *max_size as usize);
 
-                let max_size = a_bounds.max_size
-                    + b_bounds.max_size
-                    + bytes_to_store_size(&a_bounds)
-                    + bytes_to_store_size(&b_bounds);
+            // The sizes are stored after all the padded elements.
+            let mut sizes_offset = 0;
+            for bound in bounds {
+                sizes_offset += bounds_of(bound).expect("tuple must be bounded").max_size as usize;
+            }
 
-                let is_fixed_size = a_bounds.is_fixed_size && b_bounds.is_fixed_size;
+            let mut elements = Vec::with_capacity(bounds.len());
+            let mut offset = 0;
+            for bound in bounds {
+                let element_bounds = bounds_of(bound).expect("tuple must be bounded");
+                let size_len = bytes_to_store_size(&element_bounds) as usize;
+                let len = decode_size(
+                    &bytes[sizes_offset..sizes_offset + size_len],
+                    &element_bounds,
+                );
+                elements.push(&bytes[offset..offset + len]);
+                offset += element_bounds.max_size as usize;
+                sizes_offset += size_len;
+            }
 
-                Bound::Bounded {
-                    max_size,
-                    is_fixed_size,
+            elements
+        }
+        Bound::Unbounded => {
+            let mut elements = Vec::with_capacity(bounds.len());
+            let last_idx = bounds.len() - 1;
+            let mut offset = 0;
+            for (idx, bound) in bounds.iter().enumerate() {
+                if idx == last_idx {
+                    // The last element takes up the rest of the bytes.
+                    elements.push(&bytes[offset..]);
+                    break;
                 }
+
+                let len = match bounds_of(bound) {
+                    Some(element_bounds) => {
+                        let size_len = bytes_to_store_size(&element_bounds) as usize;
+                        let len = decode_size(&bytes[offset..offset + size_len], &element_bounds);
+                        offset += size_len;
+                        len
+                    }
+                    None => {
+                        let len = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+                        offset += 4;
+                        len as usize
+                    }
+                };
+                elements.push(&bytes[offset..offset + len]);
+                offset += len;
             }
-            _ => Bound::Unbounded,
+
+            elements
         }
-    };
+    }
 }
 
 pub(crate) struct Bounds {
@@ -428,17 +587,26 @@ pub(crate) struct Bounds {
 
 /// Returns the bounds of the given type, panics if unbounded.
 pub(crate) const fn bounds<A: Storable>() -> Bounds {
+    if let Some(bounds) = bounds_of(&A::BOUND) {
+        bounds
+    } else {
+        panic!("Cannot get bounds of unbounded type.");
+    }
+}
+
+/// Returns the bounds of the given bound, or `None` if unbounded.
+const fn bounds_of(bound: &Bound) -> Option<Bounds> {
     if let Bound::Bounded {
         max_size,
         is_fixed_size,
-    } = A::BOUND
+    } = bound
     {
-        Bounds {
-            max_size,
-            is_fixed_size,
-        }
+        Some(Bounds {
+            max_size: *max_size,
+            is_fixed_size: *is_fixed_size,
+        })
     } else {
-        panic!("Cannot get bounds of unbounded type.");
+        None
     }
 }
 
@@ -0,0 +1,388 @@
+//! Order-preserving encodings.
+//!
+//! The `Storable` encodings of most types don't preserve order when compared
+//! byte-wise. For example, a bounded `(A, B)` tuple pads `A` to its max size and
+//! stores the sizes of the elements at the end.
+//!
+//! This module provides [`OrderedEncode`], an encoding where comparing the encoded
+//! bytes of two values gives the same result as comparing the values themselves.
+//! Encodings are self-delimiting, so tuples are encoded by concatenating the
+//! encodings of their elements, and the encoding of a tuple starts with the
+//! encoding of any of its prefixes.
+//!
+//! [`Key`] stores a value in its ordered encoding. Keys are compared byte-wise,
+//! so a `BTreeMap` with `Key`s never deserializes the keys it traverses.
+//!
+//! # Encoding
+//!
+//! * Unsigned integers are encoded in big-endian.
+//! * Signed integers are encoded in big-endian with the sign bit flipped.
+//! * `bool` is encoded as a single byte, 0 or 1.
+//! * Byte arrays of a fixed size are encoded as-is.
+//! * Variable-size byte sequences (`String`, `Vec<u8>`, `Blob`) escape every 0x00
+//!   byte as 0x00 0xFF and are terminated with 0x00 0x01.
+//! * Tuples are encoded as the concatenation of the encodings of their elements.
+//!
+//! ```
+//! use ic_stable_structures::storable::ordered::Key;
+//! use ic_stable_structures::{BTreeMap, DefaultMemoryImpl};
+//!
+//! let mut map: BTreeMap<Key<(String, u64)>, u64, _> = BTreeMap::init(DefaultMemoryImpl::default());
+//! map.insert(Key::new(&("alice".to_string(), 2)), 20);
+//! map.insert(Key::new(&("alice".to_string(), 1)), 10);
+//! map.insert(Key::new(&("bob".to_string(), 1)), 30);
+//!
+//! // All the entries of "alice".
+//! let alice: Vec<_> = map
+//!     .range(Key::prefix_range(&"alice".to_string()))
+//!     .map(|(k, v)| (k.get().1, v))
+//!     .collect();
+//! assert_eq!(alice, vec![(1, 10), (2, 20)]);
+//! ```
+use super::{Blob, Bound, Storable};
+use crate::btreemap::{prefix::cmp_bytes, KeyPrefix};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Bound as RangeBound;
+
+#[cfg(test)]
+mod tests;
+
+// The byte that 0x00 bytes are escaped with in variable-size byte sequences.
+const ESCAPE: u8 = 0xFF;
+// The byte that follows a 0x00 byte to terminate a variable-size byte sequence.
+const TERMINATOR: u8 = 0x01;
+
+/// A type with an order-preserving encoding.
+///
+/// Implementations must guarantee that for any values `a` and `b`, `a.cmp(&b)` is
+/// the same as comparing their encodings byte-wise, and that encodings are
+/// self-delimiting, i.e. no encoding is a strict prefix of another.
+pub trait OrderedEncode: Sized {
+    /// Appends the encoding of `self` to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decodes a value from the start of `bytes`, returning it along with the
+    /// remaining bytes.
+    fn decode(bytes: &[u8]) -> (Self, &[u8]);
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {
+        $(
+            impl OrderedEncode for $t {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+                    let (head, rest) = bytes.split_at(std::mem::size_of::<$t>());
+                    (<$t>::from_be_bytes(head.try_into().unwrap()), rest)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),*) => {
+        $(
+            impl OrderedEncode for $t {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    // Flipping the sign bit orders negative numbers before positive ones.
+                    buf.extend_from_slice(&(self ^ <$t>::MIN).to_be_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+                    let (head, rest) = bytes.split_at(std::mem::size_of::<$t>());
+                    (<$t>::from_be_bytes(head.try_into().unwrap()) ^ <$t>::MIN, rest)
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned!(u8, u16, u32, u64, u128);
+impl_signed!(i8, i16, i32, i64, i128);
+
+impl OrderedEncode for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        match bytes[0] {
+            0 => (false, &bytes[1..]),
+            1 => (true, &bytes[1..]),
+            other => panic!("Invalid bool encoding: {other}"),
+        }
+    }
+}
+
+impl<const N: usize> OrderedEncode for [u8; N] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        let (head, rest) = bytes.split_at(N);
+        (head.try_into().unwrap(), rest)
+    }
+}
+
+impl OrderedEncode for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self, buf);
+    }
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        decode_bytes(bytes)
+    }
+}
+
+impl OrderedEncode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), buf);
+    }
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        let (decoded, rest) = decode_bytes(bytes);
+        (String::from_utf8(decoded).unwrap(), rest)
+    }
+}
+
+impl<const N: usize> OrderedEncode for Blob<N> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self.as_slice(), buf);
+    }
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        let (decoded, rest) = decode_bytes(bytes);
+        (Blob::try_from(&decoded[..]).unwrap(), rest)
+    }
+}
+
+impl OrderedEncode for () {
+    fn encode(&self, _buf: &mut Vec<u8>) {}
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        ((), bytes)
+    }
+}
+
+impl<A, B> OrderedEncode for (A, B)
+where
+    A: OrderedEncode,
+    B: OrderedEncode,
+{
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+        self.1.encode(buf);
+    }
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        let (a, bytes) = A::decode(bytes);
+        let (b, bytes) = B::decode(bytes);
+        ((a, b), bytes)
+    }
+}
+
+impl<A, B, C> OrderedEncode for (A, B, C)
+where
+    A: OrderedEncode,
+    B: OrderedEncode,
+    C: OrderedEncode,
+{
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+        self.1.encode(buf);
+        self.2.encode(buf);
+    }
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        let (a, bytes) = A::decode(bytes);
+        let (b, bytes) = B::decode(bytes);
+        let (c, bytes) = C::decode(bytes);
+        ((a, b, c), bytes)
+    }
+}
+
+impl<A, B, C, D> OrderedEncode for (A, B, C, D)
+where
+    A: OrderedEncode,
+    B: OrderedEncode,
+    C: OrderedEncode,
+    D: OrderedEncode,
+{
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+        self.1.encode(buf);
+        self.2.encode(buf);
+        self.3.encode(buf);
+    }
+
+    fn decode(bytes: &[u8]) -> (Self, &[u8]) {
+        let (a, bytes) = A::decode(bytes);
+        let (b, bytes) = B::decode(bytes);
+        let (c, bytes) = C::decode(bytes);
+        let (d, bytes) = D::decode(bytes);
+        ((a, b, c, d), bytes)
+    }
+}
+
+// Encodes a variable-size byte sequence, escaping 0x00 bytes and appending a terminator.
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    for byte in bytes {
+        buf.push(*byte);
+        if *byte == 0 {
+            buf.push(ESCAPE);
+        }
+    }
+    buf.extend_from_slice(&[0, TERMINATOR]);
+}
+
+// Decodes a variable-size byte sequence encoded with `encode_bytes`.
+fn decode_bytes(bytes: &[u8]) -> (Vec<u8>, &[u8]) {
+    let mut decoded = vec![];
+    let mut i = 0;
+    loop {
+        let byte = bytes[i];
+        if byte == 0 {
+            match bytes[i + 1] {
+                ESCAPE => decoded.push(0),
+                TERMINATOR => return (decoded, &bytes[i + 2..]),
+                other => panic!("Invalid byte after 0x00: {other}"),
+            }
+            i += 2;
+        } else {
+            decoded.push(byte);
+            i += 1;
+        }
+    }
+}
+
+/// A value stored in its order-preserving encoding.
+///
+/// Keys are compared by their encoded bytes, which is equivalent to comparing
+/// the values they hold. The value is only decoded when calling [`Key::get`].
+pub struct Key<T> {
+    bytes: Vec<u8>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: OrderedEncode> Key<T> {
+    /// Creates a key holding the given value.
+    pub fn new(value: &T) -> Self {
+        let mut bytes = vec![];
+        value.encode(&mut bytes);
+        Self::from_encoded(bytes)
+    }
+
+    /// Decodes the value held by the key.
+    pub fn get(&self) -> T {
+        let (value, rest) = T::decode(&self.bytes);
+        assert!(rest.is_empty(), "Key has trailing bytes.");
+        value
+    }
+
+    /// Returns the range of keys whose values start with the given prefix.
+    ///
+    /// The prefix is typically the first element(s) of a tuple. For example, given
+    /// keys of type `Key<(A, B, C)>`, the prefix can either be an `A` or an `(A, B)`.
+    pub fn prefix_range<P: OrderedEncode>(prefix: &P) -> (RangeBound<Self>, RangeBound<Self>) {
+        let mut start = vec![];
+        prefix.encode(&mut start);
+
+        // The smallest byte sequence that is greater than all the sequences starting with
+        // the prefix. It's obtained by incrementing the last byte that isn't 0xFF.
+        let mut end = start.clone();
+        while end.last() == Some(&u8::MAX) {
+            end.pop();
+        }
+        let end = match end.last_mut() {
+            Some(last) => {
+                *last += 1;
+                RangeBound::Excluded(Self::from_encoded(end))
+            }
+            None => RangeBound::Unbounded,
+        };
+
+        (RangeBound::Included(Self::from_encoded(start)), end)
+    }
+}
+
+impl<T> Key<T> {
+    fn from_encoded(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the encoded bytes of the key.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<T: OrderedEncode> From<T> for Key<T> {
+    fn from(value: T) -> Self {
+        Self::new(&value)
+    }
+}
+
+impl<T> Clone for Key<T> {
+    fn clone(&self) -> Self {
+        Self::from_encoded(self.bytes.clone())
+    }
+}
+
+impl<T> PartialEq for Key<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl<T> Eq for Key<T> {}
+
+impl<T> PartialOrd for Key<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Key<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bytes.cmp(&other.bytes)
+    }
+}
+
+impl<T: OrderedEncode + fmt::Debug> fmt::Debug for Key<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Key").field(&self.get()).finish()
+    }
+}
+
+/// Any value can be used as a prefix of keys in [`BTreeMap::prefix_iter`](crate::BTreeMap::prefix_iter).
+/// Keys start with the prefix if their encoding starts with the encoding of the prefix.
+impl<T, P: OrderedEncode> KeyPrefix<Key<T>> for P {
+    fn cmp_key(&self, key: &Key<T>) -> Ordering {
+        let mut prefix = vec![];
+        self.encode(&mut prefix);
+        cmp_bytes(&key.bytes, &prefix)
+    }
+}
+
+impl<T> Storable for Key<T> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self::from_encoded(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
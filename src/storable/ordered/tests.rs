@@ -0,0 +1,135 @@
+use super::*;
+use crate::{btreemap::BTreeMap, VectorMemory};
+use proptest::collection::vec as pvec;
+use proptest::prelude::*;
+
+fn encode<T: OrderedEncode>(value: &T) -> Vec<u8> {
+    Key::new(value).as_bytes().to_vec()
+}
+
+fn roundtrip<T: OrderedEncode + PartialEq + fmt::Debug + Clone>(value: T) {
+    assert_eq!(Key::new(&value).get(), value);
+}
+
+proptest! {
+    #[test]
+    fn unsigned_order_is_preserved(a in any::<u64>(), b in any::<u64>()) {
+        prop_assert_eq!(a.cmp(&b), encode(&a).cmp(&encode(&b)));
+        roundtrip(a);
+    }
+
+    #[test]
+    fn signed_order_is_preserved(a in any::<i64>(), b in any::<i64>()) {
+        prop_assert_eq!(a.cmp(&b), encode(&a).cmp(&encode(&b)));
+        roundtrip(a);
+    }
+
+    #[test]
+    fn small_signed_order_is_preserved(a in any::<i8>(), b in any::<i8>()) {
+        prop_assert_eq!(a.cmp(&b), encode(&a).cmp(&encode(&b)));
+        roundtrip(a);
+    }
+
+    #[test]
+    fn bytes_order_is_preserved(a in pvec(0..3u8, 0..10), b in pvec(0..3u8, 0..10)) {
+        prop_assert_eq!(a.cmp(&b), encode(&a).cmp(&encode(&b)));
+        roundtrip(a);
+    }
+
+    #[test]
+    fn string_order_is_preserved(a in "[a\u{0}b]{0,10}", b in "[a\u{0}b]{0,10}") {
+        prop_assert_eq!(a.cmp(&b), encode(&a).cmp(&encode(&b)));
+        roundtrip(a);
+    }
+
+    #[test]
+    fn tuple_order_is_preserved(
+        a in (pvec(0..3u8, 0..5), any::<u8>(), any::<i16>()),
+        b in (pvec(0..3u8, 0..5), any::<u8>(), any::<i16>()),
+    ) {
+        prop_assert_eq!(a.cmp(&b), encode(&a).cmp(&encode(&b)));
+        roundtrip(a);
+    }
+
+    #[test]
+    fn four_tuple_roundtrip(a in (".*", any::<bool>(), pvec(any::<u8>(), 0..10), any::<i128>())) {
+        roundtrip(a);
+    }
+
+    #[test]
+    fn prefix_range_returns_keys_with_prefix(
+        entries in pvec((0..5u8, "[ab]{0,3}", any::<u32>()), 0..50),
+        prefix in (0..5u8, "[ab]{0,3}"),
+    ) {
+        let mut btree = BTreeMap::new(VectorMemory::default());
+        let mut std_btree = std::collections::BTreeMap::new();
+        for (a, b, c) in entries {
+            btree.insert(Key::new(&(a, b.clone(), c)), ());
+            std_btree.insert((a, b, c), ());
+        }
+
+        // A prefix of one element.
+        let expected: Vec<_> = std_btree.keys().filter(|(a, _, _)| *a == prefix.0).cloned().collect();
+        let actual: Vec<_> = btree.range(Key::prefix_range(&prefix.0)).map(|(k, _)| k.get()).collect();
+        prop_assert_eq!(actual, expected);
+
+        // A prefix of two elements.
+        let expected: Vec<_> = std_btree
+            .keys()
+            .filter(|(a, b, _)| (a, b) == (&prefix.0, &prefix.1))
+            .cloned()
+            .collect();
+        let actual: Vec<_> = btree.range(Key::prefix_range(&prefix)).map(|(k, _)| k.get()).collect();
+        prop_assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn encodings() {
+    assert_eq!(encode(&1u16), vec![0, 1]);
+    assert_eq!(encode(&-1i16), vec![0x7F, 0xFF]);
+    assert_eq!(encode(&1i16), vec![0x80, 1]);
+    assert_eq!(encode(&true), vec![1]);
+    assert_eq!(encode(&[1u8, 0]), vec![1, 0]);
+    assert_eq!(
+        encode(&vec![1u8, 0, 2]),
+        vec![1, 0, ESCAPE, 2, 0, TERMINATOR]
+    );
+    assert_eq!(encode(&String::new()), vec![0, TERMINATOR]);
+    assert_eq!(
+        encode(&(String::from("a"), 2u8)),
+        vec![b'a', 0, TERMINATOR, 2]
+    );
+}
+
+#[test]
+fn prefix_range_of_max_bytes_is_unbounded_above() {
+    let (start, end) = Key::<(u8, u8)>::prefix_range(&u8::MAX);
+    assert_eq!(start, RangeBound::Included(Key::from_encoded(vec![0xFF])));
+    assert_eq!(end, RangeBound::Unbounded);
+
+    let (_, end) = Key::<(u16, u8)>::prefix_range(&0x01FFu16);
+    assert_eq!(end, RangeBound::Excluded(Key::from_encoded(vec![0x02])));
+}
+
+#[test]
+fn keys_are_storable() {
+    let key = Key::new(&(String::from("hello"), -5i32));
+    let loaded: Key<(String, i32)> = Storable::from_bytes(key.to_bytes());
+    assert_eq!(loaded, key);
+    assert_eq!(loaded.get(), (String::from("hello"), -5));
+}
+
+#[test]
+fn prefix_iter_on_keys() {
+    let mut btree = BTreeMap::new(VectorMemory::default());
+    for (a, b) in [("a", 1u64), ("a", 2), ("ab", 1), ("b", 1)] {
+        btree.insert(Key::new(&(a.to_string(), b)), ());
+    }
+
+    let keys: Vec<_> = btree
+        .prefix_iter(&"a".to_string())
+        .map(|(k, _)| k.get())
+        .collect();
+    assert_eq!(keys, vec![("a".to_string(), 1), ("a".to_string(), 2)]);
+}
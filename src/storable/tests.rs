@@ -26,6 +26,53 @@ proptest! {
         prop_assert_eq!(tuple, Storable::from_bytes(tuple.to_bytes()));
     }
 
+    #[test]
+    fn tuple_with_unbounded_first_element_roundtrip(x in ".*", y in any::<u64>()) {
+        let tuple = (x, y);
+        prop_assert_eq!(tuple.clone(), Storable::from_bytes(tuple.to_bytes()));
+    }
+
+    #[test]
+    fn tuple_with_unbounded_elements_roundtrip(x in pvec(any::<u8>(), 0..40), y in pvec(any::<u8>(), 0..40)) {
+        let tuple = (x, y);
+        prop_assert_eq!(tuple.clone(), Storable::from_bytes(tuple.to_bytes()));
+    }
+
+    #[test]
+    fn tuple_with_bounded_and_unbounded_elements_roundtrip(x in pvec(any::<u8>(), 0..40), y in ".*") {
+        let blob = Blob::<300>::try_from(&x[..]).unwrap();
+        let tuple = (blob, y);
+        prop_assert_eq!(tuple.clone(), Storable::from_bytes(tuple.to_bytes()));
+    }
+
+    #[test]
+    fn triple_roundtrip(x in any::<u64>(), v in pvec(any::<u8>(), 0..40), y in any::<u32>()) {
+        let tuple = (x, Blob::<48>::try_from(&v[..]).unwrap(), y);
+        let bytes = tuple.to_bytes();
+        prop_assert_eq!(bytes.len(), 8 + 48 + 1 + 4);
+        prop_assert_eq!(tuple, Storable::from_bytes(bytes));
+    }
+
+    #[test]
+    fn unbounded_triple_roundtrip(x in ".*", v in pvec(any::<u8>(), 0..40), y in any::<u32>()) {
+        let tuple = (x, v, y);
+        prop_assert_eq!(tuple.clone(), Storable::from_bytes(tuple.to_bytes()));
+    }
+
+    #[test]
+    fn quadruple_roundtrip(a in any::<u8>(), b in any::<u16>(), c in any::<u32>(), d in any::<u64>()) {
+        let tuple = (a, b, c, d);
+        let bytes = tuple.to_bytes();
+        prop_assert_eq!(bytes.len(), 15);
+        prop_assert_eq!(tuple, Storable::from_bytes(bytes));
+    }
+
+    #[test]
+    fn unbounded_quadruple_roundtrip(a in ".*", b in pvec(any::<u8>(), 0..40), c in ".*", d in pvec(any::<u8>(), 0..40)) {
+        let tuple = (a, b, c, d);
+        prop_assert_eq!(tuple.clone(), Storable::from_bytes(tuple.to_bytes()));
+    }
+
     #[test]
     fn f64_roundtrip(v in any::<f64>()) {
         prop_assert_eq!(v, Storable::from_bytes(v.to_bytes()));
@@ -36,3 +83,44 @@ proptest! {
         prop_assert_eq!(v, Storable::from_bytes(v.to_bytes()));
     }
 }
+
+#[test]
+fn bounded_tuple_encoding() {
+    // Elements are padded to their max size, with their sizes appended at the end.
+    let tuple = (Blob::<3>::try_from(&[1, 2][..]).unwrap(), 5u16);
+    assert_eq!(tuple.to_bytes(), vec![1, 2, 0, 0, 5, 2]);
+}
+
+#[test]
+fn unbounded_tuple_encoding() {
+    // All the elements except the last one are prefixed with their size.
+    let tuple = (
+        String::from("ab"),
+        Blob::<3>::try_from(&[1][..]).unwrap(),
+        vec![7, 8],
+    );
+    assert_eq!(tuple.to_bytes(), vec![0, 0, 0, 2, b'a', b'b', 1, 1, 7, 8]);
+
+    let tuple = (5u16, String::from("ab"));
+    assert_eq!(tuple.to_bytes(), vec![0, 5, b'a', b'b']);
+}
+
+#[test]
+fn tuple_bounds() {
+    assert!(matches!(
+        <(u64, Blob<300>, u8)>::BOUND,
+        Bound::Bounded {
+            max_size: 311,
+            is_fixed_size: false
+        }
+    ));
+    assert!(matches!(
+        <(u8, u16, u32, u64)>::BOUND,
+        Bound::Bounded {
+            max_size: 15,
+            is_fixed_size: true
+        }
+    ));
+    assert!(matches!(<(u64, String)>::BOUND, Bound::Unbounded));
+    assert!(matches!(<(u8, u8, u8, Vec<u8>)>::BOUND, Bound::Unbounded));
+}
@@ -1,4 +1,4 @@
-use crate::{Memory, WASM_PAGE_SIZE};
+use crate::{Memory, ShrinkableMemory, WASM_PAGE_SIZE};
 use std::cell::RefCell;
 use std::ops::Deref;
 use std::rc::Rc;
@@ -51,6 +51,18 @@ impl Memory for RefCell<Vec<u8>> {
     }
 }
 
+impl ShrinkableMemory for RefCell<Vec<u8>> {
+    fn truncate(&self, pages: u64) -> u64 {
+        let size = self.size();
+        if pages < size {
+            let mut vec = self.borrow_mut();
+            vec.truncate((pages * WASM_PAGE_SIZE) as usize);
+            vec.shrink_to_fit();
+        }
+        size
+    }
+}
+
 impl<M: Memory> Memory for Rc<M> {
     fn size(&self) -> u64 {
         self.deref().size()
@@ -65,3 +77,9 @@ impl<M: Memory> Memory for Rc<M> {
         self.deref().write(offset, src)
     }
 }
+
+impl<M: ShrinkableMemory> ShrinkableMemory for Rc<M> {
+    fn truncate(&self, pages: u64) -> u64 {
+        self.deref().truncate(pages)
+    }
+}